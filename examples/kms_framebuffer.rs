@@ -0,0 +1,57 @@
+use std::fs::OpenOptions;
+
+use drm_fourcc::DrmFourcc;
+use wgpu::{
+    DeviceDescriptor, Extent3d, Instance, RequestAdapterOptions, TextureDescriptor,
+    TextureDimension, TextureFormat, TextureUsages,
+};
+use wgpu_drm::{adapter::AdapterExt, instance::InstanceExt, kms::Framebuffer};
+
+fn main() {
+    env_logger::init();
+
+    // The primary node of the device to create the framebuffer on, such as vkms.
+    let path = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "/dev/dri/card0".into());
+    let drm = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&path)
+        .expect("Failed to open DRM device");
+
+    let instance = Instance::with_drm();
+    let adapter = pollster::block_on(instance.request_adapter(&RequestAdapterOptions::default()))
+        .expect("No adapter available");
+    let (device, _queue) = adapter
+        .request_device_with_external_memory(&DeviceDescriptor::default(), None)
+        .expect("Failed to create device");
+
+    let texture = device
+        .create_exportable_texture(
+            &TextureDescriptor {
+                label: Some("framebuffer"),
+                size: Extent3d {
+                    width: 1024,
+                    height: 768,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format: TextureFormat::Bgra8Unorm,
+                usage: TextureUsages::RENDER_ATTACHMENT,
+            },
+            DrmFourcc::Xrgb8888,
+        )
+        .expect("Failed to create exportable texture");
+
+    let framebuffer = Framebuffer::new(&drm, &texture).expect("Failed to create framebuffer");
+
+    println!(
+        "Created framebuffer {} on {} with modifier {:?}",
+        framebuffer.id(),
+        path,
+        texture.dmabuf().format.modifier
+    );
+}
@@ -1,8 +1,9 @@
 use std::path::Path;
 
-use wgpu::{DeviceDescriptor, RequestDeviceError};
+use nix::{libc::dev_t, sys::stat::makedev};
+use wgpu::{BufferUsages, DeviceDescriptor, RequestDeviceError, TextureFormat, TextureUsages};
 
-use crate::ExternalMemoryDevice;
+use crate::{ExternalMemoryDevice, ExternalMemoryProperties, ExternalMemoryType};
 
 /// Length of a UUID.
 pub const UUID_LEN: usize = 16;
@@ -17,11 +18,24 @@ pub struct DrmInfo {
     pub render_node: Option<(u64, u64)>,
 }
 
+impl DrmInfo {
+    /// The device number of this adapter's DRM device primary node.
+    pub fn primary_dev(&self) -> Option<dev_t> {
+        self.primary_node
+            .map(|(major, minor)| makedev(major, minor))
+    }
+
+    /// The device number of this adapter's DRM device render node.
+    pub fn render_dev(&self) -> Option<dev_t> {
+        self.render_node.map(|(major, minor)| makedev(major, minor))
+    }
+}
+
 /// Persistent UUIDs that can identify a device and driver across graphics APIs.
 ///
 /// - Vulkan: corresponds to values in `VkPhysicalDeviceIDProperties`.
 /// - EGL: provided by `EGL_EXT_device_persistent_id` extension.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct DeviceUuids {
     /// The driver uuid of the adapter.
     ///
@@ -51,6 +65,27 @@ pub trait AdapterExt: Sized {
     /// This does not guarantee that both import and export operations are supported.
     fn supports_dmabuf_external_memory(&self) -> bool;
 
+    /// Returns what operations are supported on external memory of the specified handle type for a texture
+    /// with the specified format and usage.
+    ///
+    /// This will return [`None`] if the handle type may not be imported or exported for the format and usage.
+    fn external_memory_capabilities(
+        &self,
+        handle_type: ExternalMemoryType,
+        format: TextureFormat,
+        usage: TextureUsages,
+    ) -> Option<ExternalMemoryProperties>;
+
+    /// Returns what operations are supported on external memory of the specified handle type for a buffer
+    /// with the specified usage.
+    ///
+    /// This will return [`None`] if the handle type may not be imported or exported for the usage.
+    fn external_buffer_memory_capabilities(
+        &self,
+        handle_type: ExternalMemoryType,
+        usage: BufferUsages,
+    ) -> Option<ExternalMemoryProperties>;
+
     /// Requests a connection to a physical device, creating a logical device.
     ///
     /// Returns the Device together with a Queue that executes command buffers.
@@ -0,0 +1,195 @@
+//! A cache of imported dmabufs.
+
+use std::{collections::HashMap, io, os::unix::io::AsRawFd, sync::Arc};
+
+use drm_fourcc::DrmFormat;
+use nix::sys::stat::fstat;
+use wgpu::{TextureFormat, TextureUsages};
+
+use crate::{
+    dmabuf::{Dmabuf, DmabufImportDescriptor, ImportError},
+    ExternalMemoryDevice,
+};
+
+/// Identifies a plane by the dmabuf it is stored in and its layout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct PlaneKey {
+    dev: u64,
+    ino: u64,
+    offset: u32,
+    stride: u32,
+}
+
+/// Identifies a dmabuf independently of the file descriptors used to refer to it.
+///
+/// Every file descriptor of a dmabuf refers to the same inode, so a buffer which is sent again with new file
+/// descriptors has the same key.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DmabufKey {
+    format: DrmFormat,
+    width: u32,
+    height: u32,
+    planes: Vec<PlaneKey>,
+}
+
+impl DmabufKey {
+    /// Returns the key of a dmabuf.
+    pub fn new(dmabuf: &Dmabuf) -> io::Result<Self> {
+        let planes = dmabuf
+            .planes
+            .iter()
+            .map(|plane| {
+                let stat = fstat(plane.fd.as_raw_fd()).map_err(io::Error::from)?;
+
+                Ok(PlaneKey {
+                    dev: stat.st_dev,
+                    ino: stat.st_ino,
+                    offset: plane.offset,
+                    stride: plane.stride,
+                })
+            })
+            .collect::<io::Result<_>>()?;
+
+        Ok(Self {
+            format: dmabuf.format,
+            width: dmabuf.width,
+            height: dmabuf.height,
+            planes,
+        })
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    texture: Arc<wgpu::Texture>,
+    format: TextureFormat,
+    usage: TextureUsages,
+    /// The value of the use counter when the entry was last used.
+    last_used: u64,
+}
+
+/// Caches the textures of imported dmabufs, so buffers which are received again are not imported again.
+///
+/// A compositor receives the same few buffers from a client every frame. The cache returns the texture of a
+/// buffer which was imported before, and evicts the least recently used texture once the cache is full. Buffers
+/// should be evicted when the client destroys them.
+///
+/// The texture of a cached dmabuf keeps the dmabuf alive, so the inode of the dmabuf can not be reused by another
+/// dmabuf while the dmabuf is in the cache.
+#[derive(Debug)]
+pub struct DmabufImportCache<'a> {
+    device: &'a ExternalMemoryDevice,
+    capacity: usize,
+    entries: HashMap<DmabufKey, Entry>,
+    use_counter: u64,
+}
+
+impl<'a> DmabufImportCache<'a> {
+    pub(crate) fn new(device: &'a ExternalMemoryDevice, capacity: usize) -> Self {
+        Self {
+            device,
+            capacity,
+            entries: HashMap::with_capacity(capacity),
+            use_counter: 0,
+        }
+    }
+
+    /// Returns the texture of the dmabuf, importing the dmabuf if it is not in the cache.
+    ///
+    /// A cached texture is only returned if it was imported with the same texture format and usages, otherwise the
+    /// dmabuf is imported again and replaces the cached texture.
+    pub fn import(
+        &mut self,
+        dmabuf: &Dmabuf,
+        desc: &DmabufImportDescriptor,
+    ) -> Result<Arc<wgpu::Texture>, ImportError> {
+        let key = DmabufKey::new(dmabuf)?;
+        self.use_counter += 1;
+
+        if let Some(entry) = self.entries.get_mut(&key) {
+            if entry.format == desc.format && entry.usage == desc.usage {
+                entry.last_used = self.use_counter;
+                return Ok(entry.texture.clone());
+            }
+        }
+
+        let texture = Arc::new(self.device.import_dmabuf(dmabuf, desc)?);
+
+        if !self.entries.contains_key(&key) && self.entries.len() >= self.capacity {
+            self.evict_least_recently_used();
+        }
+
+        if self.capacity != 0 {
+            self.entries.insert(
+                key,
+                Entry {
+                    texture: texture.clone(),
+                    format: desc.format,
+                    usage: desc.usage,
+                    last_used: self.use_counter,
+                },
+            );
+        }
+
+        Ok(texture)
+    }
+
+    /// Removes a dmabuf from the cache, for example once the client destroys the buffer.
+    ///
+    /// The texture is destroyed once every reference returned by [`DmabufImportCache::import`] is dropped.
+    pub fn evict(&mut self, dmabuf: &Dmabuf) -> io::Result<()> {
+        let key = DmabufKey::new(dmabuf)?;
+        self.evict_key(&key);
+        Ok(())
+    }
+
+    /// Removes the dmabuf with the key from the cache.
+    ///
+    /// Unlike [`DmabufImportCache::evict`], this does not require the file descriptors of the dmabuf, which may
+    /// already be closed when the buffer is destroyed.
+    pub fn evict_key(&mut self, key: &DmabufKey) {
+        self.entries.remove(key);
+    }
+
+    /// Removes every dmabuf from the cache.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Changes the maximum number of dmabufs in the cache, evicting the least recently used dmabufs if the cache
+    /// is too large.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+
+        while self.entries.len() > capacity {
+            self.evict_least_recently_used();
+        }
+    }
+
+    /// The maximum number of dmabufs in the cache.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The number of dmabufs in the cache.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether the cache is empty.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn evict_least_recently_used(&mut self) {
+        let key = self
+            .entries
+            .iter()
+            .min_by_key(|(_, entry)| entry.last_used)
+            .map(|(key, _)| key.clone());
+
+        if let Some(key) = key {
+            self.entries.remove(&key);
+        }
+    }
+}
@@ -0,0 +1,351 @@
+//! Copying textures between devices which cannot share textures directly.
+//!
+//! If two devices share no modifier for a format, textures rendered on one device cannot be imported on the
+//! other. A [`CrossDeviceCopy`] instead copies the texture into an intermediate `DRM_FORMAT_MOD_LINEAR`
+//! dmabuf which is imported on the destination device and copied into the destination texture. If the devices
+//! cannot even share `DRM_FORMAT_MOD_LINEAR` dmabufs, the texture is copied through host memory.
+//!
+//! The dmabuf is released by one device and acquired by the other using
+//! [`ExternalMemoryDevice::release_texture`] and [`ExternalMemoryDevice::acquire_texture`], and each device
+//! waits for the submission releasing the dmabuf to complete before the other device acquires it.
+
+use std::{num::NonZeroU32, sync::mpsc};
+
+use drm_fourcc::{DrmFourcc, DrmModifier};
+use wgpu::{
+    BufferAsyncError, Extent3d, ImageCopyBuffer, ImageCopyTexture, ImageDataLayout, Maintain,
+    MapMode, Origin3d, TextureAspect, TextureDimension, TextureFormat, TextureUsages,
+};
+
+use crate::{
+    dmabuf::{DmabufImportDescriptor, ExportError, ExportedTexture, ImportError},
+    layout::LayoutError,
+    negotiation::{negotiate_modifiers, NegotiationDescriptor},
+    ExternalMemoryDevice,
+};
+
+/// Describes a [`CrossDeviceCopy`].
+#[derive(Debug, Clone)]
+pub struct CrossDeviceCopyDescriptor<'a> {
+    /// Debug label of the intermediate allocations.
+    pub label: wgpu::Label<'a>,
+
+    /// Width of the copied textures in pixels.
+    pub width: u32,
+
+    /// Height of the copied textures in pixels.
+    pub height: u32,
+
+    /// Format of the copied textures.
+    pub format: TextureFormat,
+
+    /// The fourcc code of the intermediate dmabuf, which must have the same memory layout as the texture
+    /// format.
+    pub fourcc: DrmFourcc,
+}
+
+/// Error returned when copying a texture between devices.
+#[derive(Debug, thiserror::Error)]
+pub enum CopyError {
+    /// The intermediate texture could not be exported from the source device.
+    #[error(transparent)]
+    Export(#[from] ExportError),
+
+    /// The intermediate texture could not be imported on the destination device.
+    #[error(transparent)]
+    Import(#[from] ImportError),
+
+    /// The intermediate texture could not be handed from one device to the other.
+    #[error(transparent)]
+    Layout(#[from] LayoutError),
+
+    /// The intermediate buffer could not be mapped.
+    #[error(transparent)]
+    Map(#[from] BufferAsyncError),
+}
+
+/// The usages of the intermediate texture on the source device.
+///
+/// [`TextureUsages::COPY_SRC`] lets the device move the texture to a known layout before it is released.
+const STAGING_EXPORT_USAGE: TextureUsages = TextureUsages::COPY_DST.union(TextureUsages::COPY_SRC);
+
+enum Staging {
+    /// A `DRM_FORMAT_MOD_LINEAR` texture exported by the source device and imported on the destination device.
+    Dmabuf {
+        exported: ExportedTexture,
+        imported: wgpu::Texture,
+    },
+
+    /// A buffer on the source device which is read back and written to the destination texture.
+    Host {
+        buffer: wgpu::Buffer,
+        padded_bytes_per_row: u32,
+    },
+}
+
+/// Copies textures from a source device to a destination device.
+///
+/// The intermediate allocations are created once and reused for every copy. Every copy waits for the copy on
+/// the destination device to complete, so the textures may be used immediately after [`CrossDeviceCopy::copy`]
+/// returns.
+pub struct CrossDeviceCopy<'a> {
+    src: &'a ExternalMemoryDevice,
+    src_queue: &'a wgpu::Queue,
+    dst: &'a ExternalMemoryDevice,
+    dst_queue: &'a wgpu::Queue,
+    size: Extent3d,
+    staging: Staging,
+}
+
+impl<'a> CrossDeviceCopy<'a> {
+    /// Creates the intermediate allocations used to copy textures between the devices.
+    ///
+    /// A `DRM_FORMAT_MOD_LINEAR` dmabuf is used if both devices support one, otherwise textures are copied
+    /// through host memory.
+    pub fn new(
+        src: &'a ExternalMemoryDevice,
+        src_queue: &'a wgpu::Queue,
+        dst: &'a ExternalMemoryDevice,
+        dst_queue: &'a wgpu::Queue,
+        desc: &CrossDeviceCopyDescriptor,
+    ) -> Result<Self, CopyError> {
+        let size = Extent3d {
+            width: desc.width,
+            height: desc.height,
+            depth_or_array_layers: 1,
+        };
+
+        let shares_linear = negotiate_modifiers(
+            src,
+            dst,
+            desc.fourcc,
+            &NegotiationDescriptor {
+                export_usage: STAGING_EXPORT_USAGE,
+                import_usage: TextureUsages::COPY_SRC,
+            },
+        )
+        .map(|modifiers| modifiers.contains(&DrmModifier::Linear))
+        .unwrap_or(false);
+
+        let staging = if shares_linear {
+            let exported = src.create_texture_with_modifiers(
+                &wgpu::TextureDescriptor {
+                    label: desc.label,
+                    size,
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: TextureDimension::D2,
+                    format: desc.format,
+                    usage: STAGING_EXPORT_USAGE,
+                },
+                desc.fourcc,
+                &[DrmModifier::Linear],
+            )?;
+            let imported = dst.import_dmabuf(
+                exported.dmabuf(),
+                &DmabufImportDescriptor {
+                    label: desc.label,
+                    format: desc.format,
+                    usage: TextureUsages::COPY_SRC,
+                },
+            )?;
+
+            Staging::Dmabuf { exported, imported }
+        } else {
+            log::info!(
+                "Devices share no linear {} dmabuf, copying through host memory",
+                desc.fourcc
+            );
+
+            let bytes_per_row = desc.width * u32::from(desc.format.describe().block_size);
+            let padded_bytes_per_row = align_to(bytes_per_row, wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+            let buffer = src.device().create_buffer(&wgpu::BufferDescriptor {
+                label: desc.label,
+                size: u64::from(padded_bytes_per_row) * u64::from(desc.height),
+                usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+                mapped_at_creation: false,
+            });
+
+            Staging::Host {
+                buffer,
+                padded_bytes_per_row,
+            }
+        };
+
+        Ok(Self {
+            src,
+            src_queue,
+            dst,
+            dst_queue,
+            size,
+            staging,
+        })
+    }
+
+    /// Whether the textures are copied through host memory instead of a dmabuf.
+    pub fn is_host_copy(&self) -> bool {
+        matches!(self.staging, Staging::Host { .. })
+    }
+
+    /// Copies the source texture on the source device to the destination texture on the destination device.
+    ///
+    /// The source texture must have been created with [`TextureUsages::COPY_SRC`] and the destination texture
+    /// with [`TextureUsages::COPY_DST`]. Both textures must have the size and format of the descriptor.
+    ///
+    /// # Safety
+    ///
+    /// The intermediate dmabuf is handed between the devices by submitting to the Vulkan queues directly, so
+    /// no other thread may use either queue until this returns.
+    pub unsafe fn copy(&self, src: &wgpu::Texture, dst: &wgpu::Texture) -> Result<(), CopyError> {
+        match &self.staging {
+            Staging::Dmabuf { exported, imported } => {
+                let mut encoder = self
+                    .src
+                    .device()
+                    .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+                encoder.copy_texture_to_texture(
+                    whole_texture(src),
+                    whole_texture(exported.texture()),
+                    self.size,
+                );
+                self.src_queue.submit(Some(encoder.finish()));
+
+                // The destination device may only acquire the dmabuf once the source device has released it.
+                release_texture(self.src, self.src_queue, exported.texture())?;
+                wait_for_queue(self.src, self.src_queue);
+
+                acquire_texture(self.dst, imported)?;
+                let mut encoder = self
+                    .dst
+                    .device()
+                    .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+                encoder.copy_texture_to_texture(
+                    whole_texture(imported),
+                    whole_texture(dst),
+                    self.size,
+                );
+                self.dst_queue.submit(Some(encoder.finish()));
+
+                // The source device may only write to the dmabuf again once the destination device has read it.
+                release_texture(self.dst, self.dst_queue, imported)?;
+                wait_for_queue(self.dst, self.dst_queue);
+
+                acquire_texture(self.src, exported.texture())?;
+            }
+
+            Staging::Host {
+                buffer,
+                padded_bytes_per_row,
+            } => {
+                let bytes_per_row = NonZeroU32::new(*padded_bytes_per_row);
+
+                let mut encoder = self
+                    .src
+                    .device()
+                    .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+                encoder.copy_texture_to_buffer(
+                    whole_texture(src),
+                    ImageCopyBuffer {
+                        buffer,
+                        layout: ImageDataLayout {
+                            offset: 0,
+                            bytes_per_row,
+                            rows_per_image: None,
+                        },
+                    },
+                    self.size,
+                );
+                let index = self.src_queue.submit(Some(encoder.finish()));
+
+                let slice = buffer.slice(..);
+                let (sender, receiver) = mpsc::channel();
+                slice.map_async(MapMode::Read, move |result| {
+                    let _ = sender.send(result);
+                });
+                self.src
+                    .device()
+                    .poll(Maintain::WaitForSubmissionIndex(index));
+                receiver
+                    .recv()
+                    .expect("Buffer mapping callback was not called")?;
+
+                {
+                    let data = slice.get_mapped_range();
+                    self.dst_queue.write_texture(
+                        whole_texture(dst),
+                        &data,
+                        ImageDataLayout {
+                            offset: 0,
+                            bytes_per_row,
+                            rows_per_image: None,
+                        },
+                        self.size,
+                    );
+                }
+
+                buffer.unmap();
+                self.dst_queue.submit(None);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for CrossDeviceCopy<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CrossDeviceCopy")
+            .field("size", &self.size)
+            .field("host_copy", &self.is_host_copy())
+            .finish()
+    }
+}
+
+/// Releases the intermediate dmabuf to the other device.
+///
+/// The GLES backend does not transfer ownership of dmabufs, so only textures of the Vulkan backend are
+/// released.
+unsafe fn release_texture(
+    device: &ExternalMemoryDevice,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+) -> Result<(), LayoutError> {
+    match device.release_texture(queue, texture) {
+        Err(LayoutError::Unsupported) => Ok(()),
+        result => result,
+    }
+}
+
+/// Acquires the intermediate dmabuf from the other device.
+unsafe fn acquire_texture(
+    device: &ExternalMemoryDevice,
+    texture: &wgpu::Texture,
+) -> Result<(), LayoutError> {
+    match device.acquire_texture(texture, false) {
+        Err(LayoutError::Unsupported) => Ok(()),
+        result => result,
+    }
+}
+
+/// Waits for all work submitted to the queue, including ownership transfers submitted outside of wgpu.
+///
+/// wgpu signals a fence for every submission, which also waits for every earlier submission to the queue.
+fn wait_for_queue(device: &ExternalMemoryDevice, queue: &wgpu::Queue) {
+    let index = queue.submit(None);
+    device
+        .device()
+        .poll(Maintain::WaitForSubmissionIndex(index));
+}
+
+fn whole_texture(texture: &wgpu::Texture) -> ImageCopyTexture<'_> {
+    ImageCopyTexture {
+        texture,
+        mip_level: 0,
+        origin: Origin3d::ZERO,
+        aspect: TextureAspect::All,
+    }
+}
+
+fn align_to(value: u32, alignment: u32) -> u32 {
+    (value + alignment - 1) / alignment * alignment
+}
@@ -0,0 +1,187 @@
+//! Dmabuf external memory.
+
+use std::{io, os::unix::io::OwnedFd};
+
+use drm_fourcc::{DrmFormat, DrmFourcc, DrmModifier};
+use wgpu::{TextureFormat, TextureUsages};
+use wgpu_hal::DeviceError;
+
+use crate::validation::ValidationError;
+
+/// A single plane of a dmabuf.
+#[derive(Debug)]
+pub struct DmabufPlane {
+    /// The file descriptor of the dmabuf containing this plane.
+    pub fd: OwnedFd,
+
+    /// Offset of the plane from the start of the dmabuf in bytes.
+    pub offset: u32,
+
+    /// Number of bytes between the start of consecutive rows of the plane.
+    pub stride: u32,
+}
+
+/// A dmabuf and the layout of the image stored in it.
+#[derive(Debug)]
+pub struct Dmabuf {
+    /// The fourcc code and modifier of the image.
+    pub format: DrmFormat,
+
+    /// Width of the image in pixels.
+    pub width: u32,
+
+    /// Height of the image in pixels.
+    pub height: u32,
+
+    /// The planes of the image.
+    pub planes: Vec<DmabufPlane>,
+}
+
+/// Describes the texture a dmabuf is imported as.
+#[derive(Debug, Clone)]
+pub struct DmabufImportDescriptor<'a> {
+    /// Debug label of the texture.
+    pub label: wgpu::Label<'a>,
+
+    /// Format of the texture, which must have the same memory layout as the fourcc code of the dmabuf.
+    pub format: TextureFormat,
+
+    /// Allowed usages of the texture.
+    pub usage: TextureUsages,
+}
+
+/// A texture whose memory has been exported as a dmabuf.
+#[derive(Debug)]
+pub struct ExportedTexture {
+    pub(crate) texture: wgpu::Texture,
+    pub(crate) dmabuf: Dmabuf,
+}
+
+impl ExportedTexture {
+    /// The exported texture.
+    pub fn texture(&self) -> &wgpu::Texture {
+        &self.texture
+    }
+
+    /// The dmabuf backing the texture.
+    pub fn dmabuf(&self) -> &Dmabuf {
+        &self.dmabuf
+    }
+
+    /// The modifier chosen by the device for the texture.
+    pub fn modifier(&self) -> DrmModifier {
+        self.dmabuf.format.modifier
+    }
+
+    /// Returns the texture and dmabuf.
+    pub fn into_parts(self) -> (wgpu::Texture, Dmabuf) {
+        (self.texture, self.dmabuf)
+    }
+}
+
+/// Error returned when exporting a texture as a dmabuf.
+#[derive(Debug, thiserror::Error)]
+pub enum ExportError {
+    /// The device does not support exporting dmabufs.
+    #[error("the device does not support exporting dmabufs")]
+    Unsupported,
+
+    /// The fourcc code is not supported by the device.
+    #[error("the format {0} is not supported")]
+    UnsupportedFormat(DrmFourcc),
+
+    /// The memory layout of the texture format does not match the fourcc code.
+    #[error("the texture format {format:?} is not compatible with {fourcc}")]
+    IncompatibleFormat {
+        format: TextureFormat,
+        fourcc: DrmFourcc,
+    },
+
+    /// The texture descriptor describes a texture which can not be exported.
+    ///
+    /// Only 2D textures with a single mip level, array layer and sample may be exported.
+    #[error("only 2D textures with a single mip level, array layer and sample may be exported")]
+    InvalidDescriptor,
+
+    /// None of the modifiers supported by the device for the fourcc code support the texture usages.
+    #[error("no modifier for {0} supports the requested usages")]
+    NoSupportedModifier(DrmFourcc),
+
+    /// The dmabuf file descriptor could not be duplicated for each plane.
+    #[error("failed to duplicate the dmabuf file descriptor: {0}")]
+    Io(#[from] io::Error),
+
+    /// An error occurred in the device.
+    #[error(transparent)]
+    Device(#[from] DeviceError),
+}
+
+/// Error returned when importing a dmabuf.
+#[derive(Debug, thiserror::Error)]
+pub enum ImportError {
+    /// The device does not support importing dmabufs.
+    #[error("the device does not support importing dmabufs")]
+    Unsupported,
+
+    /// The combination of fourcc code and modifier is not supported by the device.
+    #[error("the format {0:?} is not supported")]
+    UnsupportedFormat(DrmFormat),
+
+    /// The memory layout of the texture format does not match the fourcc code.
+    #[error("the texture format {format:?} is not compatible with {fourcc}")]
+    IncompatibleFormat {
+        format: TextureFormat,
+        fourcc: DrmFourcc,
+    },
+
+    /// The modifier of the dmabuf does not support the requested usages.
+    #[error("the format {format:?} does not support {usage:?}")]
+    UnsupportedUsage {
+        format: DrmFormat,
+        usage: TextureUsages,
+    },
+
+    /// The dmabuf is larger than the largest image the device supports for the format and usages.
+    #[error(
+        "{width}x{height} exceeds the maximum size of {max_width}x{max_height} for {format:?}"
+    )]
+    UnsupportedSize {
+        format: DrmFormat,
+        width: u32,
+        height: u32,
+        max_width: u32,
+        max_height: u32,
+    },
+
+    /// The layout or file descriptors of the dmabuf are invalid.
+    #[error(transparent)]
+    Invalid(#[from] ValidationError),
+
+    /// The planes of the dmabuf are stored in different dmabufs, but the format does not support disjoint
+    /// images.
+    #[error("the planes are stored in different dmabufs, but the format does not support disjoint images")]
+    DisjointPlanes,
+
+    /// The driver does not recognise the file descriptor of a plane as a dmabuf.
+    #[error("the file descriptor is not a dmabuf the device can import")]
+    InvalidHandle,
+
+    /// None of the memory types the dmabuf may be imported as can be bound to the image.
+    #[error("no memory type is compatible with both the image and the dmabuf")]
+    NoCompatibleMemoryType {
+        image_type_bits: u32,
+        fd_type_bits: u32,
+    },
+
+    /// The sampler YCbCr conversion is not supported for the format.
+    #[error("the YCbCr conversion is not supported for {0:?}")]
+    UnsupportedYcbcrConversion(DrmFormat),
+
+    /// The file descriptor of a plane could not be duplicated or queried.
+    #[error("failed to access the dmabuf file descriptor: {0}")]
+    Io(#[from] io::Error),
+
+    /// An error occurred in the device.
+    #[error(transparent)]
+    Device(#[from] DeviceError),
+}
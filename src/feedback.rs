@@ -0,0 +1,445 @@
+//! Generation of `zwp_linux_dmabuf_feedback_v1` feedback for Wayland compositors.
+//!
+//! Compositors send the format table as a file descriptor to mmap and describe each tranche of the feedback
+//! using indices into that table.
+
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::CStr,
+    fs::File,
+    io::{self, Write},
+    os::unix::io::{AsFd, BorrowedFd, FromRawFd, OwnedFd},
+};
+
+use bitflags::bitflags;
+use drm_fourcc::DrmFormat;
+use nix::{
+    fcntl::{fcntl, FcntlArg, SealFlag},
+    libc::dev_t,
+    sys::memfd::{memfd_create, MemFdCreateFlag},
+};
+
+use crate::adapter::DrmInfo;
+
+/// Size of a single entry of the format table in bytes.
+pub const FORMAT_TABLE_ENTRY_SIZE: usize = 16;
+
+/// Encodes the format table as described by `zwp_linux_dmabuf_feedback_v1::format_table`.
+///
+/// Each entry is 16 bytes: a 32-bit fourcc code, 4 bytes of padding and a 64-bit modifier, in native
+/// endianness.
+pub fn encode_format_table(formats: &[DrmFormat]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(formats.len() * FORMAT_TABLE_ENTRY_SIZE);
+
+    for format in formats {
+        data.extend_from_slice(&(format.code as u32).to_ne_bytes());
+        data.extend_from_slice(&[0; 4]);
+        data.extend_from_slice(&u64::from(format.modifier).to_ne_bytes());
+    }
+
+    data
+}
+
+/// A format table stored in a sealed memfd.
+#[derive(Debug)]
+pub struct FormatTable {
+    fd: OwnedFd,
+    formats: Vec<DrmFormat>,
+    indices: HashMap<DrmFormat, u16>,
+}
+
+impl FormatTable {
+    /// Creates a format table containing the specified formats.
+    ///
+    /// Duplicate formats are ignored. At most 65536 formats may be stored in the table, since tranches refer
+    /// to formats using 16-bit indices.
+    pub fn new(formats: impl IntoIterator<Item = DrmFormat>) -> io::Result<Self> {
+        let mut unique = Vec::new();
+        let mut indices = HashMap::new();
+
+        for format in formats {
+            if indices.contains_key(&format) {
+                continue;
+            }
+
+            let index = u16::try_from(unique.len()).map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "too many formats for the format table",
+                )
+            })?;
+
+            indices.insert(format, index);
+            unique.push(format);
+        }
+
+        let name = CStr::from_bytes_with_nul(b"wgpu-drm-format-table\0").unwrap();
+        let fd = memfd_create(
+            name,
+            MemFdCreateFlag::MFD_CLOEXEC | MemFdCreateFlag::MFD_ALLOW_SEALING,
+        )?;
+        // SAFETY: memfd_create returns a new file descriptor which we now own.
+        let mut file = unsafe { File::from_raw_fd(fd) };
+
+        file.write_all(&encode_format_table(&unique))?;
+
+        // Clients map the table, so the contents must never change.
+        fcntl(
+            fd,
+            FcntlArg::F_ADD_SEALS(
+                SealFlag::F_SEAL_SHRINK
+                    | SealFlag::F_SEAL_GROW
+                    | SealFlag::F_SEAL_WRITE
+                    | SealFlag::F_SEAL_SEAL,
+            ),
+        )?;
+
+        Ok(Self {
+            fd: file.into(),
+            formats: unique,
+            indices,
+        })
+    }
+
+    /// The file descriptor of the memfd containing the table.
+    pub fn fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+
+    /// The size of the table in bytes.
+    pub fn size(&self) -> u32 {
+        (self.formats.len() * FORMAT_TABLE_ENTRY_SIZE) as u32
+    }
+
+    /// The formats in the table, in the order they are stored.
+    pub fn formats(&self) -> &[DrmFormat] {
+        &self.formats
+    }
+
+    /// Returns the index of a format in the table.
+    pub fn index_of(&self, format: &DrmFormat) -> Option<u16> {
+        self.indices.get(format).copied()
+    }
+}
+
+bitflags! {
+    /// Flags of a tranche as described by `zwp_linux_dmabuf_feedback_v1::tranche_flags`.
+    pub struct TrancheFlags: u32 {
+        /// The target device may scanout buffers using the formats of the tranche.
+        const SCANOUT = 0b0001;
+    }
+}
+
+/// A tranche of formats preferred for a target device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tranche {
+    /// The device the buffers will be used on.
+    pub target_device: dev_t,
+
+    /// Flags of the tranche.
+    pub flags: TrancheFlags,
+
+    /// Indices of the formats of the tranche in the format table.
+    pub formats: Vec<u16>,
+}
+
+impl Tranche {
+    /// The target device encoded for `zwp_linux_dmabuf_feedback_v1::tranche_target_device`.
+    pub fn target_device_bytes(&self) -> Vec<u8> {
+        self.target_device.to_ne_bytes().to_vec()
+    }
+
+    /// The format indices encoded for `zwp_linux_dmabuf_feedback_v1::tranche_formats`.
+    pub fn formats_bytes(&self) -> Vec<u8> {
+        self.formats
+            .iter()
+            .flat_map(|index| index.to_ne_bytes())
+            .collect()
+    }
+}
+
+/// Feedback sent to clients using `zwp_linux_dmabuf_feedback_v1`.
+#[derive(Debug)]
+pub struct DmabufFeedback {
+    main_device: dev_t,
+    format_table: FormatTable,
+    tranches: Vec<Tranche>,
+}
+
+impl DmabufFeedback {
+    /// The main device of the compositor.
+    pub fn main_device(&self) -> dev_t {
+        self.main_device
+    }
+
+    /// The main device encoded for `zwp_linux_dmabuf_feedback_v1::main_device`.
+    pub fn main_device_bytes(&self) -> Vec<u8> {
+        self.main_device.to_ne_bytes().to_vec()
+    }
+
+    /// The format table containing every format of every tranche.
+    pub fn format_table(&self) -> &FormatTable {
+        &self.format_table
+    }
+
+    /// The tranches, in order of preference.
+    pub fn tranches(&self) -> &[Tranche] {
+        &self.tranches
+    }
+}
+
+/// Builds a [`DmabufFeedback`].
+///
+/// Tranches are sent in the order they are added, followed by a tranche containing all the formats of the main
+/// device.
+#[derive(Debug)]
+pub struct DmabufFeedbackBuilder {
+    main_device: dev_t,
+    main_formats: Vec<DrmFormat>,
+    tranches: Vec<(dev_t, TrancheFlags, Vec<DrmFormat>)>,
+}
+
+impl DmabufFeedbackBuilder {
+    /// Creates a builder for feedback of a main device which supports the specified formats.
+    pub fn new(main_device: dev_t, formats: impl IntoIterator<Item = DrmFormat>) -> Self {
+        Self {
+            main_device,
+            main_formats: formats.into_iter().collect(),
+            tranches: Vec::new(),
+        }
+    }
+
+    /// Creates a builder using the device described by the DRM info as the main device.
+    ///
+    /// The render node is preferred over the primary node. This will return [`None`] if the adapter has
+    /// neither a render node nor a primary node.
+    pub fn from_drm_info(
+        drm_info: &DrmInfo,
+        formats: impl IntoIterator<Item = DrmFormat>,
+    ) -> Option<Self> {
+        let main_device = drm_info.render_dev().or_else(|| drm_info.primary_dev())?;
+        Some(Self::new(main_device, formats))
+    }
+
+    /// Adds a tranche of formats preferred for the target device.
+    ///
+    /// Tranches should be added in order of preference.
+    pub fn add_tranche(
+        mut self,
+        target_device: dev_t,
+        flags: TrancheFlags,
+        formats: impl IntoIterator<Item = DrmFormat>,
+    ) -> Self {
+        self.tranches
+            .push((target_device, flags, formats.into_iter().collect()));
+        self
+    }
+
+    /// Creates the format table and the tranches of the feedback.
+    pub fn build(self) -> io::Result<DmabufFeedback> {
+        let all_formats = self
+            .tranches
+            .iter()
+            .flat_map(|(_, _, formats)| formats.iter())
+            .chain(self.main_formats.iter())
+            .copied();
+        let format_table = FormatTable::new(all_formats)?;
+
+        let indices = |formats: &[DrmFormat]| {
+            let mut seen = HashSet::with_capacity(formats.len());
+
+            formats
+                .iter()
+                .filter_map(|format| format_table.index_of(format))
+                .filter(|&index| seen.insert(index))
+                .collect::<Vec<_>>()
+        };
+
+        let mut tranches = self
+            .tranches
+            .iter()
+            .map(|(target_device, flags, formats)| Tranche {
+                target_device: *target_device,
+                flags: *flags,
+                formats: indices(formats),
+            })
+            .collect::<Vec<_>>();
+
+        // The last tranche contains every format the main device supports.
+        tranches.push(Tranche {
+            target_device: self.main_device,
+            flags: TrancheFlags::empty(),
+            formats: indices(&self.main_formats),
+        });
+
+        Ok(DmabufFeedback {
+            main_device: self.main_device,
+            format_table,
+            tranches,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::{fs::FileExt, io::AsRawFd};
+
+    use drm_fourcc::{DrmFourcc, DrmModifier};
+    use nix::sys::stat::makedev;
+
+    use super::*;
+
+    const ARGB_LINEAR: DrmFormat = DrmFormat {
+        code: DrmFourcc::Argb8888,
+        modifier: DrmModifier::Linear,
+    };
+    const ARGB_INVALID: DrmFormat = DrmFormat {
+        code: DrmFourcc::Argb8888,
+        modifier: DrmModifier::Invalid,
+    };
+    const XRGB_LINEAR: DrmFormat = DrmFormat {
+        code: DrmFourcc::Xrgb8888,
+        modifier: DrmModifier::Linear,
+    };
+    const NV12_LINEAR: DrmFormat = DrmFormat {
+        code: DrmFourcc::Nv12,
+        modifier: DrmModifier::Linear,
+    };
+
+    fn read_table(table: &FormatTable) -> Vec<u8> {
+        let file = File::from(table.fd().try_clone_to_owned().unwrap());
+        let mut data = vec![0; table.size() as usize];
+        file.read_exact_at(&mut data, 0).unwrap();
+        data
+    }
+
+    #[test]
+    fn encode_entry() {
+        let data = encode_format_table(&[ARGB_INVALID]);
+
+        assert_eq!(data.len(), FORMAT_TABLE_ENTRY_SIZE);
+        assert_eq!(data[..4], (DrmFourcc::Argb8888 as u32).to_ne_bytes());
+        assert_eq!(data[4..8], [0; 4]);
+        assert_eq!(data[8..], 0x00ff_ffff_ffff_ffffu64.to_ne_bytes());
+    }
+
+    #[test]
+    fn format_table_dedup() {
+        let table = FormatTable::new([
+            ARGB_LINEAR,
+            XRGB_LINEAR,
+            ARGB_LINEAR,
+            NV12_LINEAR,
+            XRGB_LINEAR,
+        ])
+        .unwrap();
+
+        assert_eq!(table.formats(), [ARGB_LINEAR, XRGB_LINEAR, NV12_LINEAR]);
+        assert_eq!(table.size(), 3 * FORMAT_TABLE_ENTRY_SIZE as u32);
+        assert_eq!(table.index_of(&ARGB_LINEAR), Some(0));
+        assert_eq!(table.index_of(&XRGB_LINEAR), Some(1));
+        assert_eq!(table.index_of(&NV12_LINEAR), Some(2));
+        assert_eq!(table.index_of(&ARGB_INVALID), None);
+    }
+
+    #[test]
+    fn format_table_contents() {
+        let table = FormatTable::new([NV12_LINEAR, ARGB_INVALID, NV12_LINEAR]).unwrap();
+
+        assert_eq!(
+            read_table(&table),
+            encode_format_table(&[NV12_LINEAR, ARGB_INVALID])
+        );
+    }
+
+    #[test]
+    fn format_table_sealed() {
+        let table = FormatTable::new([ARGB_LINEAR]).unwrap();
+        let seals = fcntl(table.fd().as_raw_fd(), FcntlArg::F_GET_SEALS).unwrap();
+
+        assert!(SealFlag::from_bits_truncate(seals).contains(
+            SealFlag::F_SEAL_SHRINK
+                | SealFlag::F_SEAL_GROW
+                | SealFlag::F_SEAL_WRITE
+                | SealFlag::F_SEAL_SEAL
+        ));
+
+        let file = File::from(table.fd().try_clone_to_owned().unwrap());
+        assert!(file.write_at(&[0xff], 0).is_err());
+    }
+
+    #[test]
+    fn format_table_empty() {
+        let table = FormatTable::new([]).unwrap();
+
+        assert_eq!(table.size(), 0);
+        assert!(table.formats().is_empty());
+    }
+
+    #[test]
+    fn feedback_tranches() {
+        let main_device = makedev(226, 128);
+        let scanout_device = makedev(226, 0);
+
+        let feedback = DmabufFeedbackBuilder::new(main_device, [ARGB_LINEAR, NV12_LINEAR])
+            .add_tranche(
+                scanout_device,
+                TrancheFlags::SCANOUT,
+                [XRGB_LINEAR, ARGB_LINEAR, XRGB_LINEAR],
+            )
+            .build()
+            .unwrap();
+
+        assert_eq!(feedback.main_device(), main_device);
+        assert_eq!(feedback.main_device_bytes(), main_device.to_ne_bytes());
+
+        let table = feedback.format_table();
+        assert_eq!(table.formats(), [XRGB_LINEAR, ARGB_LINEAR, NV12_LINEAR]);
+
+        assert_eq!(
+            feedback.tranches(),
+            [
+                Tranche {
+                    target_device: scanout_device,
+                    flags: TrancheFlags::SCANOUT,
+                    formats: vec![0, 1],
+                },
+                Tranche {
+                    target_device: main_device,
+                    flags: TrancheFlags::empty(),
+                    formats: vec![1, 2],
+                },
+            ]
+        );
+
+        let tranche = &feedback.tranches()[0];
+        assert_eq!(tranche.target_device_bytes(), scanout_device.to_ne_bytes());
+        assert_eq!(
+            tranche.formats_bytes(),
+            [0u16.to_ne_bytes(), 1u16.to_ne_bytes()].concat()
+        );
+    }
+
+    #[test]
+    fn feedback_from_drm_info() {
+        let drm_info = DrmInfo {
+            primary_node: Some((226, 0)),
+            render_node: Some((226, 128)),
+        };
+        let builder = DmabufFeedbackBuilder::from_drm_info(&drm_info, [ARGB_LINEAR]).unwrap();
+        assert_eq!(builder.build().unwrap().main_device(), makedev(226, 128));
+
+        let drm_info = DrmInfo {
+            primary_node: Some((226, 0)),
+            render_node: None,
+        };
+        let builder = DmabufFeedbackBuilder::from_drm_info(&drm_info, [ARGB_LINEAR]).unwrap();
+        assert_eq!(builder.build().unwrap().main_device(), makedev(226, 0));
+
+        let drm_info = DrmInfo {
+            primary_node: None,
+            render_node: None,
+        };
+        assert!(DmabufFeedbackBuilder::from_drm_info(&drm_info, [ARGB_LINEAR]).is_none());
+    }
+}
@@ -0,0 +1,37 @@
+use wgpu::{TextureFormat, TextureSampleType, TextureUsages};
+use wgpu_hal::TextureUses;
+
+/// Converts texture usages to the usages of a hal texture.
+///
+/// This is equivalent to the conversion wgpu-core performs when creating a texture.
+pub fn map_texture_usage(usage: TextureUsages, format: TextureFormat) -> TextureUses {
+    let is_color = format.describe().sample_type != TextureSampleType::Depth;
+    let mut uses = TextureUses::empty();
+
+    uses.set(
+        TextureUses::COPY_SRC,
+        usage.contains(TextureUsages::COPY_SRC),
+    );
+    uses.set(
+        TextureUses::COPY_DST,
+        usage.contains(TextureUsages::COPY_DST),
+    );
+    uses.set(
+        TextureUses::RESOURCE,
+        usage.contains(TextureUsages::TEXTURE_BINDING),
+    );
+    uses.set(
+        TextureUses::STORAGE_READ | TextureUses::STORAGE_READ_WRITE,
+        usage.contains(TextureUsages::STORAGE_BINDING),
+    );
+    uses.set(
+        TextureUses::COLOR_TARGET,
+        usage.contains(TextureUsages::RENDER_ATTACHMENT) && is_color,
+    );
+    uses.set(
+        TextureUses::DEPTH_STENCIL_READ | TextureUses::DEPTH_STENCIL_WRITE,
+        usage.contains(TextureUsages::RENDER_ATTACHMENT) && !is_color,
+    );
+
+    uses
+}
@@ -0,0 +1,36 @@
+use wgpu::TextureFormat;
+
+use crate::semaphore::TextureLayout;
+
+/// Returns the sized internal format wgpu-hal uses for a texture format.
+pub fn map_texture_format(format: TextureFormat) -> Option<u32> {
+    Some(match format {
+        TextureFormat::R8Unorm => glow::R8,
+        TextureFormat::Rg8Unorm => glow::RG8,
+        TextureFormat::Rgba8Unorm => glow::RGBA8,
+        TextureFormat::Rgba8UnormSrgb => glow::SRGB8_ALPHA8,
+        TextureFormat::Rgb10a2Unorm => glow::RGB10_A2,
+        TextureFormat::R16Float => glow::R16F,
+        TextureFormat::Rg16Float => glow::RG16F,
+        TextureFormat::Rgba16Float => glow::RGBA16F,
+        TextureFormat::R32Float => glow::R32F,
+        TextureFormat::Rgba32Float => glow::RGBA32F,
+        // GL has no BGRA internal formats, so the memory of a BGRA image exported by another API would be
+        // sampled with the red and blue channels swapped.
+        _ => return None,
+    })
+}
+
+/// Returns the `GL_EXT_semaphore` layout of a texture layout.
+pub fn map_texture_layout(layout: TextureLayout) -> u32 {
+    match layout {
+        TextureLayout::Undefined => glow::NONE,
+        TextureLayout::General => 0x958D,
+        TextureLayout::ColorAttachment => 0x958E,
+        TextureLayout::DepthStencilAttachment => 0x958F,
+        TextureLayout::DepthStencilReadOnly => 0x9590,
+        TextureLayout::ShaderReadOnly => 0x9591,
+        TextureLayout::TransferSrc => 0x9592,
+        TextureLayout::TransferDst => 0x9593,
+    }
+}
@@ -0,0 +1,224 @@
+//! Importing memory objects using `GL_EXT_memory_object_fd`.
+
+use std::os::unix::io::{AsRawFd, IntoRawFd};
+
+use glow::HasContext;
+use wgpu::{TextureDescriptor, TextureDimension, TextureFormat, TextureUsages};
+use wgpu_hal::{api::Gles, Api, DeviceError};
+
+use crate::{
+    imp,
+    opaque_fd::{OpaqueFd, OpaqueFdImportError},
+    ExternalMemoryCapabilities, ExternalMemoryProperties, ExternalMemoryType,
+};
+
+use super::{conv, Inner};
+
+const GL_HANDLE_TYPE_OPAQUE_FD_EXT: u32 = 0x9586;
+
+/// Entry points of `GL_EXT_memory_object` and `GL_EXT_memory_object_fd`.
+#[derive(Clone, Copy)]
+pub struct MemoryObjectFns {
+    pub create_memory_objects_ext: GlCreateMemoryObjectsEXT,
+    pub delete_memory_objects_ext: GlDeleteMemoryObjectsEXT,
+    pub import_memory_fd_ext: GlImportMemoryFdEXT,
+    pub tex_storage_mem_2d_ext: GlTexStorageMem2DEXT,
+}
+
+/// Returns the external memory properties of textures with the format and usage.
+pub fn get_external_memory_capabilities(
+    adapter: Option<&<Gles as Api>::Adapter>,
+    handle_type: ExternalMemoryType,
+    format: TextureFormat,
+    usage: TextureUsages,
+) -> Option<ExternalMemoryProperties> {
+    let adapter = adapter.unwrap();
+    let inner = Inner::shared(adapter)?;
+    let gl = adapter.adapter_context().lock();
+
+    inner.external_texture_properties(&gl, handle_type, format, usage)
+}
+
+impl Inner {
+    pub fn external_memory_capabilities(
+        &self,
+        device: &wgpu::Device,
+        handle_type: ExternalMemoryType,
+        desc: &TextureDescriptor,
+    ) -> Option<ExternalMemoryProperties> {
+        // Only single 2D textures may be imported.
+        if desc.dimension != TextureDimension::D2
+            || desc.size.depth_or_array_layers != 1
+            || desc.mip_level_count != 1
+            || desc.sample_count != 1
+        {
+            return None;
+        }
+
+        unsafe {
+            device.as_hal::<Gles, _, _>(|hal_device| {
+                let gl = hal_device.unwrap().context().lock();
+                self.external_texture_properties(&gl, handle_type, desc.format, desc.usage)
+            })
+        }
+    }
+
+    /// Opaque fds may only be imported, using `GL_EXT_memory_object_fd`. GL has no way to export memory.
+    fn external_texture_properties(
+        &self,
+        gl: &glow::Context,
+        handle_type: ExternalMemoryType,
+        format: TextureFormat,
+        usage: TextureUsages,
+    ) -> Option<ExternalMemoryProperties> {
+        if handle_type != ExternalMemoryType::OpaqueFd
+            || usage.is_empty()
+            || self.fns.memory_object.is_none()
+            || conv::map_texture_format(format).is_none()
+            || !supports_memory_object_fd(gl)
+        {
+            return None;
+        }
+
+        Some(ExternalMemoryProperties {
+            capabilities: ExternalMemoryCapabilities::IMPORT,
+            dedicated_only: false,
+            prefers_dedicated: false,
+        })
+    }
+
+    pub fn import_opaque_fd(
+        &self,
+        device: &wgpu::Device,
+        memory: OpaqueFd,
+        desc: &TextureDescriptor,
+    ) -> Result<wgpu::Texture, OpaqueFdImportError> {
+        let fns = self
+            .fns
+            .memory_object
+            .ok_or(OpaqueFdImportError::Unsupported)?;
+
+        if desc.dimension != TextureDimension::D2
+            || desc.size.depth_or_array_layers != 1
+            || desc.mip_level_count != 1
+            || desc.sample_count != 1
+        {
+            return Err(OpaqueFdImportError::InvalidDescriptor);
+        }
+
+        let internal_format = conv::map_texture_format(desc.format)
+            .ok_or(OpaqueFdImportError::UnsupportedFormat(desc.format))?;
+
+        unsafe {
+            device.as_hal::<Gles, _, _>(|hal_device| {
+                let hal_device = hal_device.unwrap();
+                let gl = hal_device.context().lock();
+
+                if !supports_memory_object_fd(&gl) {
+                    return Err(OpaqueFdImportError::Unsupported);
+                }
+
+                let mut memory_object = 0;
+                (fns.create_memory_objects_ext)(1, &mut memory_object);
+                (fns.import_memory_fd_ext)(
+                    memory_object,
+                    memory.size,
+                    GL_HANDLE_TYPE_OPAQUE_FD_EXT,
+                    memory.fd.as_raw_fd(),
+                );
+
+                if gl.get_error() != glow::NO_ERROR {
+                    (fns.delete_memory_objects_ext)(1, &memory_object);
+                    return Err(OpaqueFdImportError::ImportFailed);
+                }
+
+                // A successful import transfers ownership of the file descriptor to the driver.
+                let _ = memory.fd.into_raw_fd();
+
+                let texture = match gl.create_texture() {
+                    Ok(texture) => texture,
+                    Err(_) => {
+                        (fns.delete_memory_objects_ext)(1, &memory_object);
+                        return Err(DeviceError::OutOfMemory.into());
+                    }
+                };
+
+                gl.bind_texture(glow::TEXTURE_2D, Some(texture));
+                (fns.tex_storage_mem_2d_ext)(
+                    glow::TEXTURE_2D,
+                    1,
+                    internal_format,
+                    desc.size.width as i32,
+                    desc.size.height as i32,
+                    memory_object,
+                    0,
+                );
+                let error = gl.get_error();
+                gl.bind_texture(glow::TEXTURE_2D, None);
+
+                // The texture keeps the memory alive after the memory object is deleted.
+                (fns.delete_memory_objects_ext)(1, &memory_object);
+
+                if error != glow::NO_ERROR {
+                    gl.delete_texture(texture);
+                    return Err(OpaqueFdImportError::ImportFailed);
+                }
+
+                drop(gl);
+
+                let hal_desc = wgpu_hal::TextureDescriptor {
+                    label: desc.label,
+                    size: desc.size,
+                    mip_level_count: desc.mip_level_count,
+                    sample_count: desc.sample_count,
+                    dimension: desc.dimension,
+                    format: desc.format,
+                    usage: imp::conv::map_texture_usage(desc.usage, desc.format),
+                    memory_flags: wgpu_hal::MemoryFlags::empty(),
+                };
+
+                // wgpu-hal deletes the texture when it is destroyed.
+                let hal_texture = hal_device.texture_from_raw(texture.0, &hal_desc, None);
+
+                Ok(device.create_texture_from_hal::<Gles>(hal_texture, desc))
+            })
+        }
+    }
+}
+
+/// Whether the context supports importing memory objects from opaque fds.
+fn supports_memory_object_fd(gl: &glow::Context) -> bool {
+    // The entry points may be exposed even if the context does not support the extensions.
+    const GL_EXTENSIONS: &[&str] = &["GL_EXT_memory_object", "GL_EXT_memory_object_fd"];
+
+    GL_EXTENSIONS
+        .iter()
+        .all(|&name| gl.supported_extensions().contains(name))
+}
+
+pub type GlCreateMemoryObjectsEXT = unsafe extern "C" fn(
+    i32,      // n
+    *mut u32, // memoryObjects
+);
+
+pub type GlDeleteMemoryObjectsEXT = unsafe extern "C" fn(
+    i32,        // n
+    *const u32, // memoryObjects
+);
+
+pub type GlImportMemoryFdEXT = unsafe extern "C" fn(
+    u32, // memory
+    u64, // size
+    u32, // handleType
+    i32, // fd
+);
+
+pub type GlTexStorageMem2DEXT = unsafe extern "C" fn(
+    u32, // target
+    i32, // levels
+    u32, // internalFormat
+    i32, // width
+    i32, // height
+    u32, // memory
+    u64, // offset
+);
@@ -1,10 +1,18 @@
 // TODO:
 // - EGL is going to be limited to export only unless wgpu has changes to support https://www.khronos.org/registry/OpenGL/extensions/OES/OES_EGL_image_external.txt
 
+mod conv;
+mod memory_object;
+mod semaphore;
+
 use std::{
+    collections::HashSet,
     ffi::{c_void, CStr},
-    mem,
-    os::raw::{c_char, c_uint}, path::Path,
+    fmt, mem,
+    os::raw::{c_char, c_uint},
+    path::Path,
+    ptr,
+    sync::{Arc, Mutex},
 };
 
 use nix::sys::stat::{major, minor, stat};
@@ -12,7 +20,21 @@ use wgpu::{Adapter, DeviceDescriptor, RequestDeviceError};
 use wgpu_core::api::Gles;
 use wgpu_hal::{Api, InstanceDescriptor};
 
-use crate::{adapter::{DeviceUuids, DrmInfo, UUID_LEN}, ExternalMemoryDevice};
+use self::memory_object::{
+    GlCreateMemoryObjectsEXT, GlDeleteMemoryObjectsEXT, GlImportMemoryFdEXT, GlTexStorageMem2DEXT,
+    MemoryObjectFns,
+};
+use self::semaphore::{
+    EglCreateSyncKHR, EglDestroySyncKHR, EglWaitSyncKHR, GlDeleteSemaphoresEXT, GlGenSemaphoresEXT,
+    GlImportSemaphoreFdEXT, GlSignalSemaphoreEXT, GlWaitSemaphoreEXT, NativeFenceSyncFns,
+    SemaphoreFns,
+};
+pub use self::{memory_object::get_external_memory_capabilities, semaphore::Semaphore};
+use crate::{
+    adapter::{AdapterExt, DeviceUuids, DrmInfo, UUID_LEN},
+    instance::DrmInstanceDescriptor,
+    ExternalMemoryDevice,
+};
 
 pub const EGL_EXTENSIONS: i32 = 0x3055;
 pub const EGL_DEVICE_EXT: i32 = 0x322C;
@@ -22,14 +44,19 @@ pub const EGL_DRM_RENDER_NODE_FILE_EXT: i32 = 0x3377;
 pub const DEVICE_UUID_EXT: i32 = 0x9597;
 pub const DRIVER_UUID_EXT: i32 = 0x9598;
 
-pub fn try_create_instance(desc: InstanceDescriptor<'static>) -> Option<<Gles as Api>::Instance> {
+pub fn try_create_instance(desc: &DrmInstanceDescriptor) -> Option<<Gles as Api>::Instance> {
+    let desc = InstanceDescriptor {
+        name: desc.application_name,
+        flags: desc.flags,
+    };
+
     // EGL does not require any additional instance creation parameters, so we can use the default `init` function.
     unsafe { <<Gles as Api>::Instance as wgpu_hal::Instance<Gles>>::init(&desc) }.ok()
 }
 
 pub fn get_device_uuids(adapter: Option<&<Gles as Api>::Adapter>) -> Option<DeviceUuids> {
     let adapter = adapter.unwrap();
-    let instance = adapter.adapter_context().egl_instance()?;
+    let get_unsigned_bytev_ext = Inner::shared(adapter)?.fns.get_unsigned_bytev_ext?;
 
     // GL_EXT_external_objects is an OpenGL extension, to test support make the context current.
     {
@@ -45,12 +72,6 @@ pub fn get_device_uuids(adapter: Option<&<Gles as Api>::Adapter>) -> Option<Devi
             .iter()
             .any(|name| gl_extensions.iter().any(|ext| ext == name))
         {
-            // Load GetUnsignedBytevEXT
-            let get_unsigned_bytev_ext = {
-                let fn_ = instance.get_proc_address("glGetUnsignedBytevEXT")?;
-                unsafe { mem::transmute::<_, GlGetUnsignedBytevEXT>(fn_) }
-            };
-
             let mut device_uuid = [0u8; UUID_LEN];
             let mut driver_uuid = [0u8; UUID_LEN];
 
@@ -70,52 +91,7 @@ pub fn get_device_uuids(adapter: Option<&<Gles as Api>::Adapter>) -> Option<Devi
 }
 
 pub fn get_drm_info(adapter: Option<&<Gles as Api>::Adapter>) -> Option<DrmInfo> {
-    let adapter = adapter.unwrap();
-    let egl_device = get_egl_device(adapter)?;
-
-    // Check the device extensions.
-    let device_extensions = unsafe { get_device_extensions(adapter, egl_device) }?;
-
-    if !device_extensions
-        .iter()
-        .any(|name| name == "EGL_EXT_device_drm")
-    {
-        return None;
-    }
-
-    let has_render = device_extensions
-        .iter()
-        .any(|name| name == "EGL_EXT_device_drm_render_node");
-
-    let primary_node_path =
-        unsafe { query_device_string(adapter, egl_device, EGL_DRM_DEVICE_FILE_EXT) }?;
-    let render_node_path = if has_render {
-        unsafe { query_device_string(adapter, egl_device, EGL_DRM_RENDER_NODE_FILE_EXT) }
-    } else {
-        None
-    };
-
-    let mut drm_info = DrmInfo {
-        primary_node: None,
-        render_node: None,
-    };
-
-    // stat the paths to get the major and minor numbers
-    if let Ok(stat) = stat(primary_node_path.as_str()) {
-        drm_info.primary_node = Some((major(stat.st_rdev), minor(stat.st_rdev)));
-    }
-
-    if let Some(render_node_path) = render_node_path {
-        if let Ok(stat) = stat(render_node_path.as_str()) {
-            drm_info.render_node = Some((major(stat.st_rdev), minor(stat.st_rdev)));
-        }
-    }
-
-    if drm_info.primary_node.is_none() && drm_info.primary_node.is_none() {
-        return None;
-    }
-
-    Some(drm_info)
+    Inner::shared(adapter.unwrap())?.drm_info()
 }
 
 pub fn request_device(
@@ -129,102 +105,268 @@ pub fn request_device(
             let adapter = adapter.unwrap();
             wgpu_hal::Adapter::open(adapter, desc.features, &desc.limits)
         })
-    }.map_err(|_| RequestDeviceError)?;
+    }
+    .map_err(|_| RequestDeviceError)?;
 
+    // The device is still usable without the display, it just can not share memory or semaphores.
+    let inner = unsafe { adapter.as_hal::<Gles, _, _>(|adapter| Inner::shared(adapter.unwrap())) }
+        .unwrap_or_else(|| {
+            log::warn!("Failed to load the EGL display, external memory is not supported");
+            Arc::new(Inner::unsupported())
+        });
+
+    let uuids = adapter.uuids();
     let (device, queue) = unsafe { adapter.create_device_from_hal(hal_device, desc, trace_path) }?;
 
     Ok((
         ExternalMemoryDevice {
+            inner: super::DeviceInner::Egl(inner),
             device,
-            inner: super::DeviceInner::Egl,
+            uuids,
         },
         queue,
     ))
 }
 
-fn get_egl_extensions(adapter: &<Gles as Api>::Adapter) -> Option<Vec<String>> {
-    let instance = adapter.adapter_context().egl_instance()?;
-
-    // Passing None for display is intentional as it returns the EGL extensions rather than display extensions.
-    let egl_extensions = instance.query_string(None, EGL_EXTENSIONS).ok()?;
-    let egl_extensions = egl_extensions.to_string_lossy().into_owned();
-    Some(
-        egl_extensions
-            .split_whitespace()
-            .map(|s| s.to_owned())
-            .collect::<Vec<_>>(),
-    )
+/// Extension entry points, which are [`None`] if the implementation does not provide them.
+#[derive(Clone, Copy)]
+pub struct EglFns {
+    pub query_display_attrib_ext: Option<EglQueryDisplayAttribEXT>,
+    pub query_device_string_ext: Option<QueryDeviceStringEXT>,
+    pub get_unsigned_bytev_ext: Option<GlGetUnsignedBytevEXT>,
+    /// Only loaded if every entry point of `GL_EXT_memory_object_fd` is available.
+    pub memory_object: Option<MemoryObjectFns>,
+    /// Only loaded if every entry point of `GL_EXT_semaphore_fd` is available.
+    pub semaphore: Option<SemaphoreFns>,
+    /// Only loaded if every entry point needed to wait on native fences is available.
+    pub native_fence_sync: Option<NativeFenceSyncFns>,
 }
 
-#[allow(dead_code)]
-fn get_display_extensions(adapter: &<Gles as Api>::Adapter) -> Option<Vec<String>> {
-    let instance = adapter.adapter_context().egl_instance()?;
-    let display = *adapter.adapter_context().raw_display()?;
-
-    let egl_extensions = instance.query_string(Some(display), EGL_EXTENSIONS).ok()?;
-    let egl_extensions = egl_extensions.to_string_lossy().into_owned();
-    Some(
-        egl_extensions
-            .split_whitespace()
-            .map(|s| s.to_owned())
-            .collect::<Vec<_>>(),
-    )
+/// The EGL display of a device, the `EGLDeviceEXT` of the display and the supported extensions.
+pub struct Inner {
+    /// The `EGLDisplay` used by the device.
+    pub display: *mut c_void,
+    /// The `EGLDeviceEXT` of the display, if `EGL_EXT_device_query` is supported and the display has a device.
+    pub device: Option<*mut c_void>,
+    pub client_extensions: HashSet<String>,
+    pub display_extensions: HashSet<String>,
+    pub device_extensions: HashSet<String>,
+    pub fns: EglFns,
 }
 
-unsafe fn get_device_extensions(
-    adapter: &<Gles as Api>::Adapter,
-    device: *mut c_void,
-) -> Option<Vec<String>> {
-    Some(
-        query_device_string(adapter, device, EGL_EXTENSIONS)?
-            .split_whitespace()
-            .map(ToOwned::to_owned)
-            .collect::<Vec<_>>(),
-    )
-}
+// SAFETY: EGL displays and devices may be used from any thread.
+unsafe impl Send for Inner {}
+unsafe impl Sync for Inner {}
+
+/// The displays whose entry points and extensions have been loaded.
+///
+/// EGL returns the same `EGLDisplay` for every adapter of a native display, so the queries of an adapter and
+/// the devices created from it share a single `Inner`.
+static DISPLAYS: Mutex<Vec<Arc<Inner>>> = Mutex::new(Vec::new());
+
+impl Inner {
+    /// Returns the extension entry points and extensions of the display of the adapter, loading them the
+    /// first time the display is used.
+    ///
+    /// This will return [`None`] if the adapter does not use EGL.
+    pub fn shared(adapter: &<Gles as Api>::Adapter) -> Option<Arc<Self>> {
+        let display = adapter.adapter_context().raw_display()?.as_ptr();
+        let mut displays = DISPLAYS.lock().unwrap();
+
+        if let Some(inner) = displays.iter().find(|inner| inner.display == display) {
+            return Some(inner.clone());
+        }
 
-unsafe fn query_device_string(
-    adapter: &<Gles as Api>::Adapter,
-    device: *mut c_void,
-    name: i32,
-) -> Option<String> {
-    let instance = adapter.adapter_context().egl_instance()?;
-    let query_device_string_ext = mem::transmute::<_, QueryDeviceStringEXT>(
-        instance.get_proc_address("eglQueryDeviceStringEXT")?,
-    );
-    let raw_extensions = query_device_string_ext(device, name);
-
-    if raw_extensions.is_null() {
-        return None;
+        let inner = Arc::new(Self::load(adapter)?);
+        displays.push(inner.clone());
+        Some(inner)
     }
 
-    Some(CStr::from_ptr(raw_extensions).to_str().unwrap().to_owned())
-}
+    /// An `Inner` without a display or any extensions, which reports every external object as unsupported.
+    pub fn unsupported() -> Self {
+        Self {
+            display: ptr::null_mut(),
+            device: None,
+            client_extensions: HashSet::new(),
+            display_extensions: HashSet::new(),
+            device_extensions: HashSet::new(),
+            fns: EglFns {
+                query_display_attrib_ext: None,
+                query_device_string_ext: None,
+                get_unsigned_bytev_ext: None,
+                memory_object: None,
+                semaphore: None,
+                native_fence_sync: None,
+            },
+        }
+    }
 
-fn get_egl_device(adapter: &<Gles as Api>::Adapter) -> Option<*mut c_void> {
-    let instance = adapter.adapter_context().egl_instance()?;
-    let display = *adapter.adapter_context().raw_display()?;
+    /// Loads the extension entry points and queries the extensions of the display of the adapter and its
+    /// device.
+    fn load(adapter: &<Gles as Api>::Adapter) -> Option<Self> {
+        let instance = adapter.adapter_context().egl_instance()?;
+        let display = *adapter.adapter_context().raw_display()?;
+        let fns = unsafe {
+            EglFns {
+                query_display_attrib_ext: instance
+                    .get_proc_address("eglQueryDisplayAttribEXT")
+                    .map(|fn_| mem::transmute::<_, EglQueryDisplayAttribEXT>(fn_)),
+                query_device_string_ext: instance
+                    .get_proc_address("eglQueryDeviceStringEXT")
+                    .map(|fn_| mem::transmute::<_, QueryDeviceStringEXT>(fn_)),
+                get_unsigned_bytev_ext: instance
+                    .get_proc_address("glGetUnsignedBytevEXT")
+                    .map(|fn_| mem::transmute::<_, GlGetUnsignedBytevEXT>(fn_)),
+                memory_object: (|| {
+                    Some(MemoryObjectFns {
+                        create_memory_objects_ext: mem::transmute::<_, GlCreateMemoryObjectsEXT>(
+                            instance.get_proc_address("glCreateMemoryObjectsEXT")?,
+                        ),
+                        delete_memory_objects_ext: mem::transmute::<_, GlDeleteMemoryObjectsEXT>(
+                            instance.get_proc_address("glDeleteMemoryObjectsEXT")?,
+                        ),
+                        import_memory_fd_ext: mem::transmute::<_, GlImportMemoryFdEXT>(
+                            instance.get_proc_address("glImportMemoryFdEXT")?,
+                        ),
+                        tex_storage_mem_2d_ext: mem::transmute::<_, GlTexStorageMem2DEXT>(
+                            instance.get_proc_address("glTexStorageMem2DEXT")?,
+                        ),
+                    })
+                })(),
+                semaphore: (|| {
+                    Some(SemaphoreFns {
+                        gen_semaphores_ext: mem::transmute::<_, GlGenSemaphoresEXT>(
+                            instance.get_proc_address("glGenSemaphoresEXT")?,
+                        ),
+                        delete_semaphores_ext: mem::transmute::<_, GlDeleteSemaphoresEXT>(
+                            instance.get_proc_address("glDeleteSemaphoresEXT")?,
+                        ),
+                        import_semaphore_fd_ext: mem::transmute::<_, GlImportSemaphoreFdEXT>(
+                            instance.get_proc_address("glImportSemaphoreFdEXT")?,
+                        ),
+                        wait_semaphore_ext: mem::transmute::<_, GlWaitSemaphoreEXT>(
+                            instance.get_proc_address("glWaitSemaphoreEXT")?,
+                        ),
+                        signal_semaphore_ext: mem::transmute::<_, GlSignalSemaphoreEXT>(
+                            instance.get_proc_address("glSignalSemaphoreEXT")?,
+                        ),
+                    })
+                })(),
+                native_fence_sync: (|| {
+                    Some(NativeFenceSyncFns {
+                        create_sync_khr: mem::transmute::<_, EglCreateSyncKHR>(
+                            instance.get_proc_address("eglCreateSyncKHR")?,
+                        ),
+                        destroy_sync_khr: mem::transmute::<_, EglDestroySyncKHR>(
+                            instance.get_proc_address("eglDestroySyncKHR")?,
+                        ),
+                        wait_sync_khr: mem::transmute::<_, EglWaitSyncKHR>(
+                            instance.get_proc_address("eglWaitSyncKHR")?,
+                        ),
+                    })
+                })(),
+            }
+        };
 
-    let egl_extensions = get_egl_extensions(adapter)?;
+        // Passing None for display is intentional as it returns the EGL extensions rather than display extensions.
+        let client_extensions = instance
+            .query_string(None, EGL_EXTENSIONS)
+            .map(parse_extensions)
+            .unwrap_or_default();
+        let display_extensions = instance
+            .query_string(Some(display), EGL_EXTENSIONS)
+            .map(parse_extensions)
+            .unwrap_or_default();
+
+        let mut inner = Self {
+            display: display.as_ptr(),
+            device: None,
+            client_extensions,
+            display_extensions,
+            device_extensions: HashSet::new(),
+            fns,
+        };
 
-    const REQUIRED_EXTENSIONS: &[&str] = &["EGL_EXT_device_base", "EGL_EXT_device_query"];
+        inner.device = inner.query_egl_device();
 
-    if REQUIRED_EXTENSIONS
-        .iter()
-        .all(|req| egl_extensions.iter().any(|name| name == req))
-    {
-        // Load the function to get the EGLDevice
-        let query_display_attrib_ext = {
-            let fn_ = instance.get_proc_address("eglQueryDisplayAttribEXT")?;
-            unsafe { mem::transmute::<_, EglQueryDisplayAttribEXT>(fn_) }
+        if let Some(device) = inner.device {
+            inner.device_extensions = unsafe { inner.query_device_string(device, EGL_EXTENSIONS) }
+                .map(|extensions| parse_extensions(&extensions))
+                .unwrap_or_default();
+        }
+
+        Some(inner)
+    }
+
+    pub fn drm_info(&self) -> Option<DrmInfo> {
+        let device = self.device?;
+
+        if !self.device_extensions.contains("EGL_EXT_device_drm") {
+            return None;
+        }
+
+        let has_render = self
+            .device_extensions
+            .contains("EGL_EXT_device_drm_render_node");
+
+        let primary_node_path =
+            unsafe { self.query_device_string(device, EGL_DRM_DEVICE_FILE_EXT) }?;
+        let render_node_path = if has_render {
+            unsafe { self.query_device_string(device, EGL_DRM_RENDER_NODE_FILE_EXT) }
+        } else {
+            None
+        };
+
+        let mut drm_info = DrmInfo {
+            primary_node: None,
+            render_node: None,
         };
 
+        // stat the paths to get the major and minor numbers
+        if let Ok(stat) = stat(primary_node_path.as_str()) {
+            drm_info.primary_node = Some((major(stat.st_rdev), minor(stat.st_rdev)));
+        }
+
+        if let Some(render_node_path) = render_node_path {
+            if let Ok(stat) = stat(render_node_path.as_str()) {
+                drm_info.render_node = Some((major(stat.st_rdev), minor(stat.st_rdev)));
+            }
+        }
+
+        if drm_info.primary_node.is_none() && drm_info.render_node.is_none() {
+            return None;
+        }
+
+        Some(drm_info)
+    }
+
+    unsafe fn query_device_string(&self, device: *mut c_void, name: i32) -> Option<String> {
+        let query_device_string_ext = self.fns.query_device_string_ext?;
+        let raw_string = query_device_string_ext(device, name);
+
+        if raw_string.is_null() {
+            return None;
+        }
+
+        Some(CStr::from_ptr(raw_string).to_string_lossy().into_owned())
+    }
+
+    fn query_egl_device(&self) -> Option<*mut c_void> {
+        const REQUIRED_EXTENSIONS: &[&str] = &["EGL_EXT_device_base", "EGL_EXT_device_query"];
+
+        if !REQUIRED_EXTENSIONS
+            .iter()
+            .all(|&req| self.client_extensions.contains(req))
+        {
+            return None;
+        }
+
+        let query_display_attrib_ext = self.fns.query_display_attrib_ext?;
         let mut device: isize = 0;
 
         // Get the EGLDevice corresponding to the display
-        if unsafe {
-            query_display_attrib_ext(display.as_ptr(), EGL_DEVICE_EXT, &mut device as *mut _)
-        } != 1
+        if unsafe { query_display_attrib_ext(self.display, EGL_DEVICE_EXT, &mut device as *mut _) }
+            != 1
         {
             // No device available, could be software EGL.
             return None;
@@ -235,24 +377,42 @@ fn get_egl_device(adapter: &<Gles as Api>::Adapter) -> Option<*mut c_void> {
             return None;
         }
 
-        return Some(device as *mut c_void);
+        Some(device as *mut c_void)
     }
+}
 
-    None
+impl fmt::Debug for Inner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Inner")
+            .field("display", &self.display)
+            .field("device", &self.device)
+            .field("client_extensions", &self.client_extensions)
+            .field("display_extensions", &self.display_extensions)
+            .field("device_extensions", &self.device_extensions)
+            .finish()
+    }
+}
+
+fn parse_extensions(extensions: &CStr) -> HashSet<String> {
+    extensions
+        .to_string_lossy()
+        .split_whitespace()
+        .map(ToOwned::to_owned)
+        .collect()
 }
 
-type QueryDeviceStringEXT = unsafe extern "C" fn(
+pub type QueryDeviceStringEXT = unsafe extern "C" fn(
     *mut c_void, // egl device
     i32,         // name
 ) -> *const c_char;
 
-type EglQueryDisplayAttribEXT = unsafe extern "C" fn(
+pub type EglQueryDisplayAttribEXT = unsafe extern "C" fn(
     *mut c_void, // dpy
     i32,         // attribute
     *mut isize,  // value
 ) -> c_uint;
 
-type GlGetUnsignedBytevEXT = unsafe extern "C" fn(
+pub type GlGetUnsignedBytevEXT = unsafe extern "C" fn(
     i32,     // pname
     *mut u8, // data
 );
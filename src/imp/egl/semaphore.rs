@@ -0,0 +1,348 @@
+//! Synchronisation with other devices using `GL_EXT_semaphore_fd` and `EGL_ANDROID_native_fence_sync`.
+
+use std::{
+    ffi::c_void,
+    os::{
+        raw::c_uint,
+        unix::io::{AsRawFd, IntoRawFd},
+    },
+    ptr,
+};
+
+use glow::HasContext;
+use wgpu_hal::api::Gles;
+
+use crate::semaphore::{
+    SemaphoreError, SemaphoreFd, SemaphoreHandleType, SemaphoreOperation, SemaphoreTexture,
+};
+
+use super::{conv, Inner};
+
+const GL_HANDLE_TYPE_OPAQUE_FD_EXT: u32 = 0x9586;
+
+const EGL_TRUE: i32 = 1;
+const EGL_NONE: i32 = 0x3038;
+const EGL_SYNC_NATIVE_FENCE_ANDROID: u32 = 0x3144;
+const EGL_SYNC_NATIVE_FENCE_FD_ANDROID: i32 = 0x3145;
+
+/// Entry points of `GL_EXT_semaphore` and `GL_EXT_semaphore_fd`.
+#[derive(Clone, Copy)]
+pub struct SemaphoreFns {
+    pub gen_semaphores_ext: GlGenSemaphoresEXT,
+    pub delete_semaphores_ext: GlDeleteSemaphoresEXT,
+    pub import_semaphore_fd_ext: GlImportSemaphoreFdEXT,
+    pub wait_semaphore_ext: GlWaitSemaphoreEXT,
+    pub signal_semaphore_ext: GlSignalSemaphoreEXT,
+}
+
+/// Entry points of `EGL_KHR_fence_sync` and `EGL_KHR_wait_sync`, used with `EGL_ANDROID_native_fence_sync`.
+#[derive(Clone, Copy)]
+pub struct NativeFenceSyncFns {
+    pub create_sync_khr: EglCreateSyncKHR,
+    pub destroy_sync_khr: EglDestroySyncKHR,
+    pub wait_sync_khr: EglWaitSyncKHR,
+}
+
+/// An imported semaphore.
+#[derive(Debug)]
+pub enum Semaphore {
+    /// A `GL_EXT_semaphore` semaphore object imported from an opaque file descriptor.
+    Gl(u32),
+
+    /// An `EGLSyncKHR` imported from a sync file.
+    ///
+    /// GL has no semaphore handle type for sync files, so the sync file is imported as a native fence.
+    NativeFence(*mut c_void),
+}
+
+// SAFETY: Semaphore objects and EGL syncs may be used with the context on any thread.
+unsafe impl Send for Semaphore {}
+unsafe impl Sync for Semaphore {}
+
+impl Semaphore {
+    pub fn handle_type(&self) -> SemaphoreHandleType {
+        match self {
+            Semaphore::Gl(_) => SemaphoreHandleType::OpaqueFd,
+            Semaphore::NativeFence(_) => SemaphoreHandleType::SyncFd,
+        }
+    }
+}
+
+impl Inner {
+    pub fn import_semaphore(
+        &self,
+        device: &wgpu::Device,
+        semaphore: SemaphoreFd,
+    ) -> Result<Semaphore, SemaphoreError> {
+        let handle_type = semaphore.handle_type;
+
+        match handle_type {
+            SemaphoreHandleType::OpaqueFd => {
+                let fns = self
+                    .fns
+                    .semaphore
+                    .ok_or(SemaphoreError::Unsupported(handle_type))?;
+
+                unsafe {
+                    device.as_hal::<Gles, _, _>(|hal_device| {
+                        let gl = hal_device.unwrap().context().lock();
+
+                        // The entry points may be exposed even if the context does not support the extensions.
+                        const GL_EXTENSIONS: &[&str] = &["GL_EXT_semaphore", "GL_EXT_semaphore_fd"];
+
+                        if !GL_EXTENSIONS
+                            .iter()
+                            .all(|&name| gl.supported_extensions().contains(name))
+                        {
+                            return Err(SemaphoreError::Unsupported(handle_type));
+                        }
+
+                        let mut raw = 0;
+                        (fns.gen_semaphores_ext)(1, &mut raw);
+                        (fns.import_semaphore_fd_ext)(
+                            raw,
+                            GL_HANDLE_TYPE_OPAQUE_FD_EXT,
+                            semaphore.fd.as_raw_fd(),
+                        );
+
+                        if gl.get_error() != glow::NO_ERROR {
+                            (fns.delete_semaphores_ext)(1, &raw);
+                            return Err(SemaphoreError::ImportFailed);
+                        }
+
+                        // A successful import transfers ownership of the file descriptor to the driver.
+                        let _ = semaphore.fd.into_raw_fd();
+
+                        Ok(Semaphore::Gl(raw))
+                    })
+                }
+            }
+
+            SemaphoreHandleType::SyncFd => {
+                const EGL_EXTENSIONS: &[&str] =
+                    &["EGL_ANDROID_native_fence_sync", "EGL_KHR_wait_sync"];
+
+                let fns = self
+                    .fns
+                    .native_fence_sync
+                    .filter(|_| {
+                        EGL_EXTENSIONS
+                            .iter()
+                            .all(|&name| self.display_extensions.contains(name))
+                    })
+                    .ok_or(SemaphoreError::Unsupported(handle_type))?;
+
+                let attribs = [
+                    EGL_SYNC_NATIVE_FENCE_FD_ANDROID,
+                    semaphore.fd.as_raw_fd(),
+                    EGL_NONE,
+                ];
+
+                // The display of the sync must be the display of the current context.
+                let sync = unsafe {
+                    device.as_hal::<Gles, _, _>(|hal_device| {
+                        let _gl = hal_device.unwrap().context().lock();
+                        (fns.create_sync_khr)(
+                            self.display,
+                            EGL_SYNC_NATIVE_FENCE_ANDROID,
+                            attribs.as_ptr(),
+                        )
+                    })
+                };
+
+                if sync.is_null() {
+                    return Err(SemaphoreError::ImportFailed);
+                }
+
+                // A successful import transfers ownership of the file descriptor to the driver.
+                let _ = semaphore.fd.into_raw_fd();
+
+                Ok(Semaphore::NativeFence(sync))
+            }
+        }
+    }
+
+    pub fn destroy_semaphore(&self, device: &wgpu::Device, semaphore: &Semaphore) {
+        unsafe {
+            device.as_hal::<Gles, _, _>(|hal_device| {
+                let _gl = hal_device.unwrap().context().lock();
+
+                match *semaphore {
+                    Semaphore::Gl(raw) => {
+                        if let Some(fns) = self.fns.semaphore {
+                            (fns.delete_semaphores_ext)(1, &raw);
+                        }
+                    }
+
+                    Semaphore::NativeFence(sync) => {
+                        if let Some(fns) = self.fns.native_fence_sync {
+                            (fns.destroy_sync_khr)(self.display, sync);
+                        }
+                    }
+                }
+            })
+        }
+    }
+
+    pub fn submit_with_semaphores<I: IntoIterator<Item = wgpu::CommandBuffer>>(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        command_buffers: I,
+        waits: &[SemaphoreOperation],
+        signals: &[SemaphoreOperation],
+    ) -> Result<wgpu::SubmissionIndex, SemaphoreError> {
+        // Resolve the textures before any semaphore operation is issued, so an invalid texture does not leave a
+        // wait without the submission it guards.
+        let waits = waits
+            .iter()
+            .map(|op| Ok((&op.semaphore.inner, raw_textures(op.textures)?)))
+            .collect::<Result<Vec<_>, SemaphoreError>>()?;
+        let signals = signals
+            .iter()
+            .map(|op| match op.semaphore.inner {
+                Semaphore::Gl(raw) => Ok((raw, raw_textures(op.textures)?)),
+                Semaphore::NativeFence(_) => Err(SemaphoreError::SignalSyncFd),
+            })
+            .collect::<Result<Vec<_>, SemaphoreError>>()?;
+
+        // The GLES backend executes submissions on the context when the queue is submitted, so semaphore
+        // operations issued before and after the submission are ordered around it.
+        unsafe {
+            device.as_hal::<Gles, _, _>(|hal_device| {
+                let _gl = hal_device.unwrap().context().lock();
+
+                for (semaphore, (textures, layouts)) in &waits {
+                    match **semaphore {
+                        Semaphore::Gl(raw) => {
+                            let fns = self.fns.semaphore.unwrap();
+                            (fns.wait_semaphore_ext)(
+                                raw,
+                                0,
+                                ptr::null(),
+                                textures.len() as u32,
+                                textures.as_ptr(),
+                                layouts.as_ptr(),
+                            );
+                        }
+
+                        // Sync files do not transition textures, the layouts are ignored.
+                        Semaphore::NativeFence(sync) => {
+                            let fns = self.fns.native_fence_sync.unwrap();
+
+                            if (fns.wait_sync_khr)(self.display, sync, 0) != EGL_TRUE {
+                                return Err(SemaphoreError::WaitFailed);
+                            }
+                        }
+                    }
+                }
+
+                Ok(())
+            })
+        }?;
+
+        let index = queue.submit(command_buffers);
+
+        if !signals.is_empty() {
+            unsafe {
+                device.as_hal::<Gles, _, _>(|hal_device| {
+                    let gl = hal_device.unwrap().context().lock();
+                    let fns = self.fns.semaphore.unwrap();
+
+                    for (raw, (textures, layouts)) in &signals {
+                        (fns.signal_semaphore_ext)(
+                            *raw,
+                            0,
+                            ptr::null(),
+                            textures.len() as u32,
+                            textures.as_ptr(),
+                            layouts.as_ptr(),
+                        );
+                    }
+
+                    // The other device may wait on the semaphores before this context is flushed again.
+                    gl.flush();
+                })
+            };
+        }
+
+        Ok(index)
+    }
+}
+
+/// Returns the texture objects and `GL_EXT_semaphore` layouts of the textures.
+fn raw_textures(textures: &[SemaphoreTexture]) -> Result<(Vec<u32>, Vec<u32>), SemaphoreError> {
+    textures
+        .iter()
+        .map(|texture| {
+            let mut raw = None;
+
+            unsafe {
+                texture.texture.as_hal::<Gles, _>(|hal_texture| {
+                    // Renderbuffers cannot be used with semaphores.
+                    if let Some(wgpu_hal::gles::TextureInner::Texture { raw: texture, .. }) =
+                        hal_texture.map(|hal_texture| &hal_texture.inner)
+                    {
+                        raw = Some(texture.0.get());
+                    }
+                })
+            };
+
+            Ok((
+                raw.ok_or(SemaphoreError::InvalidTexture)?,
+                conv::map_texture_layout(texture.layout),
+            ))
+        })
+        .collect()
+}
+
+pub type GlGenSemaphoresEXT = unsafe extern "C" fn(
+    i32,      // n
+    *mut u32, // semaphores
+);
+
+pub type GlDeleteSemaphoresEXT = unsafe extern "C" fn(
+    i32,        // n
+    *const u32, // semaphores
+);
+
+pub type GlImportSemaphoreFdEXT = unsafe extern "C" fn(
+    u32, // semaphore
+    u32, // handleType
+    i32, // fd
+);
+
+pub type GlWaitSemaphoreEXT = unsafe extern "C" fn(
+    u32,        // semaphore
+    u32,        // numBufferBarriers
+    *const u32, // buffers
+    u32,        // numTextureBarriers
+    *const u32, // textures
+    *const u32, // srcLayouts
+);
+
+pub type GlSignalSemaphoreEXT = unsafe extern "C" fn(
+    u32,        // semaphore
+    u32,        // numBufferBarriers
+    *const u32, // buffers
+    u32,        // numTextureBarriers
+    *const u32, // textures
+    *const u32, // dstLayouts
+);
+
+pub type EglCreateSyncKHR = unsafe extern "C" fn(
+    *mut c_void, // dpy
+    u32,         // type
+    *const i32,  // attrib_list
+) -> *mut c_void;
+
+pub type EglDestroySyncKHR = unsafe extern "C" fn(
+    *mut c_void, // dpy
+    *mut c_void, // sync
+) -> c_uint;
+
+pub type EglWaitSyncKHR = unsafe extern "C" fn(
+    *mut c_void, // dpy
+    *mut c_void, // sync
+    i32,         // flags
+) -> i32;
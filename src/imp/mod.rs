@@ -1,20 +1,23 @@
+mod conv;
 pub mod egl;
 pub mod vulkan;
 
-use std::path::Path;
+use std::{path::Path, sync::Arc};
 
-use wgpu::{Adapter, DeviceDescriptor, RequestDeviceError};
+use wgpu::{
+    Adapter, BufferUsages, DeviceDescriptor, RequestDeviceError, TextureFormat, TextureUsages,
+};
 use wgpu_hal::{api::Gles, api::Vulkan};
 
 use crate::{
     adapter::{AdapterExt, DeviceUuids, DrmInfo},
-    ExternalMemoryDevice,
+    ExternalMemoryDevice, ExternalMemoryProperties, ExternalMemoryType,
 };
 
 #[derive(Debug)]
 pub enum DeviceInner {
     Vulkan(vulkan::Inner),
-    Egl,
+    Egl(Arc<egl::Inner>),
 }
 
 impl AdapterExt for Adapter {
@@ -63,7 +66,80 @@ impl AdapterExt for Adapter {
     }
 
     fn supports_dmabuf_external_memory(&self) -> bool {
-        false // TODO
+        #[cfg(vulkan)]
+        {
+            let is_vulkan = unsafe { self.as_hal::<Vulkan, _, bool>(|adapter| adapter.is_some()) };
+
+            if is_vulkan {
+                return unsafe {
+                    self.as_hal::<Vulkan, _, _>(|adapter| vulkan::supports_dmabuf(adapter.unwrap()))
+                };
+            }
+        }
+
+        // TODO: EGL_EXT_image_dma_buf_import
+        false
+    }
+
+    fn external_memory_capabilities(
+        &self,
+        handle_type: ExternalMemoryType,
+        format: TextureFormat,
+        usage: TextureUsages,
+    ) -> Option<ExternalMemoryProperties> {
+        #[cfg(vulkan)]
+        {
+            let is_vulkan = unsafe { self.as_hal::<Vulkan, _, bool>(|adapter| adapter.is_some()) };
+
+            if is_vulkan {
+                return unsafe {
+                    self.as_hal::<Vulkan, _, _>(|adapter| {
+                        vulkan::get_external_memory_capabilities(
+                            adapter,
+                            handle_type,
+                            format,
+                            usage,
+                        )
+                    })
+                };
+            }
+        }
+
+        #[cfg(egl)]
+        {
+            let is_gl = unsafe { self.as_hal::<Gles, _, bool>(|adapter| adapter.is_some()) };
+
+            if is_gl {
+                return unsafe {
+                    self.as_hal::<Gles, _, _>(|adapter| {
+                        egl::get_external_memory_capabilities(adapter, handle_type, format, usage)
+                    })
+                };
+            }
+        }
+
+        None
+    }
+
+    fn external_buffer_memory_capabilities(
+        &self,
+        handle_type: ExternalMemoryType,
+        usage: BufferUsages,
+    ) -> Option<ExternalMemoryProperties> {
+        #[cfg(vulkan)]
+        {
+            let is_vulkan = unsafe { self.as_hal::<Vulkan, _, bool>(|adapter| adapter.is_some()) };
+
+            if is_vulkan {
+                return unsafe {
+                    self.as_hal::<Vulkan, _, _>(|adapter| {
+                        vulkan::get_external_buffer_memory_capabilities(adapter, handle_type, usage)
+                    })
+                };
+            }
+        }
+
+        None
     }
 
     fn request_device_with_external_memory(
@@ -0,0 +1,137 @@
+//! Pipeline barriers submitted outside of wgpu.
+//!
+//! wgpu cannot record barriers which transfer ownership of an image to or from another queue family, so these
+//! barriers are submitted to the queue of the device directly.
+
+use ash::vk;
+use wgpu_hal::DeviceError;
+
+/// The subresource range of a color texture with a single mip level and array layer.
+pub const COLOR_SUBRESOURCE_RANGE: vk::ImageSubresourceRange = vk::ImageSubresourceRange {
+    aspect_mask: vk::ImageAspectFlags::COLOR,
+    base_mip_level: 0,
+    level_count: 1,
+    base_array_layer: 0,
+    layer_count: 1,
+};
+
+/// A command buffer used to submit barriers to the queue of a device.
+///
+/// Barriers are submitted directly to the queue, so they must not be submitted while another thread submits to
+/// the queue.
+pub struct BarrierQueue {
+    device: ash::Device,
+    queue: vk::Queue,
+    queue_family_index: u32,
+    command_pool: vk::CommandPool,
+    command_buffer: vk::CommandBuffer,
+    /// Signalled when the command buffer may be recorded again.
+    fence: vk::Fence,
+}
+
+impl BarrierQueue {
+    pub unsafe fn new(hal_device: &wgpu_hal::vulkan::Device) -> Result<Self, DeviceError> {
+        let device = hal_device.raw_device();
+        let queue_family_index = hal_device.queue_family_index();
+
+        let pool_info = vk::CommandPoolCreateInfo::builder()
+            .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
+            .queue_family_index(queue_family_index);
+        let command_pool = device.create_command_pool(&pool_info, None)?;
+
+        // Any objects created before a failure are destroyed when the queue is dropped.
+        let mut barrier_queue = Self {
+            device: device.clone(),
+            queue: device.get_device_queue(queue_family_index, hal_device.queue_index()),
+            queue_family_index,
+            command_pool,
+            command_buffer: vk::CommandBuffer::null(),
+            fence: vk::Fence::null(),
+        };
+
+        let allocate_info = vk::CommandBufferAllocateInfo::builder()
+            .command_pool(command_pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count(1);
+        barrier_queue.command_buffer = device.allocate_command_buffers(&allocate_info)?[0];
+
+        // The fence starts signalled, since the command buffer is not in use yet.
+        let fence_info = vk::FenceCreateInfo::builder().flags(vk::FenceCreateFlags::SIGNALED);
+        barrier_queue.fence = device.create_fence(&fence_info, None)?;
+
+        Ok(barrier_queue)
+    }
+
+    pub fn device(&self) -> &ash::Device {
+        &self.device
+    }
+
+    pub fn queue_family_index(&self) -> u32 {
+        self.queue_family_index
+    }
+
+    /// Submits the barriers after waiting on the semaphore and signals the semaphore once they complete.
+    pub unsafe fn submit(
+        &self,
+        barriers: &[vk::ImageMemoryBarrier],
+        wait: Option<vk::Semaphore>,
+        signal: Option<vk::Semaphore>,
+    ) -> Result<(), DeviceError> {
+        // The previous submission must be complete before the command buffer is recorded again.
+        self.wait()?;
+        self.device.reset_fences(&[self.fence])?;
+
+        self.device
+            .reset_command_buffer(self.command_buffer, vk::CommandBufferResetFlags::empty())?;
+        let begin_info = vk::CommandBufferBeginInfo::builder()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        self.device
+            .begin_command_buffer(self.command_buffer, &begin_info)?;
+        self.device.cmd_pipeline_barrier(
+            self.command_buffer,
+            vk::PipelineStageFlags::ALL_COMMANDS,
+            vk::PipelineStageFlags::ALL_COMMANDS,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            barriers,
+        );
+        self.device.end_command_buffer(self.command_buffer)?;
+
+        let wait_semaphores = wait.into_iter().collect::<Vec<_>>();
+        let wait_stages = vec![vk::PipelineStageFlags::ALL_COMMANDS; wait_semaphores.len()];
+        let signal_semaphores = signal.into_iter().collect::<Vec<_>>();
+        let command_buffers = [self.command_buffer];
+        let submit_info = vk::SubmitInfo::builder()
+            .wait_semaphores(&wait_semaphores)
+            .wait_dst_stage_mask(&wait_stages)
+            .command_buffers(&command_buffers)
+            .signal_semaphores(&signal_semaphores);
+
+        // A failed submission means the device was lost, so waiting on the fence will not block.
+        self.device
+            .queue_submit(self.queue, &[submit_info.build()], self.fence)?;
+
+        Ok(())
+    }
+
+    /// Waits for the last submission to complete.
+    pub fn wait(&self) -> Result<(), DeviceError> {
+        unsafe { self.device.wait_for_fences(&[self.fence], true, u64::MAX) }?;
+        Ok(())
+    }
+}
+
+impl Drop for BarrierQueue {
+    fn drop(&mut self) {
+        unsafe {
+            if self.fence != vk::Fence::null() {
+                let _ = self.wait();
+                self.device.destroy_fence(self.fence, None);
+            }
+
+            // Destroying the pool frees the command buffer.
+            self.device.destroy_command_pool(self.command_pool, None);
+        }
+    }
+}
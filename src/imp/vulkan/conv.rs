@@ -0,0 +1,318 @@
+use ash::vk;
+use drm_fourcc::DrmFourcc;
+use wgpu::{BufferUsages, TextureFormat, TextureUsages};
+
+use crate::{
+    semaphore::TextureLayout,
+    ycbcr::{ChromaLocation, YcbcrModel, YcbcrRange},
+    ExternalMemoryCapabilities, ExternalMemoryProperties, ExternalMemoryType,
+};
+
+/// Converts a wgpu texture format to the equivalent Vulkan format.
+///
+/// Returns [`None`] if the format has no sensible use with external memory.
+pub fn map_texture_format(format: TextureFormat) -> Option<vk::Format> {
+    Some(match format {
+        TextureFormat::R8Unorm => vk::Format::R8_UNORM,
+        TextureFormat::R8Snorm => vk::Format::R8_SNORM,
+        TextureFormat::R8Uint => vk::Format::R8_UINT,
+        TextureFormat::R8Sint => vk::Format::R8_SINT,
+        TextureFormat::R16Uint => vk::Format::R16_UINT,
+        TextureFormat::R16Sint => vk::Format::R16_SINT,
+        TextureFormat::R16Unorm => vk::Format::R16_UNORM,
+        TextureFormat::R16Snorm => vk::Format::R16_SNORM,
+        TextureFormat::R16Float => vk::Format::R16_SFLOAT,
+        TextureFormat::Rg8Unorm => vk::Format::R8G8_UNORM,
+        TextureFormat::Rg8Snorm => vk::Format::R8G8_SNORM,
+        TextureFormat::Rg8Uint => vk::Format::R8G8_UINT,
+        TextureFormat::Rg8Sint => vk::Format::R8G8_SINT,
+        TextureFormat::R32Uint => vk::Format::R32_UINT,
+        TextureFormat::R32Sint => vk::Format::R32_SINT,
+        TextureFormat::R32Float => vk::Format::R32_SFLOAT,
+        TextureFormat::Rg16Uint => vk::Format::R16G16_UINT,
+        TextureFormat::Rg16Sint => vk::Format::R16G16_SINT,
+        TextureFormat::Rg16Unorm => vk::Format::R16G16_UNORM,
+        TextureFormat::Rg16Snorm => vk::Format::R16G16_SNORM,
+        TextureFormat::Rg16Float => vk::Format::R16G16_SFLOAT,
+        TextureFormat::Rgba8Unorm => vk::Format::R8G8B8A8_UNORM,
+        TextureFormat::Rgba8UnormSrgb => vk::Format::R8G8B8A8_SRGB,
+        TextureFormat::Rgba8Snorm => vk::Format::R8G8B8A8_SNORM,
+        TextureFormat::Rgba8Uint => vk::Format::R8G8B8A8_UINT,
+        TextureFormat::Rgba8Sint => vk::Format::R8G8B8A8_SINT,
+        TextureFormat::Bgra8Unorm => vk::Format::B8G8R8A8_UNORM,
+        TextureFormat::Bgra8UnormSrgb => vk::Format::B8G8R8A8_SRGB,
+        TextureFormat::Rgb10a2Unorm => vk::Format::A2B10G10R10_UNORM_PACK32,
+        TextureFormat::Rg11b10Float => vk::Format::B10G11R11_UFLOAT_PACK32,
+        TextureFormat::Rg32Uint => vk::Format::R32G32_UINT,
+        TextureFormat::Rg32Sint => vk::Format::R32G32_SINT,
+        TextureFormat::Rg32Float => vk::Format::R32G32_SFLOAT,
+        TextureFormat::Rgba16Uint => vk::Format::R16G16B16A16_UINT,
+        TextureFormat::Rgba16Sint => vk::Format::R16G16B16A16_SINT,
+        TextureFormat::Rgba16Unorm => vk::Format::R16G16B16A16_UNORM,
+        TextureFormat::Rgba16Snorm => vk::Format::R16G16B16A16_SNORM,
+        TextureFormat::Rgba16Float => vk::Format::R16G16B16A16_SFLOAT,
+        TextureFormat::Rgba32Uint => vk::Format::R32G32B32A32_UINT,
+        TextureFormat::Rgba32Sint => vk::Format::R32G32B32A32_SINT,
+        TextureFormat::Rgba32Float => vk::Format::R32G32B32A32_SFLOAT,
+
+        // Depth, stencil and compressed formats are not shared with other processes or apis.
+        _ => return None,
+    })
+}
+
+pub fn map_texture_usage(usage: TextureUsages) -> vk::ImageUsageFlags {
+    let mut flags = vk::ImageUsageFlags::empty();
+
+    if usage.contains(TextureUsages::COPY_SRC) {
+        flags |= vk::ImageUsageFlags::TRANSFER_SRC;
+    }
+
+    if usage.contains(TextureUsages::COPY_DST) {
+        flags |= vk::ImageUsageFlags::TRANSFER_DST;
+    }
+
+    if usage.contains(TextureUsages::TEXTURE_BINDING) {
+        flags |= vk::ImageUsageFlags::SAMPLED;
+    }
+
+    if usage.contains(TextureUsages::STORAGE_BINDING) {
+        flags |= vk::ImageUsageFlags::STORAGE;
+    }
+
+    if usage.contains(TextureUsages::RENDER_ATTACHMENT) {
+        flags |= vk::ImageUsageFlags::COLOR_ATTACHMENT;
+    }
+
+    flags
+}
+
+pub fn map_buffer_usage(usage: BufferUsages) -> vk::BufferUsageFlags {
+    let mut flags = vk::BufferUsageFlags::empty();
+
+    if usage.intersects(BufferUsages::COPY_SRC | BufferUsages::MAP_WRITE) {
+        flags |= vk::BufferUsageFlags::TRANSFER_SRC;
+    }
+
+    if usage.intersects(BufferUsages::COPY_DST | BufferUsages::MAP_READ) {
+        flags |= vk::BufferUsageFlags::TRANSFER_DST;
+    }
+
+    if usage.contains(BufferUsages::INDEX) {
+        flags |= vk::BufferUsageFlags::INDEX_BUFFER;
+    }
+
+    if usage.contains(BufferUsages::VERTEX) {
+        flags |= vk::BufferUsageFlags::VERTEX_BUFFER;
+    }
+
+    if usage.contains(BufferUsages::UNIFORM) {
+        flags |= vk::BufferUsageFlags::UNIFORM_BUFFER;
+    }
+
+    if usage.contains(BufferUsages::STORAGE) {
+        flags |= vk::BufferUsageFlags::STORAGE_BUFFER;
+    }
+
+    if usage.contains(BufferUsages::INDIRECT) {
+        flags |= vk::BufferUsageFlags::INDIRECT_BUFFER;
+    }
+
+    flags
+}
+
+pub fn map_external_memory_type(ty: ExternalMemoryType) -> vk::ExternalMemoryHandleTypeFlags {
+    match ty {
+        ExternalMemoryType::Dmabuf => vk::ExternalMemoryHandleTypeFlags::DMA_BUF_EXT,
+        ExternalMemoryType::OpaqueFd => vk::ExternalMemoryHandleTypeFlags::OPAQUE_FD,
+        ExternalMemoryType::HostAllocation => {
+            vk::ExternalMemoryHandleTypeFlags::HOST_ALLOCATION_EXT
+        }
+    }
+}
+
+/// Converts the external memory properties reported by Vulkan.
+///
+/// Returns [`None`] if the handle type can be neither imported nor exported.
+pub fn map_external_memory_properties(
+    properties: vk::ExternalMemoryProperties,
+) -> Option<ExternalMemoryProperties> {
+    let features = properties.external_memory_features;
+    let mut capabilities = ExternalMemoryCapabilities::empty();
+
+    if features.contains(vk::ExternalMemoryFeatureFlags::IMPORTABLE) {
+        capabilities |= ExternalMemoryCapabilities::IMPORT;
+    }
+
+    if features.contains(vk::ExternalMemoryFeatureFlags::EXPORTABLE) {
+        capabilities |= ExternalMemoryCapabilities::EXPORT;
+    }
+
+    if capabilities.is_empty() {
+        return None;
+    }
+
+    let dedicated_only = features.contains(vk::ExternalMemoryFeatureFlags::DEDICATED_ONLY);
+
+    Some(ExternalMemoryProperties {
+        capabilities,
+        dedicated_only,
+        prefers_dedicated: dedicated_only,
+    })
+}
+
+/// Fourcc codes which may be imported and exported and the Vulkan format with the same memory layout.
+///
+/// Drm fourcc codes describe the memory layout in little endian, while Vulkan formats describe the memory
+/// layout in byte order (except for the packed formats). This means the components are reversed.
+pub const DRM_FORMATS: &[(DrmFourcc, vk::Format)] = &[
+    (DrmFourcc::Argb8888, vk::Format::B8G8R8A8_UNORM),
+    (DrmFourcc::Xrgb8888, vk::Format::B8G8R8A8_UNORM),
+    (DrmFourcc::Abgr8888, vk::Format::R8G8B8A8_UNORM),
+    (DrmFourcc::Xbgr8888, vk::Format::R8G8B8A8_UNORM),
+    (DrmFourcc::Argb2101010, vk::Format::A2R10G10B10_UNORM_PACK32),
+    (DrmFourcc::Xrgb2101010, vk::Format::A2R10G10B10_UNORM_PACK32),
+    (DrmFourcc::Abgr2101010, vk::Format::A2B10G10R10_UNORM_PACK32),
+    (DrmFourcc::Xbgr2101010, vk::Format::A2B10G10R10_UNORM_PACK32),
+    (DrmFourcc::Abgr16161616f, vk::Format::R16G16B16A16_SFLOAT),
+    (DrmFourcc::Xbgr16161616f, vk::Format::R16G16B16A16_SFLOAT),
+    (DrmFourcc::Rgb565, vk::Format::R5G6B5_UNORM_PACK16),
+    (DrmFourcc::R8, vk::Format::R8_UNORM),
+    (DrmFourcc::Gr88, vk::Format::R8G8_UNORM),
+    (DrmFourcc::R16, vk::Format::R16_UNORM),
+    (DrmFourcc::Gr1616, vk::Format::R16G16_UNORM),
+    // Multi-planar and subsampled YCbCr formats. These can only be sampled using a YCbCr conversion.
+    (DrmFourcc::Nv12, vk::Format::G8_B8R8_2PLANE_420_UNORM),
+    (DrmFourcc::Nv16, vk::Format::G8_B8R8_2PLANE_422_UNORM),
+    (
+        DrmFourcc::P010,
+        vk::Format::G10X6_B10X6R10X6_2PLANE_420_UNORM_3PACK16,
+    ),
+    (DrmFourcc::Yuv420, vk::Format::G8_B8_R8_3PLANE_420_UNORM),
+    (DrmFourcc::Yuyv, vk::Format::G8B8G8R8_422_UNORM),
+    (DrmFourcc::Uyvy, vk::Format::B8G8R8G8_422_UNORM),
+];
+
+/// Whether the format must be sampled using a YCbCr conversion.
+pub fn is_ycbcr_format(format: vk::Format) -> bool {
+    matches!(
+        format,
+        vk::Format::G8_B8R8_2PLANE_420_UNORM
+            | vk::Format::G8_B8R8_2PLANE_422_UNORM
+            | vk::Format::G10X6_B10X6R10X6_2PLANE_420_UNORM_3PACK16
+            | vk::Format::G8_B8_R8_3PLANE_420_UNORM
+            | vk::Format::G8B8G8R8_422_UNORM
+            | vk::Format::B8G8R8G8_422_UNORM
+    )
+}
+
+/// Returns the Vulkan format with the same memory layout as the fourcc code.
+pub fn map_fourcc(fourcc: DrmFourcc) -> Option<vk::Format> {
+    DRM_FORMATS
+        .iter()
+        .find(|(code, _)| *code == fourcc)
+        .map(|&(_, format)| format)
+}
+
+/// Whether two formats have the same memory layout.
+///
+/// An sRGB format has the same memory layout as the equivalent UNORM format.
+pub fn is_layout_compatible(a: vk::Format, b: vk::Format) -> bool {
+    fn to_unorm(format: vk::Format) -> vk::Format {
+        match format {
+            vk::Format::B8G8R8A8_SRGB => vk::Format::B8G8R8A8_UNORM,
+            vk::Format::R8G8B8A8_SRGB => vk::Format::R8G8B8A8_UNORM,
+            format => format,
+        }
+    }
+
+    to_unorm(a) == to_unorm(b)
+}
+
+/// Returns the format features an image must support to be used with the specified usages.
+pub fn map_texture_usage_to_features(usage: TextureUsages) -> vk::FormatFeatureFlags {
+    let mut features = vk::FormatFeatureFlags::empty();
+
+    if usage.contains(TextureUsages::COPY_SRC) {
+        features |= vk::FormatFeatureFlags::TRANSFER_SRC;
+    }
+
+    if usage.contains(TextureUsages::COPY_DST) {
+        features |= vk::FormatFeatureFlags::TRANSFER_DST;
+    }
+
+    if usage.contains(TextureUsages::TEXTURE_BINDING) {
+        features |= vk::FormatFeatureFlags::SAMPLED_IMAGE;
+    }
+
+    if usage.contains(TextureUsages::STORAGE_BINDING) {
+        features |= vk::FormatFeatureFlags::STORAGE_IMAGE;
+    }
+
+    if usage.contains(TextureUsages::RENDER_ATTACHMENT) {
+        features |= vk::FormatFeatureFlags::COLOR_ATTACHMENT;
+    }
+
+    features
+}
+
+pub fn map_ycbcr_model(model: YcbcrModel) -> vk::SamplerYcbcrModelConversion {
+    match model {
+        YcbcrModel::RgbIdentity => vk::SamplerYcbcrModelConversion::RGB_IDENTITY,
+        YcbcrModel::YcbcrIdentity => vk::SamplerYcbcrModelConversion::YCBCR_IDENTITY,
+        YcbcrModel::Bt601 => vk::SamplerYcbcrModelConversion::YCBCR_601,
+        YcbcrModel::Bt709 => vk::SamplerYcbcrModelConversion::YCBCR_709,
+        YcbcrModel::Bt2020 => vk::SamplerYcbcrModelConversion::YCBCR_2020,
+    }
+}
+
+pub fn map_ycbcr_range(range: YcbcrRange) -> vk::SamplerYcbcrRange {
+    match range {
+        YcbcrRange::Full => vk::SamplerYcbcrRange::ITU_FULL,
+        YcbcrRange::Narrow => vk::SamplerYcbcrRange::ITU_NARROW,
+    }
+}
+
+pub fn map_chroma_location(location: ChromaLocation) -> vk::ChromaLocation {
+    match location {
+        ChromaLocation::CositedEven => vk::ChromaLocation::COSITED_EVEN,
+        ChromaLocation::Midpoint => vk::ChromaLocation::MIDPOINT,
+    }
+}
+
+pub fn map_filter_mode(filter: wgpu::FilterMode) -> vk::Filter {
+    match filter {
+        wgpu::FilterMode::Nearest => vk::Filter::NEAREST,
+        wgpu::FilterMode::Linear => vk::Filter::LINEAR,
+    }
+}
+
+pub fn map_shader_stages(stages: wgpu::ShaderStages) -> vk::ShaderStageFlags {
+    let mut flags = vk::ShaderStageFlags::empty();
+
+    if stages.contains(wgpu::ShaderStages::VERTEX) {
+        flags |= vk::ShaderStageFlags::VERTEX;
+    }
+
+    if stages.contains(wgpu::ShaderStages::FRAGMENT) {
+        flags |= vk::ShaderStageFlags::FRAGMENT;
+    }
+
+    if stages.contains(wgpu::ShaderStages::COMPUTE) {
+        flags |= vk::ShaderStageFlags::COMPUTE;
+    }
+
+    flags
+}
+
+pub fn map_texture_layout(layout: TextureLayout) -> vk::ImageLayout {
+    match layout {
+        TextureLayout::Undefined => vk::ImageLayout::UNDEFINED,
+        TextureLayout::General => vk::ImageLayout::GENERAL,
+        TextureLayout::ColorAttachment => vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        TextureLayout::DepthStencilAttachment => vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+        TextureLayout::DepthStencilReadOnly => vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+        TextureLayout::ShaderReadOnly => vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        TextureLayout::TransferSrc => vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        TextureLayout::TransferDst => vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+    }
+}
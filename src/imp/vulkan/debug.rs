@@ -0,0 +1,58 @@
+//! `VK_EXT_debug_utils` object names.
+
+use std::ffi::CString;
+
+use ash::{
+    extensions::ext,
+    vk::{self, Handle},
+};
+use drm_fourcc::DrmFormat;
+use wgpu_hal::vulkan::InstanceShared;
+
+/// Names Vulkan objects using `VK_EXT_debug_utils`.
+///
+/// wgpu-hal already creates a messenger which routes debug messages to the `log` crate when the instance is
+/// created with `VK_EXT_debug_utils`, so the names show up in the messages it logs.
+pub struct DebugUtils {
+    extension: ext::DebugUtils,
+}
+
+impl DebugUtils {
+    /// Loads the extension if `VK_EXT_debug_utils` is enabled on the instance.
+    pub fn new(instance: &InstanceShared) -> Option<Self> {
+        if !instance.extensions().contains(&ext::DebugUtils::name()) {
+            return None;
+        }
+
+        Some(Self {
+            extension: ext::DebugUtils::new(instance.entry(), instance.raw_instance()),
+        })
+    }
+
+    /// Names a Vulkan object, so the name is included in messages about the object.
+    pub unsafe fn set_object_name<H: Handle>(&self, device: &ash::Device, object: H, name: &str) {
+        let name = match CString::new(name) {
+            Ok(name) => name,
+            // Names containing nul bytes are only used for debugging, so they are not worth failing over.
+            Err(_) => return,
+        };
+
+        let name_info = vk::DebugUtilsObjectNameInfoEXT::builder()
+            .object_type(H::TYPE)
+            .object_handle(object.as_raw())
+            .object_name(&name);
+
+        // Naming an object is best effort.
+        let _ = self
+            .extension
+            .debug_utils_set_object_name(device.handle(), &name_info);
+    }
+}
+
+/// The name of an object backing a dmabuf, such as `"label (XR24, Linear)"`.
+pub fn dmabuf_object_name(label: Option<&str>, format: DrmFormat) -> String {
+    match label {
+        Some(label) => format!("{} ({}, {:?})", label, format.code, format.modifier),
+        None => format!("dmabuf ({}, {:?})", format.code, format.modifier),
+    }
+}
@@ -1,24 +1,934 @@
-use std::ffi::CStr;
+use std::{
+    ffi::CStr,
+    io,
+    os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd},
+};
 
 use ash::{
     extensions::khr::ExternalMemoryFd,
     vk::{
-        ExtExternalMemoryDmaBufFn, ExtImageDrmFormatModifierFn, KhrBindMemory2Fn,
+        self, ExtExternalMemoryDmaBufFn, ExtImageDrmFormatModifierFn, KhrBindMemory2Fn,
         KhrGetMemoryRequirements2Fn, KhrImageFormatListFn, KhrMaintenance1Fn,
         KhrSamplerYcbcrConversionFn,
     },
 };
+use drm_fourcc::{DrmFormat, DrmFourcc, DrmModifier};
+use nix::sys::stat::fstat;
+use wgpu::{TextureDescriptor, TextureDimension};
+use wgpu_hal::{api::Vulkan, vulkan::InstanceShared, DeviceError};
+
+use crate::{
+    dmabuf::{
+        Dmabuf, DmabufImportDescriptor, DmabufPlane, ExportError, ExportedTexture, ImportError,
+    },
+    validation::{self, DmabufConstraints},
+    ycbcr::{ChromaLocation, YcbcrImportDescriptor, YcbcrTexture},
+};
 
-#[allow(dead_code)]
-pub const REQUIRED_DEVICE_EXTENSIONS: &[&CStr] = &[
+use super::{
+    conv, debug, find_memory_type_index, get_physical_device_image_format_properties2,
+    promoted::Extension, Inner,
+};
+
+/// Device extensions required to import and export dmabufs.
+pub const REQUIRED_DEVICE_EXTENSIONS: &[Extension] = &[
     /* VK_EXT_external_memory_dma_buf */
-    ExtExternalMemoryDmaBufFn::name(),
-    ExternalMemoryFd::name(),
+    (ExtExternalMemoryDmaBufFn::name(), None),
+    (ExternalMemoryFd::name(), None),
     /* VK_EXT_image_drm_format_modifier */
-    ExtImageDrmFormatModifierFn::name(),
-    KhrBindMemory2Fn::name(),            // or 1.1
-    KhrImageFormatListFn::name(),        // or 1.2
-    KhrSamplerYcbcrConversionFn::name(), // or 1.1
-    KhrMaintenance1Fn::name(),           // or 1.1
-    KhrGetMemoryRequirements2Fn::name(), // or 1.1
+    (ExtImageDrmFormatModifierFn::name(), None),
+    (KhrBindMemory2Fn::name(), Some(vk::API_VERSION_1_1)),
+    (KhrImageFormatListFn::name(), Some(vk::API_VERSION_1_2)),
+    (
+        KhrSamplerYcbcrConversionFn::name(),
+        Some(vk::API_VERSION_1_1),
+    ),
+    (KhrMaintenance1Fn::name(), Some(vk::API_VERSION_1_1)),
+    (
+        KhrGetMemoryRequirements2Fn::name(),
+        Some(vk::API_VERSION_1_1),
+    ),
+];
+
+/// The image aspect used to query the layout of each memory plane of an image.
+const MEMORY_PLANE_ASPECTS: [vk::ImageAspectFlags; 4] = [
+    vk::ImageAspectFlags::MEMORY_PLANE_0_EXT,
+    vk::ImageAspectFlags::MEMORY_PLANE_1_EXT,
+    vk::ImageAspectFlags::MEMORY_PLANE_2_EXT,
+    vk::ImageAspectFlags::MEMORY_PLANE_3_EXT,
 ];
+
+impl Inner {
+    pub fn dmabuf_formats(&self) -> Vec<DrmFormat> {
+        let mut formats = self
+            .supported_drm_formats
+            .keys()
+            .copied()
+            .collect::<Vec<_>>();
+
+        // Sort the formats so the order does not change between calls.
+        formats.sort_by_key(|format| (format.code as u32, u64::from(format.modifier)));
+        formats
+    }
+
+    pub fn dmabuf_formats_with_usage(&self, usage: wgpu::TextureUsages) -> Vec<DrmFormat> {
+        let required_features = conv::map_texture_usage_to_features(usage);
+        let mut formats = self
+            .supported_drm_formats
+            .iter()
+            .filter(|(_, properties)| {
+                properties
+                    .drm_format_modifier_tiling_features
+                    .contains(required_features)
+            })
+            .map(|(format, _)| *format)
+            .collect::<Vec<_>>();
+
+        formats.sort_by_key(|format| (format.code as u32, u64::from(format.modifier)));
+        formats
+    }
+
+    pub fn dmabuf_constraints(&self, format: DrmFormat) -> Option<DmabufConstraints> {
+        self.supported_drm_formats.get(&format).map(constraints)
+    }
+
+    pub fn create_exportable_texture(
+        &self,
+        device: &wgpu::Device,
+        desc: &TextureDescriptor,
+        fourcc: DrmFourcc,
+    ) -> Result<ExportedTexture, ExportError> {
+        let modifiers = self
+            .supported_drm_formats
+            .keys()
+            .filter(|format| format.code == fourcc)
+            .map(|format| format.modifier)
+            .collect::<Vec<_>>();
+
+        self.create_texture_with_modifiers(device, desc, fourcc, &modifiers)
+    }
+
+    /// Creates an exportable texture using one of the specified modifiers.
+    pub fn create_texture_with_modifiers(
+        &self,
+        device: &wgpu::Device,
+        desc: &TextureDescriptor,
+        fourcc: DrmFourcc,
+        modifiers: &[DrmModifier],
+    ) -> Result<ExportedTexture, ExportError> {
+        if !self.supports_dmabuf {
+            return Err(ExportError::Unsupported);
+        }
+
+        if desc.dimension != TextureDimension::D2
+            || desc.size.depth_or_array_layers != 1
+            || desc.mip_level_count != 1
+            || desc.sample_count != 1
+        {
+            return Err(ExportError::InvalidDescriptor);
+        }
+
+        let fourcc_format =
+            conv::map_fourcc(fourcc).ok_or(ExportError::UnsupportedFormat(fourcc))?;
+        let format = conv::map_texture_format(desc.format)
+            .filter(|&format| conv::is_layout_compatible(format, fourcc_format))
+            .ok_or(ExportError::IncompatibleFormat {
+                format: desc.format,
+                fourcc,
+            })?;
+
+        // Only use the modifiers which support the usages of the texture.
+        let required_features = conv::map_texture_usage_to_features(desc.usage);
+        let modifiers = modifiers
+            .iter()
+            .filter(|&&modifier| {
+                self.supported_drm_formats
+                    .get(&DrmFormat {
+                        code: fourcc,
+                        modifier,
+                    })
+                    .map(|properties| {
+                        properties
+                            .drm_format_modifier_tiling_features
+                            .contains(required_features)
+                    })
+                    .unwrap_or(false)
+            })
+            .map(|&modifier| u64::from(modifier))
+            .collect::<Vec<_>>();
+
+        if modifiers.is_empty() {
+            return Err(ExportError::NoSupportedModifier(fourcc));
+        }
+
+        unsafe {
+            device.as_hal::<Vulkan, _, _>(|hal_device| {
+                let hal_device = hal_device.unwrap();
+                let raw_device = hal_device.raw_device();
+
+                let image = self.create_exportable_image(raw_device, desc, format, &modifiers)?;
+
+                let memory = match self.allocate_exportable_memory(raw_device, image) {
+                    Ok(memory) => memory,
+                    Err(err) => {
+                        raw_device.destroy_image(image, None);
+                        return Err(err);
+                    }
+                };
+
+                let dmabuf = match self.export_dmabuf(raw_device, image, memory, desc, fourcc) {
+                    Ok(dmabuf) => dmabuf,
+                    Err(err) => {
+                        raw_device.destroy_image(image, None);
+                        raw_device.free_memory(memory, None);
+                        return Err(err);
+                    }
+                };
+
+                self.name_dmabuf_objects(raw_device, desc.label, dmabuf.format, image, &[memory]);
+                self.track_external_image(image, desc.usage, true);
+
+                Ok(ExportedTexture {
+                    texture: self.texture_from_raw(device, raw_device, image, vec![memory], desc),
+                    dmabuf,
+                })
+            })
+        }
+    }
+
+    unsafe fn create_exportable_image(
+        &self,
+        device: &ash::Device,
+        desc: &TextureDescriptor,
+        format: vk::Format,
+        modifiers: &[u64],
+    ) -> Result<vk::Image, ExportError> {
+        let mut modifier_list =
+            vk::ImageDrmFormatModifierListCreateInfoEXT::builder().drm_format_modifiers(modifiers);
+        let mut external_memory_image = vk::ExternalMemoryImageCreateInfo::builder()
+            .handle_types(vk::ExternalMemoryHandleTypeFlags::DMA_BUF_EXT);
+
+        let create_info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(format)
+            .extent(vk::Extent3D {
+                width: desc.size.width,
+                height: desc.size.height,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::DRM_FORMAT_MODIFIER_EXT)
+            .usage(conv::map_texture_usage(desc.usage))
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .push_next(&mut external_memory_image)
+            .push_next(&mut modifier_list);
+
+        Ok(device
+            .create_image(&create_info, None)
+            .map_err(DeviceError::from)?)
+    }
+
+    unsafe fn allocate_exportable_memory(
+        &self,
+        device: &ash::Device,
+        image: vk::Image,
+    ) -> Result<vk::DeviceMemory, ExportError> {
+        let (requirements, dedicated) = self.image_memory_requirements(image);
+        let memory_type_index = find_memory_type_index(
+            &self.memory_properties,
+            requirements.memory_type_bits,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )
+        .ok_or(DeviceError::OutOfMemory)?;
+
+        let mut export_info = vk::ExportMemoryAllocateInfo::builder()
+            .handle_types(vk::ExternalMemoryHandleTypeFlags::DMA_BUF_EXT);
+        let mut dedicated_info = vk::MemoryDedicatedAllocateInfo::builder().image(image);
+        let mut allocate_info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(requirements.size)
+            .memory_type_index(memory_type_index)
+            .push_next(&mut export_info);
+
+        if dedicated {
+            allocate_info = allocate_info.push_next(&mut dedicated_info);
+        }
+
+        let memory = device
+            .allocate_memory(&allocate_info, None)
+            .map_err(DeviceError::from)?;
+
+        if let Err(err) = device.bind_image_memory(image, memory, 0) {
+            device.free_memory(memory, None);
+            return Err(DeviceError::from(err).into());
+        }
+
+        Ok(memory)
+    }
+
+    unsafe fn export_dmabuf(
+        &self,
+        device: &ash::Device,
+        image: vk::Image,
+        memory: vk::DeviceMemory,
+        desc: &TextureDescriptor,
+        fourcc: DrmFourcc,
+    ) -> Result<Dmabuf, ExportError> {
+        let get_fd_info = vk::MemoryGetFdInfoKHR::builder()
+            .memory(memory)
+            .handle_type(vk::ExternalMemoryHandleTypeFlags::DMA_BUF_EXT);
+        let fd = self
+            .external_memory_fd
+            .get_memory_fd(&get_fd_info)
+            .map_err(DeviceError::from)?;
+        // SAFETY: vkGetMemoryFdKHR creates a new file descriptor which we now own.
+        let fd = OwnedFd::from_raw_fd(fd);
+
+        // The driver chooses the modifier from the list given at image creation.
+        let mut modifier_properties = vk::ImageDrmFormatModifierPropertiesEXT::default();
+        self.image_drm_format_modifier
+            .get_image_drm_format_modifier_properties(image, &mut modifier_properties)
+            .map_err(DeviceError::from)?;
+
+        let format = DrmFormat {
+            code: fourcc,
+            modifier: DrmModifier::from(modifier_properties.drm_format_modifier),
+        };
+        let plane_count = self
+            .supported_drm_formats
+            .get(&format)
+            .map(|properties| properties.drm_format_modifier_plane_count)
+            .unwrap_or(1);
+
+        let mut planes = Vec::with_capacity(plane_count as usize);
+
+        for &aspect_mask in &MEMORY_PLANE_ASPECTS[..plane_count as usize] {
+            let layout = device.get_image_subresource_layout(
+                image,
+                vk::ImageSubresource {
+                    aspect_mask,
+                    mip_level: 0,
+                    array_layer: 0,
+                },
+            );
+
+            planes.push(DmabufPlane {
+                fd: fd.try_clone()?,
+                offset: layout.offset as u32,
+                stride: layout.row_pitch as u32,
+            });
+        }
+
+        Ok(Dmabuf {
+            format,
+            width: desc.size.width,
+            height: desc.size.height,
+            planes,
+        })
+    }
+}
+
+impl Inner {
+    pub fn import_dmabuf(
+        &self,
+        device: &wgpu::Device,
+        dmabuf: &Dmabuf,
+        desc: &DmabufImportDescriptor,
+    ) -> Result<wgpu::Texture, ImportError> {
+        let fourcc = dmabuf.format.code;
+        let format = conv::map_fourcc(fourcc)
+            .zip(conv::map_texture_format(desc.format))
+            .filter(|&(fourcc_format, format)| conv::is_layout_compatible(format, fourcc_format))
+            .map(|(_, format)| format)
+            .ok_or(ImportError::IncompatibleFormat {
+                format: desc.format,
+                fourcc,
+            })?;
+
+        let disjoint = self.check_importable(dmabuf, desc.usage)?;
+
+        let tex_desc = wgpu::TextureDescriptor {
+            label: desc.label,
+            size: wgpu::Extent3d {
+                width: dmabuf.width,
+                height: dmabuf.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: desc.format,
+            usage: desc.usage,
+        };
+
+        unsafe {
+            device.as_hal::<Vulkan, _, _>(|hal_device| {
+                let hal_device = hal_device.unwrap();
+                let raw_device = hal_device.raw_device();
+
+                self.check_image_format(
+                    hal_device.shared_instance(),
+                    hal_device.raw_physical_device(),
+                    dmabuf,
+                    format,
+                    desc.usage,
+                    disjoint,
+                )?;
+
+                let (image, memory) = self.import_image(
+                    raw_device,
+                    dmabuf,
+                    format,
+                    conv::map_texture_usage(desc.usage),
+                    disjoint,
+                )?;
+                self.name_dmabuf_objects(raw_device, desc.label, dmabuf.format, image, &memory);
+                self.track_external_image(image, desc.usage, false);
+
+                Ok(self.texture_from_raw(device, raw_device, image, memory, &tex_desc))
+            })
+        }
+    }
+
+    pub fn import_ycbcr_dmabuf<'a>(
+        &'a self,
+        device: &wgpu::Device,
+        dmabuf: &Dmabuf,
+        desc: &YcbcrImportDescriptor,
+    ) -> Result<YcbcrTexture<'a>, ImportError> {
+        let format = conv::map_fourcc(dmabuf.format.code)
+            .filter(|&format| conv::is_ycbcr_format(format))
+            .ok_or(ImportError::UnsupportedFormat(dmabuf.format))?;
+
+        if !self.supports_ycbcr_conversion {
+            return Err(ImportError::UnsupportedYcbcrConversion(dmabuf.format));
+        }
+
+        let disjoint = self.check_importable(dmabuf, wgpu::TextureUsages::TEXTURE_BINDING)?;
+
+        // The format must support the requested chroma reconstruction.
+        let features =
+            self.supported_drm_formats[&dmabuf.format].drm_format_modifier_tiling_features;
+        let mut required_features = vk::FormatFeatureFlags::SAMPLED_IMAGE;
+
+        if desc.chroma_filter == wgpu::FilterMode::Linear {
+            required_features |=
+                vk::FormatFeatureFlags::SAMPLED_IMAGE_YCBCR_CONVERSION_LINEAR_FILTER;
+        }
+
+        for location in [desc.x_chroma_offset, desc.y_chroma_offset] {
+            required_features |= match location {
+                ChromaLocation::CositedEven => vk::FormatFeatureFlags::COSITED_CHROMA_SAMPLES,
+                ChromaLocation::Midpoint => vk::FormatFeatureFlags::MIDPOINT_CHROMA_SAMPLES,
+            };
+        }
+
+        if !features.contains(required_features) {
+            return Err(ImportError::UnsupportedYcbcrConversion(dmabuf.format));
+        }
+
+        unsafe {
+            device.as_hal::<Vulkan, _, _>(|hal_device| {
+                let hal_device = hal_device.unwrap();
+                let raw_device = hal_device.raw_device();
+
+                self.check_image_format(
+                    hal_device.shared_instance(),
+                    hal_device.raw_physical_device(),
+                    dmabuf,
+                    format,
+                    wgpu::TextureUsages::TEXTURE_BINDING,
+                    disjoint,
+                )?;
+
+                let (image, memory) = self.import_image(
+                    raw_device,
+                    dmabuf,
+                    format,
+                    vk::ImageUsageFlags::SAMPLED,
+                    disjoint,
+                )?;
+
+                let name = debug::dmabuf_object_name(desc.label, dmabuf.format);
+                self.name_dmabuf_objects(raw_device, desc.label, dmabuf.format, image, &memory);
+
+                // The texture destroys every object when dropped, so create it before anything else can fail.
+                let mut texture = YcbcrTexture {
+                    device: raw_device.clone(),
+                    promoted_fns: self.promoted_fns.clone(),
+                    format: dmabuf.format,
+                    width: dmabuf.width,
+                    height: dmabuf.height,
+                    image,
+                    memory,
+                    conversion: vk::SamplerYcbcrConversion::null(),
+                    sampler: vk::Sampler::null(),
+                    view: vk::ImageView::null(),
+                    inner: self,
+                };
+                self.track_external_image(image, wgpu::TextureUsages::TEXTURE_BINDING, false);
+
+                let conversion_create_info = vk::SamplerYcbcrConversionCreateInfo::builder()
+                    .format(format)
+                    .ycbcr_model(conv::map_ycbcr_model(desc.model))
+                    .ycbcr_range(conv::map_ycbcr_range(desc.range))
+                    .components(vk::ComponentMapping::default())
+                    .x_chroma_offset(conv::map_chroma_location(desc.x_chroma_offset))
+                    .y_chroma_offset(conv::map_chroma_location(desc.y_chroma_offset))
+                    .chroma_filter(conv::map_filter_mode(desc.chroma_filter))
+                    .force_explicit_reconstruction(false);
+                texture.conversion = self
+                    .promoted_fns
+                    .create_sampler_ycbcr_conversion(&conversion_create_info)
+                    .map_err(DeviceError::from)?;
+                self.set_object_name(
+                    raw_device,
+                    texture.conversion,
+                    &format!("{} conversion", name),
+                );
+
+                // The sampler and the image view must both use the conversion.
+                let mut conversion_info =
+                    vk::SamplerYcbcrConversionInfo::builder().conversion(texture.conversion);
+
+                let filter = conv::map_filter_mode(desc.chroma_filter);
+                let sampler_create_info = vk::SamplerCreateInfo::builder()
+                    .mag_filter(filter)
+                    .min_filter(filter)
+                    .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+                    .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                    .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                    .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                    .unnormalized_coordinates(false)
+                    .push_next(&mut conversion_info);
+                texture.sampler = raw_device
+                    .create_sampler(&sampler_create_info, None)
+                    .map_err(DeviceError::from)?;
+                self.set_object_name(raw_device, texture.sampler, &format!("{} sampler", name));
+
+                let mut conversion_info =
+                    vk::SamplerYcbcrConversionInfo::builder().conversion(texture.conversion);
+                let view_create_info = vk::ImageViewCreateInfo::builder()
+                    .image(image)
+                    .view_type(vk::ImageViewType::TYPE_2D)
+                    .format(format)
+                    .subresource_range(vk::ImageSubresourceRange {
+                        aspect_mask: vk::ImageAspectFlags::COLOR,
+                        base_mip_level: 0,
+                        level_count: 1,
+                        base_array_layer: 0,
+                        layer_count: 1,
+                    })
+                    .push_next(&mut conversion_info);
+                texture.view = raw_device
+                    .create_image_view(&view_create_info, None)
+                    .map_err(DeviceError::from)?;
+                self.set_object_name(raw_device, texture.view, &format!("{} view", name));
+
+                Ok(texture)
+            })
+        }
+    }
+
+    /// Names the image and memory backing a dmabuf with the label, fourcc code and modifier of the dmabuf.
+    unsafe fn name_dmabuf_objects(
+        &self,
+        device: &ash::Device,
+        label: Option<&str>,
+        format: DrmFormat,
+        image: vk::Image,
+        memory: &[vk::DeviceMemory],
+    ) {
+        if self.debug_utils.is_none() {
+            return;
+        }
+
+        let name = debug::dmabuf_object_name(label, format);
+        self.set_object_name(device, image, &name);
+
+        match memory {
+            [memory] => self.set_object_name(device, *memory, &format!("{} memory", name)),
+            planes => {
+                for (plane, &memory) in planes.iter().enumerate() {
+                    self.set_object_name(
+                        device,
+                        memory,
+                        &format!("{} plane {} memory", name, plane),
+                    );
+                }
+            }
+        }
+    }
+
+    /// Checks the format, usages and planes of the dmabuf are supported by the device.
+    ///
+    /// Returns whether the image must be imported as a disjoint image.
+    fn check_importable(
+        &self,
+        dmabuf: &Dmabuf,
+        usage: wgpu::TextureUsages,
+    ) -> Result<bool, ImportError> {
+        if !self.supports_dmabuf {
+            return Err(ImportError::Unsupported);
+        }
+
+        let properties = self
+            .supported_drm_formats
+            .get(&dmabuf.format)
+            .ok_or(ImportError::UnsupportedFormat(dmabuf.format))?;
+
+        if !properties
+            .drm_format_modifier_tiling_features
+            .contains(conv::map_texture_usage_to_features(usage))
+        {
+            return Err(ImportError::UnsupportedUsage {
+                format: dmabuf.format,
+                usage,
+            });
+        }
+
+        validation::validate_dmabuf(dmabuf, &constraints(properties))?;
+
+        // If the planes are stored in different dmabufs, each plane must be bound to different memory.
+        let disjoint = !planes_share_dmabuf(dmabuf)?;
+
+        if disjoint
+            && !properties
+                .drm_format_modifier_tiling_features
+                .contains(vk::FormatFeatureFlags::DISJOINT)
+        {
+            return Err(ImportError::DisjointPlanes);
+        }
+
+        Ok(disjoint)
+    }
+
+    /// Checks an image with the format, modifier, usages and size of the dmabuf may be created.
+    ///
+    /// The format features of a modifier do not account for every limit of the implementation, such as the
+    /// maximum size of an image or usages which are unsupported with external memory.
+    unsafe fn check_image_format(
+        &self,
+        instance: &InstanceShared,
+        phd: vk::PhysicalDevice,
+        dmabuf: &Dmabuf,
+        format: vk::Format,
+        usage: wgpu::TextureUsages,
+        disjoint: bool,
+    ) -> Result<(), ImportError> {
+        let flags = if disjoint {
+            vk::ImageCreateFlags::DISJOINT
+        } else {
+            vk::ImageCreateFlags::empty()
+        };
+
+        let mut modifier_info = vk::PhysicalDeviceImageDrmFormatModifierInfoEXT::builder()
+            .drm_format_modifier(u64::from(dmabuf.format.modifier))
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        let mut external_image_format_info = vk::PhysicalDeviceExternalImageFormatInfo::builder()
+            .handle_type(vk::ExternalMemoryHandleTypeFlags::DMA_BUF_EXT);
+        let image_format_info = vk::PhysicalDeviceImageFormatInfo2::builder()
+            .format(format)
+            .ty(vk::ImageType::TYPE_2D)
+            .tiling(vk::ImageTiling::DRM_FORMAT_MODIFIER_EXT)
+            .usage(conv::map_texture_usage(usage))
+            .flags(flags)
+            .push_next(&mut external_image_format_info)
+            .push_next(&mut modifier_info);
+
+        let mut external_image_format_properties = vk::ExternalImageFormatProperties::default();
+        let mut image_format_properties =
+            vk::ImageFormatProperties2::builder().push_next(&mut external_image_format_properties);
+
+        // An error means the combination of format, modifier, usage and handle type is not supported.
+        get_physical_device_image_format_properties2(
+            instance.entry(),
+            instance.raw_instance(),
+            phd,
+            &image_format_info,
+            &mut image_format_properties,
+            instance.driver_api_version(),
+        )
+        .map_err(|_| ImportError::UnsupportedUsage {
+            format: dmabuf.format,
+            usage,
+        })?;
+
+        let max_extent = image_format_properties.image_format_properties.max_extent;
+
+        if !external_image_format_properties
+            .external_memory_properties
+            .external_memory_features
+            .contains(vk::ExternalMemoryFeatureFlags::IMPORTABLE)
+        {
+            return Err(ImportError::UnsupportedUsage {
+                format: dmabuf.format,
+                usage,
+            });
+        }
+
+        if dmabuf.width > max_extent.width || dmabuf.height > max_extent.height {
+            return Err(ImportError::UnsupportedSize {
+                format: dmabuf.format,
+                width: dmabuf.width,
+                height: dmabuf.height,
+                max_width: max_extent.width,
+                max_height: max_extent.height,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Creates an image and imports the memory of the dmabuf.
+    ///
+    /// If the image is disjoint, the dmabuf of each plane is imported and bound to the plane. Otherwise the
+    /// dmabuf of the first plane is bound to the whole image.
+    unsafe fn import_image(
+        &self,
+        device: &ash::Device,
+        dmabuf: &Dmabuf,
+        format: vk::Format,
+        usage: vk::ImageUsageFlags,
+        disjoint: bool,
+    ) -> Result<(vk::Image, Vec<vk::DeviceMemory>), ImportError> {
+        // For disjoint images the offset of each plane is relative to the memory bound to the plane.
+        let plane_layouts = dmabuf
+            .planes
+            .iter()
+            .map(|plane| vk::SubresourceLayout {
+                offset: plane.offset as u64,
+                // Must be 0 according to VUID-VkImageDrmFormatModifierExplicitCreateInfoEXT-size-02267
+                size: 0,
+                row_pitch: plane.stride as u64,
+                array_pitch: 0,
+                depth_pitch: 0,
+            })
+            .collect::<Vec<_>>();
+
+        let mut modifier_info = vk::ImageDrmFormatModifierExplicitCreateInfoEXT::builder()
+            .drm_format_modifier(u64::from(dmabuf.format.modifier))
+            .plane_layouts(&plane_layouts);
+        let mut external_memory_image = vk::ExternalMemoryImageCreateInfo::builder()
+            .handle_types(vk::ExternalMemoryHandleTypeFlags::DMA_BUF_EXT);
+
+        let flags = if disjoint {
+            vk::ImageCreateFlags::DISJOINT
+        } else {
+            vk::ImageCreateFlags::empty()
+        };
+
+        let create_info = vk::ImageCreateInfo::builder()
+            .flags(flags)
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(format)
+            .extent(vk::Extent3D {
+                width: dmabuf.width,
+                height: dmabuf.height,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::DRM_FORMAT_MODIFIER_EXT)
+            .usage(usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .push_next(&mut external_memory_image)
+            .push_next(&mut modifier_info);
+
+        let image = device
+            .create_image(&create_info, None)
+            .map_err(DeviceError::from)?;
+
+        let result = if disjoint {
+            self.import_disjoint_memory(device, image, &dmabuf.planes)
+        } else {
+            self.import_memory(device, image, &dmabuf.planes[0])
+                .map(|memory| vec![memory])
+        };
+
+        match result {
+            Ok(memory) => Ok((image, memory)),
+            Err(err) => {
+                device.destroy_image(image, None);
+                Err(err)
+            }
+        }
+    }
+
+    /// Imports the dmabuf containing the plane and binds it to the whole image.
+    unsafe fn import_memory(
+        &self,
+        device: &ash::Device,
+        image: vk::Image,
+        plane: &DmabufPlane,
+    ) -> Result<vk::DeviceMemory, ImportError> {
+        let (requirements, dedicated) = self.image_memory_requirements(image);
+        let dedicated_image = Some(image).filter(|_| dedicated);
+        let memory = self.allocate_imported_memory(device, requirements, plane, dedicated_image)?;
+
+        if let Err(err) = device.bind_image_memory(image, memory, 0) {
+            device.free_memory(memory, None);
+            return Err(DeviceError::from(err).into());
+        }
+
+        Ok(memory)
+    }
+
+    /// Imports the dmabuf of each plane and binds it to the plane of a disjoint image.
+    unsafe fn import_disjoint_memory(
+        &self,
+        device: &ash::Device,
+        image: vk::Image,
+        planes: &[DmabufPlane],
+    ) -> Result<Vec<vk::DeviceMemory>, ImportError> {
+        let free_all = |memory: &[vk::DeviceMemory]| {
+            for &memory in memory {
+                device.free_memory(memory, None);
+            }
+        };
+
+        let mut memory = Vec::with_capacity(planes.len());
+
+        for (plane, &plane_aspect) in planes.iter().zip(&MEMORY_PLANE_ASPECTS) {
+            let mut plane_requirements_info =
+                vk::ImagePlaneMemoryRequirementsInfo::builder().plane_aspect(plane_aspect);
+            let requirements_info = vk::ImageMemoryRequirementsInfo2::builder()
+                .image(image)
+                .push_next(&mut plane_requirements_info);
+            let mut requirements = vk::MemoryRequirements2::default();
+            self.promoted_fns
+                .get_image_memory_requirements2(&requirements_info, &mut requirements);
+
+            // Disjoint images can not be bound to a dedicated allocation.
+            match self.allocate_imported_memory(
+                device,
+                requirements.memory_requirements,
+                plane,
+                None,
+            ) {
+                Ok(plane_memory) => memory.push(plane_memory),
+                Err(err) => {
+                    free_all(&memory);
+                    return Err(err);
+                }
+            }
+        }
+
+        let mut plane_infos = MEMORY_PLANE_ASPECTS[..planes.len()]
+            .iter()
+            .map(|&plane_aspect| {
+                vk::BindImagePlaneMemoryInfo::builder()
+                    .plane_aspect(plane_aspect)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+        let bind_infos = plane_infos
+            .iter_mut()
+            .zip(&memory)
+            .map(|(plane_info, &plane_memory)| {
+                vk::BindImageMemoryInfo::builder()
+                    .image(image)
+                    .memory(plane_memory)
+                    .memory_offset(0)
+                    .push_next(plane_info)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+
+        if let Err(err) = self.promoted_fns.bind_image_memory2(&bind_infos) {
+            free_all(&memory);
+            return Err(DeviceError::from(err).into());
+        }
+
+        Ok(memory)
+    }
+
+    /// Imports the dmabuf containing the plane.
+    ///
+    /// If `dedicated_image` is specified, the memory is imported as a dedicated allocation for the image.
+    unsafe fn allocate_imported_memory(
+        &self,
+        device: &ash::Device,
+        requirements: vk::MemoryRequirements,
+        plane: &DmabufPlane,
+        dedicated_image: Option<vk::Image>,
+    ) -> Result<vk::DeviceMemory, ImportError> {
+        // The dmabuf may only be imported as some memory types, which depend on where the exporter allocated it.
+        let fd_properties = self
+            .external_memory_fd
+            .get_memory_fd_properties(
+                vk::ExternalMemoryHandleTypeFlags::DMA_BUF_EXT,
+                plane.fd.as_raw_fd(),
+            )
+            .map_err(|err| match err {
+                vk::Result::ERROR_INVALID_EXTERNAL_HANDLE => ImportError::InvalidHandle,
+                err => DeviceError::from(err).into(),
+            })?;
+
+        let type_bits = requirements.memory_type_bits & fd_properties.memory_type_bits;
+        let memory_type_index = find_memory_type_index(
+            &self.memory_properties,
+            type_bits,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )
+        .ok_or(ImportError::NoCompatibleMemoryType {
+            image_type_bits: requirements.memory_type_bits,
+            fd_type_bits: fd_properties.memory_type_bits,
+        })?;
+
+        // A successful import transfers ownership of the file descriptor to the driver.
+        let fd = plane.fd.try_clone()?;
+
+        let mut import_info = vk::ImportMemoryFdInfoKHR::builder()
+            .handle_type(vk::ExternalMemoryHandleTypeFlags::DMA_BUF_EXT)
+            .fd(fd.as_raw_fd());
+        let mut dedicated_info = vk::MemoryDedicatedAllocateInfo::builder();
+        let mut allocate_info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(requirements.size)
+            .memory_type_index(memory_type_index)
+            .push_next(&mut import_info);
+
+        if let Some(image) = dedicated_image {
+            dedicated_info = dedicated_info.image(image);
+            allocate_info = allocate_info.push_next(&mut dedicated_info);
+        }
+
+        let memory = device
+            .allocate_memory(&allocate_info, None)
+            .map_err(DeviceError::from)?;
+        let _ = fd.into_raw_fd();
+
+        Ok(memory)
+    }
+}
+
+/// The constraints on the layout of dmabufs imported with the modifier.
+fn constraints(properties: &vk::DrmFormatModifierPropertiesEXT) -> DmabufConstraints {
+    DmabufConstraints {
+        plane_count: Some(properties.drm_format_modifier_plane_count as usize),
+    }
+}
+
+/// Whether every plane of the dmabuf is stored in the same dmabuf.
+fn planes_share_dmabuf(dmabuf: &Dmabuf) -> Result<bool, ImportError> {
+    let mut planes = dmabuf.planes.iter();
+
+    let first = match planes.next() {
+        Some(plane) => fstat(plane.fd.as_raw_fd()).map_err(io::Error::from)?,
+        None => return Ok(true),
+    };
+
+    for plane in planes {
+        let stat = fstat(plane.fd.as_raw_fd()).map_err(io::Error::from)?;
+
+        if (stat.st_dev, stat.st_ino) != (first.st_dev, first.st_ino) {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
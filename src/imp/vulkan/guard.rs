@@ -0,0 +1,84 @@
+//! Destroying the images and memory of imported and exported textures.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, Weak},
+};
+
+use ash::vk;
+use wgpu::TextureDescriptor;
+use wgpu_hal::api::Vulkan;
+
+use crate::imp;
+
+use super::{layout::ExternalTexture, Inner};
+
+/// Owns the image and memory of a texture created from a raw image.
+///
+/// wgpu-hal does not destroy the image of a texture with a drop guard, so the guard destroys the image and frees
+/// the memory when wgpu destroys the texture. Freeing imported memory closes the file descriptor owned by the
+/// driver.
+///
+/// wgpu keeps the device alive until every texture of the device is destroyed, so the guard never outlives the
+/// device.
+struct TextureGuard {
+    device: ash::Device,
+    image: vk::Image,
+    memory: Vec<vk::DeviceMemory>,
+    /// The layouts of the image stop being tracked when the texture is destroyed.
+    external_textures: Weak<Mutex<HashMap<vk::Image, ExternalTexture>>>,
+}
+
+impl Drop for TextureGuard {
+    fn drop(&mut self) {
+        // The handle may be reused by the driver once the image is destroyed.
+        if let Some(external_textures) = self.external_textures.upgrade() {
+            external_textures.lock().unwrap().remove(&self.image);
+        }
+
+        unsafe {
+            self.device.destroy_image(self.image, None);
+
+            for &memory in &self.memory {
+                self.device.free_memory(memory, None);
+            }
+        }
+    }
+}
+
+impl Inner {
+    /// Creates a texture from an image and the memory bound to it.
+    ///
+    /// The texture takes ownership of the image and memory, which are destroyed when the texture is destroyed.
+    pub(super) unsafe fn texture_from_raw(
+        &self,
+        device: &wgpu::Device,
+        raw_device: &ash::Device,
+        image: vk::Image,
+        memory: Vec<vk::DeviceMemory>,
+        desc: &TextureDescriptor,
+    ) -> wgpu::Texture {
+        let guard = TextureGuard {
+            device: raw_device.clone(),
+            image,
+            memory,
+            external_textures: Arc::downgrade(&self.external_textures),
+        };
+
+        let hal_desc = wgpu_hal::TextureDescriptor {
+            label: desc.label,
+            size: desc.size,
+            mip_level_count: desc.mip_level_count,
+            sample_count: desc.sample_count,
+            dimension: desc.dimension,
+            format: desc.format,
+            usage: imp::conv::map_texture_usage(desc.usage, desc.format),
+            memory_flags: wgpu_hal::MemoryFlags::empty(),
+        };
+
+        let hal_texture =
+            wgpu_hal::vulkan::Device::texture_from_raw(image, &hal_desc, Some(Box::new(guard)));
+
+        device.create_texture_from_hal::<Vulkan>(hal_texture, desc)
+    }
+}
@@ -0,0 +1,290 @@
+//! Sharing textures and semaphores with other graphics APIs using opaque file descriptors.
+
+use std::os::unix::io::{FromRawFd, OwnedFd};
+
+use ash::{
+    extensions::khr::{ExternalMemoryFd, ExternalSemaphoreFd},
+    vk::{self, KhrExternalSemaphoreFn},
+};
+use wgpu::{TextureDescriptor, TextureDimension};
+use wgpu_hal::{api::Vulkan, DeviceError};
+
+use crate::opaque_fd::{OpaqueFd, OpaqueFdExportError};
+
+use super::{
+    barrier::{BarrierQueue, COLOR_SUBRESOURCE_RANGE},
+    conv, find_memory_type_index,
+    promoted::Extension,
+    Inner,
+};
+
+/// Device extensions required to export textures and semaphores as opaque file descriptors.
+pub const REQUIRED_DEVICE_EXTENSIONS: &[Extension] = &[
+    (ExternalMemoryFd::name(), None),
+    (KhrExternalSemaphoreFn::name(), Some(vk::API_VERSION_1_1)),
+    (ExternalSemaphoreFd::name(), None),
+];
+
+impl Inner {
+    pub fn create_opaque_fd_texture(
+        &self,
+        device: &wgpu::Device,
+        desc: &TextureDescriptor,
+    ) -> Result<(wgpu::Texture, OpaqueFd), OpaqueFdExportError> {
+        if !self.supports_opaque_fd {
+            return Err(OpaqueFdExportError::Unsupported);
+        }
+
+        if desc.dimension != TextureDimension::D2
+            || desc.size.depth_or_array_layers != 1
+            || desc.mip_level_count != 1
+            || desc.sample_count != 1
+        {
+            return Err(OpaqueFdExportError::InvalidDescriptor);
+        }
+
+        let format = conv::map_texture_format(desc.format)
+            .ok_or(OpaqueFdExportError::UnsupportedFormat(desc.format))?;
+
+        unsafe {
+            device.as_hal::<Vulkan, _, _>(|hal_device| {
+                let hal_device = hal_device.unwrap();
+                let raw_device = hal_device.raw_device();
+
+                let mut external_memory_image = vk::ExternalMemoryImageCreateInfo::builder()
+                    .handle_types(vk::ExternalMemoryHandleTypeFlags::OPAQUE_FD);
+
+                // The other api uses the optimal tiling of the driver when the memory is imported.
+                let create_info = vk::ImageCreateInfo::builder()
+                    .image_type(vk::ImageType::TYPE_2D)
+                    .format(format)
+                    .extent(vk::Extent3D {
+                        width: desc.size.width,
+                        height: desc.size.height,
+                        depth: 1,
+                    })
+                    .mip_levels(1)
+                    .array_layers(1)
+                    .samples(vk::SampleCountFlags::TYPE_1)
+                    .tiling(vk::ImageTiling::OPTIMAL)
+                    .usage(conv::map_texture_usage(desc.usage))
+                    .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                    .initial_layout(vk::ImageLayout::UNDEFINED)
+                    .push_next(&mut external_memory_image);
+
+                let image = raw_device
+                    .create_image(&create_info, None)
+                    .map_err(DeviceError::from)?;
+
+                let (memory, size) = match self.allocate_opaque_fd_memory(raw_device, image) {
+                    Ok(memory) => memory,
+                    Err(err) => {
+                        raw_device.destroy_image(image, None);
+                        return Err(err.into());
+                    }
+                };
+
+                let get_fd_info = vk::MemoryGetFdInfoKHR::builder()
+                    .memory(memory)
+                    .handle_type(vk::ExternalMemoryHandleTypeFlags::OPAQUE_FD);
+
+                let fd = match self.external_memory_fd.get_memory_fd(&get_fd_info) {
+                    // SAFETY: vkGetMemoryFdKHR creates a new file descriptor which we now own.
+                    Ok(fd) => OwnedFd::from_raw_fd(fd),
+                    Err(err) => {
+                        raw_device.destroy_image(image, None);
+                        raw_device.free_memory(memory, None);
+                        return Err(DeviceError::from(err).into());
+                    }
+                };
+
+                if let Some(label) = desc.label {
+                    self.set_object_name(raw_device, image, label);
+                    self.set_object_name(raw_device, memory, label);
+                }
+
+                Ok((
+                    self.texture_from_raw(device, raw_device, image, vec![memory], desc),
+                    OpaqueFd { fd, size },
+                ))
+            })
+        }
+    }
+
+    unsafe fn allocate_opaque_fd_memory(
+        &self,
+        device: &ash::Device,
+        image: vk::Image,
+    ) -> Result<(vk::DeviceMemory, u64), DeviceError> {
+        let (requirements, dedicated) = self.image_memory_requirements(image);
+        let memory_type_index = find_memory_type_index(
+            &self.memory_properties,
+            requirements.memory_type_bits,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )
+        .ok_or(DeviceError::OutOfMemory)?;
+
+        let mut export_info = vk::ExportMemoryAllocateInfo::builder()
+            .handle_types(vk::ExternalMemoryHandleTypeFlags::OPAQUE_FD);
+        let mut dedicated_info = vk::MemoryDedicatedAllocateInfo::builder().image(image);
+        let mut allocate_info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(requirements.size)
+            .memory_type_index(memory_type_index)
+            .push_next(&mut export_info);
+
+        if dedicated {
+            allocate_info = allocate_info.push_next(&mut dedicated_info);
+        }
+
+        let memory = device.allocate_memory(&allocate_info, None)?;
+
+        if let Err(err) = device.bind_image_memory(image, memory, 0) {
+            device.free_memory(memory, None);
+            return Err(err.into());
+        }
+
+        Ok((memory, requirements.size))
+    }
+
+    /// Creates the semaphores and command buffer used to hand a texture over to another api.
+    ///
+    /// Returns the handoff and the file descriptors of the release and acquire semaphores.
+    pub fn create_handoff(
+        &self,
+        device: &wgpu::Device,
+        texture: &wgpu::Texture,
+    ) -> Result<(Handoff, OwnedFd, OwnedFd), OpaqueFdExportError> {
+        // The semaphores are exported using VK_KHR_external_semaphore_fd.
+        if !self.supports_opaque_fd {
+            return Err(OpaqueFdExportError::Unsupported);
+        }
+
+        let mut image = vk::Image::null();
+        unsafe {
+            texture.as_hal::<Vulkan, _>(|hal_texture| {
+                image = hal_texture.unwrap().raw_handle();
+            })
+        };
+
+        unsafe {
+            device.as_hal::<Vulkan, _, _>(|hal_device| {
+                let hal_device = hal_device.unwrap();
+                let raw_device = hal_device.raw_device();
+                let queue = BarrierQueue::new(hal_device)?;
+
+                let (release_semaphore, release_fd) =
+                    self.create_exportable_semaphore(raw_device)?;
+                let (acquire_semaphore, acquire_fd) =
+                    match self.create_exportable_semaphore(raw_device) {
+                        Ok(semaphore) => semaphore,
+                        Err(err) => {
+                            raw_device.destroy_semaphore(release_semaphore, None);
+                            return Err(err.into());
+                        }
+                    };
+
+                let handoff = Handoff {
+                    queue,
+                    image,
+                    release_semaphore,
+                    acquire_semaphore,
+                };
+
+                Ok((handoff, release_fd, acquire_fd))
+            })
+        }
+    }
+
+    unsafe fn create_exportable_semaphore(
+        &self,
+        device: &ash::Device,
+    ) -> Result<(vk::Semaphore, OwnedFd), DeviceError> {
+        let mut export_info = vk::ExportSemaphoreCreateInfo::builder()
+            .handle_types(vk::ExternalSemaphoreHandleTypeFlags::OPAQUE_FD);
+        let create_info = vk::SemaphoreCreateInfo::builder().push_next(&mut export_info);
+        let semaphore = device.create_semaphore(&create_info, None)?;
+
+        let get_fd_info = vk::SemaphoreGetFdInfoKHR::builder()
+            .semaphore(semaphore)
+            .handle_type(vk::ExternalSemaphoreHandleTypeFlags::OPAQUE_FD);
+
+        match self.external_semaphore_fd.get_semaphore_fd(&get_fd_info) {
+            // SAFETY: vkGetSemaphoreFdKHR creates a new file descriptor which we now own.
+            Ok(fd) => Ok((semaphore, OwnedFd::from_raw_fd(fd))),
+            Err(err) => {
+                device.destroy_semaphore(semaphore, None);
+                Err(err.into())
+            }
+        }
+    }
+}
+
+/// Transfers ownership of an image between the queue of a device and another api.
+pub struct Handoff {
+    queue: BarrierQueue,
+    image: vk::Image,
+    /// Signalled by the device and waited on by the other api.
+    release_semaphore: vk::Semaphore,
+    /// Signalled by the other api and waited on by the device.
+    acquire_semaphore: vk::Semaphore,
+}
+
+impl Handoff {
+    /// Releases the image to the other api after all prior submissions and signals the release semaphore.
+    ///
+    /// The layout is not changed, so wgpu's view of the layout stays valid once the image is acquired again.
+    ///
+    /// The handoff submits directly to the queue of the device, so no other thread may submit to the queue
+    /// during the call.
+    pub unsafe fn release(&self, layout: vk::ImageLayout) -> Result<(), DeviceError> {
+        let barrier = vk::ImageMemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE)
+            .dst_access_mask(vk::AccessFlags::empty())
+            .old_layout(layout)
+            .new_layout(layout)
+            .src_queue_family_index(self.queue.queue_family_index())
+            .dst_queue_family_index(vk::QUEUE_FAMILY_EXTERNAL)
+            .image(self.image)
+            .subresource_range(COLOR_SUBRESOURCE_RANGE);
+
+        self.queue
+            .submit(&[barrier.build()], None, Some(self.release_semaphore))
+    }
+
+    /// Waits on the acquire semaphore and acquires the image from the other api before any later submissions.
+    ///
+    /// The other api must leave the image in the layout it was released in.
+    ///
+    /// The handoff submits directly to the queue of the device, so no other thread may submit to the queue
+    /// during the call.
+    pub unsafe fn acquire(&self, layout: vk::ImageLayout) -> Result<(), DeviceError> {
+        let barrier = vk::ImageMemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::empty())
+            .dst_access_mask(vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE)
+            .old_layout(layout)
+            .new_layout(layout)
+            .src_queue_family_index(vk::QUEUE_FAMILY_EXTERNAL)
+            .dst_queue_family_index(self.queue.queue_family_index())
+            .image(self.image)
+            .subresource_range(COLOR_SUBRESOURCE_RANGE);
+
+        self.queue
+            .submit(&[barrier.build()], Some(self.acquire_semaphore), None)
+    }
+}
+
+impl Drop for Handoff {
+    fn drop(&mut self) {
+        // The semaphores may still be in use by the last handoff.
+        let _ = self.queue.wait();
+
+        unsafe {
+            self.queue
+                .device()
+                .destroy_semaphore(self.release_semaphore, None);
+            self.queue
+                .device()
+                .destroy_semaphore(self.acquire_semaphore, None);
+        }
+    }
+}
@@ -0,0 +1,430 @@
+//! Tracking the layouts the other side expects for imported and exported dmabuf textures.
+
+use std::{
+    ffi::{c_void, CStr},
+    ptr,
+};
+
+use ash::vk::{self, ExtQueueFamilyForeignFn};
+use wgpu::{
+    Extent3d, ImageCopyBuffer, ImageCopyTexture, ImageDataLayout, LoadOp, Operations, Origin3d,
+    TextureAspect, TextureUsages,
+};
+use wgpu_hal::{api::Vulkan, DeviceError};
+
+use crate::{
+    layout::{ExternalLayouts, LayoutError},
+    semaphore::TextureLayout,
+    ycbcr::YcbcrTexture,
+};
+
+use super::{
+    barrier::{BarrierQueue, COLOR_SUBRESOURCE_RANGE},
+    conv,
+    promoted::Extension,
+    Inner,
+};
+
+/// Device extensions which are enabled with the dmabuf extensions if they are available.
+pub const OPTIONAL_DEVICE_EXTENSIONS: &[Extension] = &[
+    (ExtQueueFamilyForeignFn::name(), None),
+    (EXTERNAL_MEMORY_ACQUIRE_UNMODIFIED_NAME, None),
+];
+
+/// The state of a dmabuf texture shared with the other side.
+#[derive(Debug)]
+pub struct ExternalTexture {
+    layouts: ExternalLayouts,
+    /// The usages the texture was created with.
+    usage: TextureUsages,
+    /// The layout wgpu tracks for the image, or [`None`] if wgpu still considers the image uninitialized.
+    wgpu_layout: Option<TextureLayout>,
+    /// The layout the image was last released to the other side in.
+    released_layout: Option<vk::ImageLayout>,
+    /// Whether the device owns the image.
+    acquired: bool,
+}
+
+impl Inner {
+    /// Starts tracking the layouts of an imported or exported image.
+    ///
+    /// Exported images are owned by the device until they are released, imported images must be acquired first.
+    /// The image stops being tracked when the texture is destroyed.
+    pub(super) fn track_external_image(
+        &self,
+        image: vk::Image,
+        usage: TextureUsages,
+        acquired: bool,
+    ) {
+        self.external_textures.lock().unwrap().insert(
+            image,
+            ExternalTexture {
+                layouts: ExternalLayouts::default(),
+                usage,
+                wgpu_layout: None,
+                released_layout: None,
+                acquired,
+            },
+        );
+    }
+
+    pub fn set_external_layouts(
+        &self,
+        texture: &wgpu::Texture,
+        layouts: ExternalLayouts,
+    ) -> Result<(), LayoutError> {
+        if layouts.release == TextureLayout::Undefined {
+            return Err(LayoutError::UndefinedRelease);
+        }
+
+        let image = raw_image(texture).ok_or(LayoutError::UnknownTexture)?;
+        let mut textures = self.external_textures.lock().unwrap();
+        let state = textures
+            .get_mut(&image)
+            .ok_or(LayoutError::UnknownTexture)?;
+        state.layouts = layouts;
+
+        Ok(())
+    }
+
+    pub unsafe fn acquire_texture(
+        &self,
+        device: &wgpu::Device,
+        texture: &wgpu::Texture,
+        unmodified: bool,
+    ) -> Result<(), LayoutError> {
+        let image = raw_image(texture).ok_or(LayoutError::UnknownTexture)?;
+        let mut textures = self.external_textures.lock().unwrap();
+        let state = textures
+            .get_mut(&image)
+            .ok_or(LayoutError::UnknownTexture)?;
+
+        // wgpu considers a texture uninitialized until it is first used, and transitions it from the undefined
+        // layout then. Any layout is valid for that transition, so the image is acquired in the general layout
+        // until wgpu has left it in a known layout.
+        let new_layout = state
+            .wgpu_layout
+            .map_or(vk::ImageLayout::GENERAL, conv::map_texture_layout);
+
+        self.acquire_image(device, image, state, new_layout, unmodified)
+    }
+
+    pub unsafe fn release_texture(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        texture: &wgpu::Texture,
+    ) -> Result<(), LayoutError> {
+        let image = raw_image(texture).ok_or(LayoutError::UnknownTexture)?;
+        let usage = {
+            let textures = self.external_textures.lock().unwrap();
+            let state = textures.get(&image).ok_or(LayoutError::UnknownTexture)?;
+
+            if !state.acquired {
+                return Err(LayoutError::NotAcquired);
+            }
+
+            state.usage
+        };
+
+        // wgpu does not expose the layout of its last use of the image, so use the image once more to move it
+        // to a layout which is known.
+        //
+        // The lock is not held while submitting, since wgpu may destroy textures when the queue is submitted.
+        let wgpu_layout = self.pin_wgpu_layout(device, queue, texture, usage)?;
+
+        let mut textures = self.external_textures.lock().unwrap();
+        let state = textures
+            .get_mut(&image)
+            .ok_or(LayoutError::UnknownTexture)?;
+
+        self.release_image(device, image, state, conv::map_texture_layout(wgpu_layout))?;
+
+        // wgpu still expects the image in the layout of the last use, so the image is acquired in this layout.
+        state.wgpu_layout = Some(wgpu_layout);
+
+        Ok(())
+    }
+
+    pub fn set_ycbcr_external_layouts(
+        &self,
+        texture: &YcbcrTexture,
+        layouts: ExternalLayouts,
+    ) -> Result<(), LayoutError> {
+        if layouts.release == TextureLayout::Undefined {
+            return Err(LayoutError::UndefinedRelease);
+        }
+
+        let mut textures = self.external_textures.lock().unwrap();
+        let state = textures
+            .get_mut(&texture.image)
+            .ok_or(LayoutError::UnknownTexture)?;
+        state.layouts = layouts;
+
+        Ok(())
+    }
+
+    pub unsafe fn acquire_ycbcr_texture(
+        &self,
+        device: &wgpu::Device,
+        texture: &YcbcrTexture,
+        layout: TextureLayout,
+        unmodified: bool,
+    ) -> Result<(), LayoutError> {
+        if layout == TextureLayout::Undefined {
+            return Err(LayoutError::UndefinedAcquire);
+        }
+
+        let mut textures = self.external_textures.lock().unwrap();
+        let state = textures
+            .get_mut(&texture.image)
+            .ok_or(LayoutError::UnknownTexture)?;
+
+        self.acquire_image(
+            device,
+            texture.image,
+            state,
+            conv::map_texture_layout(layout),
+            unmodified,
+        )
+    }
+
+    pub unsafe fn release_ycbcr_texture(
+        &self,
+        device: &wgpu::Device,
+        texture: &YcbcrTexture,
+        layout: TextureLayout,
+    ) -> Result<(), LayoutError> {
+        let mut textures = self.external_textures.lock().unwrap();
+        let state = textures
+            .get_mut(&texture.image)
+            .ok_or(LayoutError::UnknownTexture)?;
+
+        self.release_image(
+            device,
+            texture.image,
+            state,
+            conv::map_texture_layout(layout),
+        )
+    }
+
+    /// Acquires an image from the other side in the layout.
+    unsafe fn acquire_image(
+        &self,
+        device: &wgpu::Device,
+        image: vk::Image,
+        state: &mut ExternalTexture,
+        new_layout: vk::ImageLayout,
+        unmodified: bool,
+    ) -> Result<(), LayoutError> {
+        if state.acquired {
+            return Err(LayoutError::AlreadyAcquired);
+        }
+
+        // If the other side did not modify the image, it is still in the layout it was released in.
+        let released_layout = state.released_layout.filter(|_| unmodified);
+        let old_layout =
+            released_layout.unwrap_or_else(|| conv::map_texture_layout(state.layouts.acquire));
+
+        // Tells the driver the memory is unchanged since the release, so the driver can skip reinitialising
+        // metadata such as compression state.
+        let mut acquire_unmodified = ExternalMemoryAcquireUnmodifiedEXT {
+            s_type: STRUCTURE_TYPE_EXTERNAL_MEMORY_ACQUIRE_UNMODIFIED_EXT,
+            p_next: ptr::null(),
+            acquire_unmodified_memory: vk::TRUE,
+        };
+
+        let mut barrier = vk::ImageMemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::empty())
+            .dst_access_mask(vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE)
+            .old_layout(old_layout)
+            .new_layout(new_layout)
+            .image(image)
+            .subresource_range(COLOR_SUBRESOURCE_RANGE);
+
+        if released_layout.is_some() && self.supports_acquire_unmodified {
+            barrier = barrier.push_next(&mut acquire_unmodified);
+        }
+
+        self.submit_ownership_transfer(device, barrier.build(), true)?;
+        state.acquired = true;
+
+        Ok(())
+    }
+
+    /// Releases an image in the layout to the other side.
+    unsafe fn release_image(
+        &self,
+        device: &wgpu::Device,
+        image: vk::Image,
+        state: &mut ExternalTexture,
+        old_layout: vk::ImageLayout,
+    ) -> Result<(), LayoutError> {
+        if !state.acquired {
+            return Err(LayoutError::NotAcquired);
+        }
+
+        let new_layout = conv::map_texture_layout(state.layouts.release);
+
+        let barrier = vk::ImageMemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE)
+            .dst_access_mask(vk::AccessFlags::empty())
+            .old_layout(old_layout)
+            .new_layout(new_layout)
+            .image(image)
+            .subresource_range(COLOR_SUBRESOURCE_RANGE);
+
+        self.submit_ownership_transfer(device, barrier.build(), false)?;
+        state.released_layout = Some(new_layout);
+        state.acquired = false;
+
+        Ok(())
+    }
+
+    /// Uses a texture in wgpu, so the layout wgpu leaves the image in is known.
+    ///
+    /// wgpu does not expose the layout of an image, but wgpu-hal derives the layout from the last use of the
+    /// image: a copy from the image leaves it in `VK_IMAGE_LAYOUT_TRANSFER_SRC_OPTIMAL`, and a render pass which
+    /// only uses the image as a color attachment leaves it in `VK_IMAGE_LAYOUT_COLOR_ATTACHMENT_OPTIMAL`. The
+    /// contents of the texture are not modified.
+    ///
+    /// Returns the layout wgpu expects the image to be in when it is next used.
+    pub fn pin_wgpu_layout(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        texture: &wgpu::Texture,
+        usage: TextureUsages,
+    ) -> Result<TextureLayout, LayoutError> {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+
+        let layout = if usage.contains(TextureUsages::COPY_SRC) {
+            // The texel is never read, so every copy reuses the same buffer.
+            let mut pin_buffer = self.pin_buffer.lock().unwrap();
+            let buffer = pin_buffer.get_or_insert_with(|| {
+                device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("layout pin"),
+                    // Large enough for a texel of any format which may be shared.
+                    size: 16,
+                    usage: wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                })
+            });
+
+            encoder.copy_texture_to_buffer(
+                ImageCopyTexture {
+                    texture,
+                    mip_level: 0,
+                    origin: Origin3d::ZERO,
+                    aspect: TextureAspect::All,
+                },
+                ImageCopyBuffer {
+                    buffer,
+                    layout: ImageDataLayout {
+                        offset: 0,
+                        bytes_per_row: None,
+                        rows_per_image: None,
+                    },
+                },
+                Extent3d {
+                    width: 1,
+                    height: 1,
+                    depth_or_array_layers: 1,
+                },
+            );
+
+            TextureLayout::TransferSrc
+        } else if usage.contains(TextureUsages::RENDER_ATTACHMENT) {
+            let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+            encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: None,
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &view,
+                    resolve_target: None,
+                    ops: Operations {
+                        load: LoadOp::Load,
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+
+            TextureLayout::ColorAttachment
+        } else {
+            return Err(LayoutError::UnsupportedUsage);
+        };
+
+        queue.submit(Some(encoder.finish()));
+
+        Ok(layout)
+    }
+
+    /// Submits a queue family ownership transfer of an image between the device and the other side.
+    ///
+    /// No other thread may submit to the queue of the device during the call.
+    unsafe fn submit_ownership_transfer(
+        &self,
+        device: &wgpu::Device,
+        mut barrier: vk::ImageMemoryBarrier,
+        acquire: bool,
+    ) -> Result<(), DeviceError> {
+        let mut barrier_queue = self.barrier_queue.lock().unwrap();
+
+        if barrier_queue.is_none() {
+            *barrier_queue = Some(
+                device
+                    .as_hal::<Vulkan, _, _>(|hal_device| BarrierQueue::new(hal_device.unwrap()))?,
+            );
+        }
+
+        let barrier_queue = barrier_queue.as_ref().unwrap();
+
+        // Dmabufs are shared with other processes and drivers, which requires the foreign queue family.
+        let external_queue_family = if self.supports_queue_family_foreign {
+            vk::QUEUE_FAMILY_FOREIGN_EXT
+        } else {
+            vk::QUEUE_FAMILY_EXTERNAL
+        };
+
+        if acquire {
+            barrier.src_queue_family_index = external_queue_family;
+            barrier.dst_queue_family_index = barrier_queue.queue_family_index();
+        } else {
+            barrier.src_queue_family_index = barrier_queue.queue_family_index();
+            barrier.dst_queue_family_index = external_queue_family;
+        }
+
+        barrier_queue.submit(&[barrier], None, None)
+    }
+}
+
+fn raw_image(texture: &wgpu::Texture) -> Option<vk::Image> {
+    let mut image = None;
+
+    unsafe {
+        texture.as_hal::<Vulkan, _>(|hal_texture| {
+            image = hal_texture.map(|hal_texture| hal_texture.raw_handle());
+        })
+    };
+
+    image
+}
+
+// VK_EXT_external_memory_acquire_unmodified is not in ash yet.
+
+pub const EXTERNAL_MEMORY_ACQUIRE_UNMODIFIED_NAME: &CStr =
+    unsafe { CStr::from_bytes_with_nul_unchecked(b"VK_EXT_external_memory_acquire_unmodified\0") };
+
+const STRUCTURE_TYPE_EXTERNAL_MEMORY_ACQUIRE_UNMODIFIED_EXT: vk::StructureType =
+    vk::StructureType::from_raw(1_000_453_000);
+
+/// <https://registry.khronos.org/vulkan/specs/1.3-extensions/man/html/VkExternalMemoryAcquireUnmodifiedEXT.html>
+#[repr(C)]
+struct ExternalMemoryAcquireUnmodifiedEXT {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    acquire_unmodified_memory: vk::Bool32,
+}
+
+unsafe impl vk::ExtendsImageMemoryBarrier for ExternalMemoryAcquireUnmodifiedEXT {}
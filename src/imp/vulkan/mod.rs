@@ -3,53 +3,61 @@
 //   This is because imported images belong to a foreign or external queue family.
 //   This means we need queue family ownership transfer on acquire and release to access the image resources.
 
+mod barrier;
+pub mod conv;
+mod debug;
 mod dmabuf;
+mod guard;
+mod interop;
+mod layout;
+mod promoted;
 
 use std::{
     collections::HashMap,
     ffi::{CStr, CString},
-    fmt,
+    fmt, iter, mem,
     path::Path,
+    sync::{Arc, Mutex},
 };
 
 use ash::{
-    extensions::khr::{ExternalMemoryFd, GetPhysicalDeviceProperties2},
-    vk::{self, KhrExternalMemoryFn},
+    extensions::khr::{ExternalMemoryFd, ExternalSemaphoreFd, GetPhysicalDeviceProperties2},
+    vk::{self, KhrDedicatedAllocationFn, KhrExternalMemoryFn, KhrGetMemoryRequirements2Fn},
+};
+use drm_fourcc::{DrmFormat, DrmModifier};
+use wgpu::{
+    Adapter, BufferUsages, DeviceDescriptor, Features, Limits, RequestDeviceError,
+    TextureDescriptor, TextureDimension, TextureFormat, TextureUsages,
 };
-use drm_fourcc::{DrmFormat, DrmFourcc, DrmModifier};
-use wgpu::{Adapter, DeviceDescriptor, Features, Limits, RequestDeviceError};
 use wgpu_hal::{
-    api::Vulkan, Api, DeviceError, InstanceDescriptor, InstanceError, InstanceFlags, OpenDevice,
-    UpdateAfterBindTypes,
+    api::Vulkan, vulkan::InstanceShared, Api, DeviceError, InstanceError, InstanceFlags,
+    OpenDevice, UpdateAfterBindTypes,
 };
 
 use crate::{
-    adapter::{DeviceUuids, DrmInfo},
-    ExternalMemoryDevice,
+    adapter::{AdapterExt, DeviceUuids, DrmInfo},
+    instance::DrmInstanceDescriptor,
+    ExternalMemoryDevice, ExternalMemoryProperties, ExternalMemoryType,
+};
+
+use self::{
+    ash_upstreamed::ImageDrmFormatModifier,
+    barrier::BarrierQueue,
+    debug::DebugUtils,
+    promoted::{device_api_version, extensions_for_version, Extension},
 };
 
-use self::ash_upstreamed::ImageDrmFormatModifier;
+pub use self::{interop::Handoff, promoted::PromotedDeviceFns};
 
 use super::DeviceInner;
 
-pub fn try_create_instance(desc: InstanceDescriptor<'static>) -> Option<<Vulkan as Api>::Instance> {
+pub fn try_create_instance(desc: &DrmInstanceDescriptor) -> Option<<Vulkan as Api>::Instance> {
     // Creating a Vulkan instance for wgpu is more complicated than EGL. Vulkan requires all extensions to be
     // explicitly enabled at creation time and specific extensions may require enabling specific Vulkan
     // features.
     //
     // Much of the code here is copied from wgpu_hal::vulkan::Instance::init
 
-    const REQUIRED_INSTANCE_EXTENSIONS: &[&CStr] = &[
-        // dependency of VK_KHR_external_memory
-        //
-        // This is required or Vulkan 1.1
-        vk::KhrExternalMemoryCapabilitiesFn::name(),
-        // WGPU requires VK_KHR_physical_device_properties2
-        //
-        // Listed for completeness
-        vk::KhrGetPhysicalDeviceProperties2Fn::name(),
-    ];
-
     let entry = match unsafe { ash::Entry::load() } {
         Ok(entry) => entry,
         Err(err) => {
@@ -67,10 +75,16 @@ pub fn try_create_instance(desc: InstanceDescriptor<'static>) -> Option<<Vulkan
         }
     };
 
-    let app_name = CString::new(desc.name).unwrap();
+    let app_name = match CString::new(desc.application_name) {
+        Ok(app_name) => app_name,
+        Err(err) => {
+            log::warn!("Invalid application name: {}", err);
+            return None;
+        }
+    };
     let app_info = vk::ApplicationInfo::builder()
         .application_name(app_name.as_c_str())
-        .application_version(1)
+        .application_version(desc.application_version)
         .engine_name(CStr::from_bytes_with_nul(b"wgpu-hal\0").unwrap())
         .engine_version(2)
         .api_version(
@@ -91,7 +105,34 @@ pub fn try_create_instance(desc: InstanceDescriptor<'static>) -> Option<<Vulkan
         );
 
     let mut extensions = <Vulkan as Api>::Instance::required_extensions(&entry, desc.flags).ok()?;
-    extensions.extend(REQUIRED_INSTANCE_EXTENSIONS);
+
+    let instance_extensions = entry
+        .enumerate_instance_extension_properties(None)
+        .map_err(|e| {
+            log::info!("enumerate_instance_extension_properties: {:?}", e);
+            InstanceError
+        })
+        .ok()?;
+
+    // The extensions promoted to the api version of the instance are not needed. Only keep the additional
+    // extensions which are available, external memory is unsupported if a required extension is missing.
+    let additional_extensions =
+        extensions_for_version(REQUIRED_INSTANCE_EXTENSIONS, driver_api_version)
+            .chain(desc.instance_extensions.iter().copied());
+
+    for extension in additional_extensions {
+        if extensions.contains(&extension) {
+            continue;
+        }
+
+        if instance_extensions.iter().any(
+            |inst_ext| unsafe { CStr::from_ptr(inst_ext.extension_name.as_ptr()) } == extension,
+        ) {
+            extensions.push(extension);
+        } else {
+            log::warn!("Unable to find extension: {}", extension.to_string_lossy());
+        }
+    }
 
     let instance_layers = entry
         .enumerate_instance_layer_properties()
@@ -152,6 +193,8 @@ pub fn try_create_instance(desc: InstanceDescriptor<'static>) -> Option<<Vulkan
         .ok()?
     };
 
+    // wgpu-hal creates a messenger which routes validation messages to the `log` crate if VK_EXT_debug_utils is
+    // enabled, which it requires when debugging is requested.
     unsafe {
         <Vulkan as Api>::Instance::from_raw(
             entry,
@@ -234,6 +277,279 @@ pub fn get_drm_info(adapter: Option<&<Vulkan as Api>::Adapter>) -> Option<DrmInf
     Some(uuids)
 }
 
+pub fn get_external_memory_capabilities(
+    adapter: Option<&<Vulkan as Api>::Adapter>,
+    handle_type: ExternalMemoryType,
+    format: TextureFormat,
+    usage: TextureUsages,
+) -> Option<ExternalMemoryProperties> {
+    let adapter = adapter.unwrap();
+    let shared = adapter.shared_instance();
+
+    if !supports_external_memory_capabilities(shared) || usage.is_empty() {
+        return None;
+    }
+
+    // The modifiers of a format can only be queried if VK_EXT_image_drm_format_modifier is supported.
+    let supports_modifiers =
+        supports_device_extensions(adapter, iter::once(vk::ExtImageDrmFormatModifierFn::name()));
+
+    // SAFETY: The instance uses Vulkan 1.1 or VK_KHR_external_memory_capabilities is enabled.
+    unsafe {
+        get_external_image_properties(
+            shared,
+            adapter.raw_physical_device(),
+            supports_modifiers,
+            handle_type,
+            format,
+            usage,
+        )
+    }
+}
+
+/// Returns the external memory properties of images with the format and usage.
+///
+/// Opaque fds are only shared with the same driver, which means the implementation defined tiling may be used.
+/// Dmabufs are created using one of the modifiers of the format, so every modifier which supports the usage is
+/// queried. The memory may be imported or exported if any modifier allows it, and must be dedicated if any
+/// modifier requires it.
+///
+/// # Safety
+///
+/// The instance must use Vulkan 1.1 or enable VK_KHR_external_memory_capabilities. If `supports_modifiers` is
+/// true, the physical device must support VK_EXT_image_drm_format_modifier.
+unsafe fn get_external_image_properties(
+    instance: &InstanceShared,
+    phd: vk::PhysicalDevice,
+    supports_modifiers: bool,
+    handle_type: ExternalMemoryType,
+    format: TextureFormat,
+    usage: TextureUsages,
+) -> Option<ExternalMemoryProperties> {
+    let format = conv::map_texture_format(format)?;
+    let image_usage = conv::map_texture_usage(usage);
+
+    match handle_type {
+        ExternalMemoryType::OpaqueFd => get_external_image_format_properties(
+            instance,
+            phd,
+            format,
+            image_usage,
+            vk::ExternalMemoryHandleTypeFlags::OPAQUE_FD,
+            None,
+        ),
+
+        ExternalMemoryType::Dmabuf if supports_modifiers => {
+            let required_features = conv::map_texture_usage_to_features(usage);
+
+            get_drm_format_properties_list(instance, phd, format)
+                .iter()
+                .filter(|properties| {
+                    properties
+                        .drm_format_modifier_tiling_features
+                        .contains(required_features)
+                })
+                .filter_map(|properties| {
+                    get_external_image_format_properties(
+                        instance,
+                        phd,
+                        format,
+                        image_usage,
+                        vk::ExternalMemoryHandleTypeFlags::DMA_BUF_EXT,
+                        Some(properties.drm_format_modifier),
+                    )
+                })
+                .reduce(|a, b| ExternalMemoryProperties {
+                    capabilities: a.capabilities | b.capabilities,
+                    dedicated_only: a.dedicated_only || b.dedicated_only,
+                    prefers_dedicated: a.prefers_dedicated || b.prefers_dedicated,
+                })
+        }
+
+        // Host allocations may only be imported as buffers.
+        ExternalMemoryType::Dmabuf | ExternalMemoryType::HostAllocation => None,
+    }
+}
+
+/// Returns the external memory properties of images with the format, usage and handle type.
+///
+/// Images are queried with the modifier tiling if a modifier is specified, otherwise the optimal tiling.
+unsafe fn get_external_image_format_properties(
+    instance: &InstanceShared,
+    phd: vk::PhysicalDevice,
+    format: vk::Format,
+    usage: vk::ImageUsageFlags,
+    handle_type: vk::ExternalMemoryHandleTypeFlags,
+    modifier: Option<u64>,
+) -> Option<ExternalMemoryProperties> {
+    let tiling = match modifier {
+        Some(_) => vk::ImageTiling::DRM_FORMAT_MODIFIER_EXT,
+        None => vk::ImageTiling::OPTIMAL,
+    };
+
+    let mut modifier_info = vk::PhysicalDeviceImageDrmFormatModifierInfoEXT::builder()
+        .drm_format_modifier(modifier.unwrap_or_default())
+        .sharing_mode(vk::SharingMode::EXCLUSIVE);
+    let mut external_image_format_info =
+        vk::PhysicalDeviceExternalImageFormatInfo::builder().handle_type(handle_type);
+    let mut image_format_info = vk::PhysicalDeviceImageFormatInfo2::builder()
+        .format(format)
+        .ty(vk::ImageType::TYPE_2D)
+        .tiling(tiling)
+        .usage(usage)
+        .push_next(&mut external_image_format_info);
+
+    if modifier.is_some() {
+        image_format_info = image_format_info.push_next(&mut modifier_info);
+    }
+
+    let mut external_image_format_properties = vk::ExternalImageFormatProperties::default();
+    let mut image_format_properties =
+        vk::ImageFormatProperties2::builder().push_next(&mut external_image_format_properties);
+
+    // An error means the combination of format, usage, tiling and handle type is not supported.
+    get_physical_device_image_format_properties2(
+        instance.entry(),
+        instance.raw_instance(),
+        phd,
+        &image_format_info,
+        &mut image_format_properties,
+        instance.driver_api_version(),
+    )
+    .ok()?;
+
+    conv::map_external_memory_properties(
+        external_image_format_properties.external_memory_properties,
+    )
+}
+
+pub fn get_external_buffer_memory_capabilities(
+    adapter: Option<&<Vulkan as Api>::Adapter>,
+    handle_type: ExternalMemoryType,
+    usage: BufferUsages,
+) -> Option<ExternalMemoryProperties> {
+    let adapter = adapter.unwrap();
+    let shared = adapter.shared_instance();
+
+    if !supports_external_memory_capabilities(shared) {
+        return None;
+    }
+
+    let external_buffer_info = vk::PhysicalDeviceExternalBufferInfo::builder()
+        .usage(conv::map_buffer_usage(usage))
+        .handle_type(conv::map_external_memory_type(handle_type));
+    let mut external_buffer_properties = vk::ExternalBufferProperties::default();
+
+    // SAFETY: The instance uses Vulkan 1.1 or VK_KHR_external_memory_capabilities is enabled.
+    unsafe {
+        get_physical_device_external_buffer_properties(
+            shared.entry(),
+            shared.raw_instance(),
+            adapter.raw_physical_device(),
+            &external_buffer_info,
+            &mut external_buffer_properties,
+            shared.driver_api_version(),
+        )
+    };
+
+    conv::map_external_memory_properties(external_buffer_properties.external_memory_properties)
+}
+
+/// Whether the external memory capability queries are available on the instance.
+fn supports_external_memory_capabilities(instance: &InstanceShared) -> bool {
+    // In Vulkan 1.1, the external memory capability queries are part of the core api.
+    instance.driver_api_version() != vk::API_VERSION_1_0
+        || instance
+            .extensions()
+            .contains(&vk::KhrExternalMemoryCapabilitiesFn::name())
+}
+
+/// Whether the adapter supports all the device extensions required to import and export dmabufs.
+pub fn supports_dmabuf(adapter: &<Vulkan as Api>::Adapter) -> bool {
+    let version = device_api_version(adapter.shared_instance(), adapter.raw_physical_device());
+
+    supports_external_memory(adapter)
+        && supports_device_extensions(
+            adapter,
+            extensions_for_version(dmabuf::REQUIRED_DEVICE_EXTENSIONS, version),
+        )
+}
+
+/// Whether the adapter supports all the device extensions required to share textures and semaphores as opaque
+/// file descriptors.
+pub fn supports_opaque_fd(adapter: &<Vulkan as Api>::Adapter) -> bool {
+    let instance = adapter.shared_instance();
+    let version = device_api_version(instance, adapter.raw_physical_device());
+
+    // In Vulkan 1.1, the external semaphore capability queries are part of the core api.
+    let supports_external_semaphore_capabilities = instance.driver_api_version()
+        != vk::API_VERSION_1_0
+        || instance
+            .extensions()
+            .contains(&vk::KhrExternalSemaphoreCapabilitiesFn::name());
+
+    supports_external_memory(adapter)
+        && supports_external_semaphore_capabilities
+        && supports_device_extensions(
+            adapter,
+            extensions_for_version(interop::REQUIRED_DEVICE_EXTENSIONS, version),
+        )
+}
+
+/// Whether the instance and adapter support the extensions required to use any external memory.
+fn supports_external_memory(adapter: &<Vulkan as Api>::Adapter) -> bool {
+    let version = device_api_version(adapter.shared_instance(), adapter.raw_physical_device());
+
+    supports_external_memory_capabilities(adapter.shared_instance())
+        && supports_device_extensions(
+            adapter,
+            extensions_for_version(REQUIRED_DEVICE_EXTENSIONS, version),
+        )
+}
+
+/// Whether the adapter supports all the specified device extensions.
+fn supports_device_extensions<'a>(
+    adapter: &<Vulkan as Api>::Adapter,
+    mut required: impl Iterator<Item = &'a CStr>,
+) -> bool {
+    let extensions = match unsafe {
+        adapter
+            .shared_instance()
+            .raw_instance()
+            .enumerate_device_extension_properties(adapter.raw_physical_device())
+    } {
+        Ok(extensions) => extensions,
+        // Device was lost
+        Err(_) => return false,
+    };
+
+    required.all(|required| {
+        extensions.iter().any(|properties| {
+            let name = unsafe { CStr::from_ptr(&properties.extension_name as *const _) };
+            name == required
+        })
+    })
+}
+
+/// Whether the physical device supports the `samplerYcbcrConversion` feature.
+fn supports_sampler_ycbcr_conversion(instance: &InstanceShared, phd: vk::PhysicalDevice) -> bool {
+    let mut ycbcr_features = vk::PhysicalDeviceSamplerYcbcrConversionFeatures::default();
+    let mut features = vk::PhysicalDeviceFeatures2::builder().push_next(&mut ycbcr_features);
+
+    // wgpu requires VK_KHR_get_physical_device_properties2, no need to check for the extension.
+    unsafe {
+        get_physical_device_features2(
+            instance.entry(),
+            instance.raw_instance(),
+            phd,
+            &mut features,
+            instance.driver_api_version(),
+        )
+    };
+
+    ycbcr_features.sampler_ycbcr_conversion == vk::TRUE
+}
+
 fn get_adapter_drm_info(
     adapter: &<Vulkan as Api>::Adapter,
 ) -> Option<vk::PhysicalDeviceDrmPropertiesEXT> {
@@ -290,6 +606,75 @@ unsafe fn get_physical_device_properties2(
     }
 }
 
+unsafe fn get_physical_device_features2(
+    entry: &ash::Entry,
+    instance: &ash::Instance,
+    phd: vk::PhysicalDevice,
+    features: &mut vk::PhysicalDeviceFeatures2,
+    version: u32,
+) {
+    if version > vk::API_VERSION_1_0 {
+        instance.get_physical_device_features2(phd, features)
+    } else {
+        // Load the extension function
+        let fns = GetPhysicalDeviceProperties2::new(entry, instance);
+        fns.get_physical_device_features2(phd, features)
+    }
+}
+
+unsafe fn get_physical_device_image_format_properties2(
+    entry: &ash::Entry,
+    instance: &ash::Instance,
+    phd: vk::PhysicalDevice,
+    format_info: &vk::PhysicalDeviceImageFormatInfo2,
+    properties: &mut vk::ImageFormatProperties2,
+    version: u32,
+) -> ash::prelude::VkResult<()> {
+    if version > vk::API_VERSION_1_0 {
+        instance.get_physical_device_image_format_properties2(phd, format_info, properties)
+    } else {
+        // Load the extension function
+        let fns = GetPhysicalDeviceProperties2::new(entry, instance);
+        fns.get_physical_device_image_format_properties2(phd, format_info, properties)
+    }
+}
+
+unsafe fn get_physical_device_format_properties2(
+    entry: &ash::Entry,
+    instance: &ash::Instance,
+    phd: vk::PhysicalDevice,
+    format: vk::Format,
+    properties: &mut vk::FormatProperties2,
+    version: u32,
+) {
+    if version > vk::API_VERSION_1_0 {
+        instance.get_physical_device_format_properties2(phd, format, properties)
+    } else {
+        // Load the extension function
+        let fns = GetPhysicalDeviceProperties2::new(entry, instance);
+        fns.get_physical_device_format_properties2(phd, format, properties)
+    }
+}
+
+unsafe fn get_physical_device_external_buffer_properties(
+    entry: &ash::Entry,
+    instance: &ash::Instance,
+    phd: vk::PhysicalDevice,
+    buffer_info: &vk::PhysicalDeviceExternalBufferInfo,
+    properties: &mut vk::ExternalBufferProperties,
+    version: u32,
+) {
+    if version > vk::API_VERSION_1_0 {
+        instance.get_physical_device_external_buffer_properties(phd, buffer_info, properties)
+    } else {
+        // Load the extension function
+        let fns = vk::KhrExternalMemoryCapabilitiesFn::load(|name| {
+            mem::transmute(entry.get_instance_proc_addr(instance.handle(), name.as_ptr()))
+        });
+        (fns.get_physical_device_external_buffer_properties_khr)(phd, buffer_info, properties)
+    }
+}
+
 pub fn request_device(
     adapter: &Adapter,
     desc: &DeviceDescriptor,
@@ -303,42 +688,62 @@ pub fn request_device(
     }
     .map_err(|_| RequestDeviceError)?;
 
+    let uuids = adapter.uuids();
     let (device, queue) = unsafe { adapter.create_device_from_hal(hal_device, &desc, trace_path) }?;
 
     let inner = unsafe {
         device.as_hal::<Vulkan, _, _>(|device| {
             let device = device.unwrap();
             let raw_device = device.raw_device();
-            let raw_instance = device.shared_instance().raw_instance();
 
             DeviceInner::Vulkan(Inner::new(
-                raw_instance,
+                device.shared_instance(),
                 device.raw_physical_device(),
                 raw_device,
+                device.enabled_device_extensions(),
             ))
         })
     };
 
-    let device = ExternalMemoryDevice { device, inner };
+    let device = ExternalMemoryDevice {
+        device,
+        inner,
+        uuids,
+    };
 
     Ok((device, queue))
 }
 
 /// Instance extensions required to use external memory.
-const REQUIRED_INSTANCE_EXTENSIONS: &[&CStr] = &[
+const REQUIRED_INSTANCE_EXTENSIONS: &[Extension] = &[
     // dependency of VK_KHR_external_memory
-    //
-    // This is required or 1.1
-    vk::KhrExternalMemoryCapabilitiesFn::name(),
+    (
+        vk::KhrExternalMemoryCapabilitiesFn::name(),
+        Some(vk::API_VERSION_1_1),
+    ),
+    // dependency of VK_KHR_external_semaphore
+    (
+        vk::KhrExternalSemaphoreCapabilitiesFn::name(),
+        Some(vk::API_VERSION_1_1),
+    ),
     // WGPU requires VK_KHR_physical_device_properties2
     //
     // Listed for completeness
-    vk::KhrGetPhysicalDeviceProperties2Fn::name(),
+    (
+        vk::KhrGetPhysicalDeviceProperties2Fn::name(),
+        Some(vk::API_VERSION_1_1),
+    ),
 ];
 
 /// Device extensions required to use all external memory handles.
-const REQUIRED_DEVICE_EXTENSIONS: &[&CStr] = &[
-    KhrExternalMemoryFn::name(), // Or 1.1
+const REQUIRED_DEVICE_EXTENSIONS: &[Extension] = &[
+    (KhrExternalMemoryFn::name(), Some(vk::API_VERSION_1_1)),
+    // Many drivers require dedicated allocations for external images.
+    (
+        KhrGetMemoryRequirements2Fn::name(),
+        Some(vk::API_VERSION_1_1),
+    ),
+    (KhrDedicatedAllocationFn::name(), Some(vk::API_VERSION_1_1)),
 ];
 
 pub trait VulkanAdapterExt: Sized {
@@ -358,31 +763,52 @@ impl VulkanAdapterExt for <Vulkan as Api>::Adapter {
         let phd_limits = self.physical_device_capabilities().properties().limits;
         let uab_types = UpdateAfterBindTypes::from_limits(limits, &phd_limits);
         let mut enabled_extensions = self.required_device_extensions(features);
+        let version = device_api_version(self.shared_instance(), self.raw_physical_device());
 
-        // Test that all required instance extensions are available
-        let instance_extensions = self.shared_instance().extensions();
-        let iter = REQUIRED_INSTANCE_EXTENSIONS
-            .iter()
-            .filter(|&&req_extension| {
-                !instance_extensions
-                    .iter()
-                    .any(|&name| name == req_extension)
-            })
-            .collect::<Vec<_>>();
+        // The device is still opened without external memory if the instance or adapter lacks the required
+        // extensions.
+        let supports_external_memory = supports_external_memory(self);
 
-        if !iter.is_empty() {
-            for &&missing in &iter {
-                log::error!("Missing instance extension {:?}", missing);
+        if supports_external_memory {
+            for extension in extensions_for_version(REQUIRED_DEVICE_EXTENSIONS, version) {
+                if !enabled_extensions.contains(&extension) {
+                    enabled_extensions.push(extension);
+                }
             }
-
-            panic!("Missing required instance extensions")
+        } else {
+            log::warn!("External memory is not supported by the adapter");
         }
 
-        // Extensions for external memory
-        enabled_extensions.extend(REQUIRED_DEVICE_EXTENSIONS);
-
         // TODO: All handle types
-        // TODO: Enable dmabuf device extensions
+
+        // Dmabuf import and export is only enabled if all the extensions are available.
+        let supports_dmabuf = supports_dmabuf(self);
+
+        if supports_dmabuf {
+            for extension in extensions_for_version(dmabuf::REQUIRED_DEVICE_EXTENSIONS, version) {
+                if !enabled_extensions.contains(&extension) {
+                    enabled_extensions.push(extension);
+                }
+            }
+
+            for extension in extensions_for_version(layout::OPTIONAL_DEVICE_EXTENSIONS, version) {
+                if !enabled_extensions.contains(&extension)
+                    && supports_device_extensions(self, iter::once(extension))
+                {
+                    enabled_extensions.push(extension);
+                }
+            }
+        }
+
+        // Sharing textures and semaphores as opaque file descriptors is only enabled if all the extensions are
+        // available.
+        if supports_opaque_fd(self) {
+            for extension in extensions_for_version(interop::REQUIRED_DEVICE_EXTENSIONS, version) {
+                if !enabled_extensions.contains(&extension) {
+                    enabled_extensions.push(extension);
+                }
+            }
+        }
 
         let mut enabled_phd_features =
             self.physical_device_features(&enabled_extensions, features, uab_types);
@@ -402,9 +828,20 @@ impl VulkanAdapterExt for <Vulkan as Api>::Adapter {
             .build();
         let family_infos = [family_info];
 
-        let pre_info = vk::DeviceCreateInfo::builder()
+        let mut pre_info = vk::DeviceCreateInfo::builder()
             .queue_create_infos(&family_infos)
             .enabled_extension_names(&str_pointers);
+
+        // Sampling multi-planar dmabufs requires a sampler YCbCr conversion.
+        let mut ycbcr_features = vk::PhysicalDeviceSamplerYcbcrConversionFeatures::builder()
+            .sampler_ycbcr_conversion(true);
+
+        if supports_dmabuf
+            && supports_sampler_ycbcr_conversion(self.shared_instance(), self.raw_physical_device())
+        {
+            pre_info = pre_info.push_next(&mut ycbcr_features);
+        }
+
         let info = enabled_phd_features
             .add_to_device_create_builder(pre_info)
             .build();
@@ -431,43 +868,250 @@ impl VulkanAdapterExt for <Vulkan as Api>::Adapter {
 
 pub struct Inner {
     pub external_memory_fd: ExternalMemoryFd,
+    pub external_semaphore_fd: ExternalSemaphoreFd,
     pub image_drm_format_modifier: ImageDrmFormatModifier,
+    pub promoted_fns: PromotedDeviceFns,
+    /// Names imported and exported objects if `VK_EXT_debug_utils` is enabled.
+    pub debug_utils: Option<DebugUtils>,
+    pub memory_properties: vk::PhysicalDeviceMemoryProperties,
+    /// Whether the device extensions required to import and export dmabufs are enabled.
+    pub supports_dmabuf: bool,
+    /// Whether the device extensions required to share textures and semaphores as opaque file descriptors are
+    /// enabled.
+    pub supports_opaque_fd: bool,
+    /// Whether the `samplerYcbcrConversion` feature is enabled.
+    pub supports_ycbcr_conversion: bool,
+    /// Whether `VK_EXT_queue_family_foreign` is enabled.
+    pub supports_queue_family_foreign: bool,
+    /// Whether `VK_EXT_external_memory_acquire_unmodified` is enabled.
+    pub supports_acquire_unmodified: bool,
     pub supported_drm_formats: HashMap<DrmFormat, vk::DrmFormatModifierPropertiesEXT>,
+    /// The layouts of the imported and exported dmabuf textures.
+    pub external_textures: Arc<Mutex<HashMap<vk::Image, layout::ExternalTexture>>>,
+    /// Created when the first ownership transfer is submitted.
+    pub barrier_queue: Mutex<Option<BarrierQueue>>,
+    /// The buffer texels are copied to when moving a texture to a known layout, created when first needed.
+    pub pin_buffer: Mutex<Option<wgpu::Buffer>>,
 }
 
 impl Inner {
-    pub fn new(instance: &ash::Instance, phd: vk::PhysicalDevice, device: &ash::Device) -> Self {
-        let external_memory_fd = ExternalMemoryFd::new(instance, device);
-        let image_drm_format_modifier = ImageDrmFormatModifier::new(instance, device);
+    pub fn new(
+        instance: &InstanceShared,
+        phd: vk::PhysicalDevice,
+        device: &ash::Device,
+        enabled_extensions: &[&CStr],
+    ) -> Self {
+        let raw_instance = instance.raw_instance();
+        let version = device_api_version(instance, phd);
+        let external_memory_fd = ExternalMemoryFd::new(raw_instance, device);
+        let external_semaphore_fd = ExternalSemaphoreFd::new(raw_instance, device);
+        let image_drm_format_modifier = ImageDrmFormatModifier::new(raw_instance, device);
+        let promoted_fns = PromotedDeviceFns::new(raw_instance, device, version);
+        let debug_utils = DebugUtils::new(instance);
+        let memory_properties = unsafe { raw_instance.get_physical_device_memory_properties(phd) };
+        let supports_dmabuf = extensions_for_version(dmabuf::REQUIRED_DEVICE_EXTENSIONS, version)
+            .all(|extension| enabled_extensions.contains(&extension));
+        let supports_opaque_fd =
+            extensions_for_version(interop::REQUIRED_DEVICE_EXTENSIONS, version)
+                .all(|extension| enabled_extensions.contains(&extension));
+        let supports_queue_family_foreign =
+            enabled_extensions.contains(&vk::ExtQueueFamilyForeignFn::name());
+        let supports_acquire_unmodified =
+            enabled_extensions.contains(&layout::EXTERNAL_MEMORY_ACQUIRE_UNMODIFIED_NAME);
+        // The feature is enabled when opening the device if dmabufs are supported.
+        let supports_ycbcr_conversion =
+            supports_dmabuf && supports_sampler_ycbcr_conversion(instance, phd);
 
         let mut supported_drm_formats = HashMap::new();
 
-        // TODO: More formats
-        {
-            let modifier_properties =
-                unsafe { get_drm_format_properties_list(instance, phd, vk::Format::B8G8R8A8_SRGB) };
-
-            for properties in modifier_properties {
-                let drm_format = DrmFormat {
-                    code: DrmFourcc::Argb8888,
-                    modifier: DrmModifier::from(properties.drm_format_modifier),
-                };
+        // The modifiers of a format can only be queried if VK_EXT_image_drm_format_modifier is enabled.
+        if supports_dmabuf {
+            for &(code, format) in conv::DRM_FORMATS {
+                let modifier_properties =
+                    unsafe { get_drm_format_properties_list(instance, phd, format) };
 
-                supported_drm_formats.insert(drm_format, properties);
+                for properties in modifier_properties {
+                    let drm_format = DrmFormat {
+                        code,
+                        modifier: DrmModifier::from(properties.drm_format_modifier),
+                    };
 
-                let drm_format = DrmFormat {
-                    code: DrmFourcc::Xrgb8888,
-                    modifier: DrmModifier::from(properties.drm_format_modifier),
-                };
-
-                supported_drm_formats.insert(drm_format, properties);
+                    supported_drm_formats.insert(drm_format, properties);
+                }
             }
         }
 
         Self {
             external_memory_fd,
+            external_semaphore_fd,
             image_drm_format_modifier,
+            promoted_fns,
+            debug_utils,
+            memory_properties,
+            supports_dmabuf,
+            supports_opaque_fd,
+            supports_ycbcr_conversion,
+            supports_queue_family_foreign,
+            supports_acquire_unmodified,
             supported_drm_formats,
+            external_textures: Arc::new(Mutex::new(HashMap::new())),
+            barrier_queue: Mutex::new(None),
+            pin_buffer: Mutex::new(None),
+        }
+    }
+}
+
+impl Inner {
+    /// Names a Vulkan object if `VK_EXT_debug_utils` is enabled.
+    pub unsafe fn set_object_name<H: vk::Handle>(
+        &self,
+        device: &ash::Device,
+        object: H,
+        name: &str,
+    ) {
+        if let Some(debug_utils) = &self.debug_utils {
+            debug_utils.set_object_name(device, object, name);
+        }
+    }
+
+    /// Returns the memory requirements of an image and whether the image should be bound to a dedicated
+    /// allocation.
+    ///
+    /// Disjoint images can not be bound to a dedicated allocation, so this must not be used for them.
+    pub unsafe fn image_memory_requirements(
+        &self,
+        image: vk::Image,
+    ) -> (vk::MemoryRequirements, bool) {
+        let (memory_requirements, dedicated_requirements) =
+            self.image_dedicated_requirements(image);
+        // Drivers which prefer a dedicated allocation may perform worse otherwise, such as by disabling
+        // compression.
+        let dedicated = dedicated_requirements.requires_dedicated_allocation == vk::TRUE
+            || dedicated_requirements.prefers_dedicated_allocation == vk::TRUE;
+
+        (memory_requirements, dedicated)
+    }
+
+    unsafe fn image_dedicated_requirements(
+        &self,
+        image: vk::Image,
+    ) -> (vk::MemoryRequirements, vk::MemoryDedicatedRequirements) {
+        let mut dedicated_requirements = vk::MemoryDedicatedRequirements::default();
+        let requirements_info = vk::ImageMemoryRequirementsInfo2::builder().image(image);
+        let mut requirements =
+            vk::MemoryRequirements2::builder().push_next(&mut dedicated_requirements);
+        self.promoted_fns
+            .get_image_memory_requirements2(&requirements_info, &mut requirements);
+
+        (requirements.memory_requirements, dedicated_requirements)
+    }
+
+    /// Returns the external memory properties of a texture with the descriptor.
+    ///
+    /// Drivers only report whether they prefer a dedicated allocation for a specific image, so an image is
+    /// created the same way as the images of exported textures and destroyed afterwards.
+    pub fn external_memory_capabilities(
+        &self,
+        device: &wgpu::Device,
+        handle_type: ExternalMemoryType,
+        desc: &TextureDescriptor,
+    ) -> Option<ExternalMemoryProperties> {
+        let handle_types = match handle_type {
+            ExternalMemoryType::Dmabuf if self.supports_dmabuf => {
+                vk::ExternalMemoryHandleTypeFlags::DMA_BUF_EXT
+            }
+            ExternalMemoryType::OpaqueFd if self.supports_opaque_fd => {
+                vk::ExternalMemoryHandleTypeFlags::OPAQUE_FD
+            }
+            _ => return None,
+        };
+
+        // Only single 2D images may be imported or exported.
+        if desc.dimension != TextureDimension::D2
+            || desc.size.depth_or_array_layers != 1
+            || desc.mip_level_count != 1
+            || desc.sample_count != 1
+            || desc.usage.is_empty()
+        {
+            return None;
+        }
+
+        let format = conv::map_texture_format(desc.format)?;
+
+        unsafe {
+            device.as_hal::<Vulkan, _, _>(|hal_device| {
+                let hal_device = hal_device.unwrap();
+                let instance = hal_device.shared_instance();
+                let phd = hal_device.raw_physical_device();
+                let raw_device = hal_device.raw_device();
+
+                let mut properties = get_external_image_properties(
+                    instance,
+                    phd,
+                    self.supports_dmabuf,
+                    handle_type,
+                    desc.format,
+                    desc.usage,
+                )?;
+
+                if properties.dedicated_only {
+                    return Some(properties);
+                }
+
+                // The modifiers of a format can only be queried if dmabufs are supported.
+                let modifiers = if handle_type == ExternalMemoryType::Dmabuf {
+                    let required_features = conv::map_texture_usage_to_features(desc.usage);
+
+                    get_drm_format_properties_list(instance, phd, format)
+                        .iter()
+                        .filter(|properties| {
+                            properties
+                                .drm_format_modifier_tiling_features
+                                .contains(required_features)
+                        })
+                        .map(|properties| properties.drm_format_modifier)
+                        .collect::<Vec<_>>()
+                } else {
+                    Vec::new()
+                };
+
+                let mut modifier_list = vk::ImageDrmFormatModifierListCreateInfoEXT::builder()
+                    .drm_format_modifiers(&modifiers);
+                let mut external_memory_image =
+                    vk::ExternalMemoryImageCreateInfo::builder().handle_types(handle_types);
+                let mut create_info = vk::ImageCreateInfo::builder()
+                    .image_type(vk::ImageType::TYPE_2D)
+                    .format(format)
+                    .extent(vk::Extent3D {
+                        width: desc.size.width,
+                        height: desc.size.height,
+                        depth: 1,
+                    })
+                    .mip_levels(1)
+                    .array_layers(1)
+                    .samples(vk::SampleCountFlags::TYPE_1)
+                    .tiling(vk::ImageTiling::OPTIMAL)
+                    .usage(conv::map_texture_usage(desc.usage))
+                    .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                    .initial_layout(vk::ImageLayout::UNDEFINED)
+                    .push_next(&mut external_memory_image);
+
+                if handle_type == ExternalMemoryType::Dmabuf {
+                    create_info = create_info
+                        .tiling(vk::ImageTiling::DRM_FORMAT_MODIFIER_EXT)
+                        .push_next(&mut modifier_list);
+                }
+
+                let image = raw_device.create_image(&create_info, None).ok()?;
+                let (_, dedicated_requirements) = self.image_dedicated_requirements(image);
+                raw_device.destroy_image(image, None);
+
+                properties.prefers_dedicated = dedicated_requirements.requires_dedicated_allocation
+                    == vk::TRUE
+                    || dedicated_requirements.prefers_dedicated_allocation == vk::TRUE;
+
+                Some(properties)
+            })
         }
     }
 }
@@ -475,13 +1119,43 @@ impl Inner {
 impl fmt::Debug for Inner {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("Inner")
+            .field("supports_dmabuf", &self.supports_dmabuf)
+            .field("supports_opaque_fd", &self.supports_opaque_fd)
+            .field("supports_ycbcr_conversion", &self.supports_ycbcr_conversion)
+            .field(
+                "supports_queue_family_foreign",
+                &self.supports_queue_family_foreign,
+            )
+            .field(
+                "supports_acquire_unmodified",
+                &self.supports_acquire_unmodified,
+            )
             .field("supported_drm_formats", &self.supported_drm_formats)
             .finish()
     }
 }
 
+/// Returns the index of a memory type allowed by `type_bits`, preferring memory types with the specified
+/// property flags.
+pub fn find_memory_type_index(
+    memory_properties: &vk::PhysicalDeviceMemoryProperties,
+    type_bits: u32,
+    preferred_flags: vk::MemoryPropertyFlags,
+) -> Option<u32> {
+    let memory_types =
+        &memory_properties.memory_types[..memory_properties.memory_type_count as usize];
+    let is_allowed = |index: usize| type_bits & (1 << index) != 0;
+
+    memory_types
+        .iter()
+        .enumerate()
+        .position(|(index, ty)| is_allowed(index) && ty.property_flags.contains(preferred_flags))
+        .or_else(|| (0..memory_types.len()).find(|&index| is_allowed(index)))
+        .map(|index| index as u32)
+}
+
 pub unsafe fn get_drm_format_properties_list(
-    instance: &ash::Instance,
+    instance: &InstanceShared,
     pdevice: vk::PhysicalDevice,
     format: vk::Format,
 ) -> Vec<vk::DrmFormatModifierPropertiesEXT> {
@@ -491,7 +1165,14 @@ pub unsafe fn get_drm_format_properties_list(
     {
         let mut format_properties_2 = vk::FormatProperties2::builder().push_next(&mut list);
 
-        instance.get_physical_device_format_properties2(pdevice, format, &mut format_properties_2);
+        get_physical_device_format_properties2(
+            instance.entry(),
+            instance.raw_instance(),
+            pdevice,
+            format,
+            &mut format_properties_2,
+            instance.driver_api_version(),
+        );
     }
 
     let mut data = Vec::with_capacity(list.drm_format_modifier_count as usize);
@@ -502,7 +1183,14 @@ pub unsafe fn get_drm_format_properties_list(
     {
         let mut format_properties_2 = vk::FormatProperties2::builder().push_next(&mut list);
 
-        instance.get_physical_device_format_properties2(pdevice, format, &mut format_properties_2);
+        get_physical_device_format_properties2(
+            instance.entry(),
+            instance.raw_instance(),
+            pdevice,
+            format,
+            &mut format_properties_2,
+            instance.driver_api_version(),
+        );
     }
 
     data.set_len(list.drm_format_modifier_count as usize);
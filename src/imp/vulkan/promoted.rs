@@ -0,0 +1,126 @@
+//! Functionality which was promoted to the core Vulkan api.
+//!
+//! Extensions promoted to core do not need to be enabled if the instance or device supports the Vulkan version
+//! they were promoted to. Drivers are not required to advertise promoted extensions, so the core entry points
+//! must be used instead.
+
+use std::{
+    ffi::{CStr, CString},
+    mem, ptr,
+};
+
+use ash::{prelude::*, vk};
+use wgpu_hal::vulkan::InstanceShared;
+
+/// An extension and the Vulkan version it was promoted to, if the extension was promoted to core.
+pub type Extension = (&'static CStr, Option<u32>);
+
+/// Returns the extensions which must be enabled, since they are not part of the core api of the Vulkan version.
+pub fn extensions_for_version(
+    extensions: &'static [Extension],
+    version: u32,
+) -> impl Iterator<Item = &'static CStr> {
+    extensions
+        .iter()
+        .filter(move |(_, promoted)| match promoted {
+            Some(promoted) => version < *promoted,
+            None => true,
+        })
+        .map(|&(name, _)| name)
+}
+
+/// The Vulkan version of the device level functionality which may be used with the physical device.
+pub fn device_api_version(instance: &InstanceShared, phd: vk::PhysicalDevice) -> u32 {
+    // The instance is created with the Vulkan 1.0 api version if the driver only supports Vulkan 1.0, which
+    // limits the device to Vulkan 1.0.
+    if instance.driver_api_version() < vk::API_VERSION_1_1 {
+        return vk::API_VERSION_1_0;
+    }
+
+    let properties = unsafe { instance.raw_instance().get_physical_device_properties(phd) };
+    properties.api_version.min(vk::HEADER_VERSION_COMPLETE)
+}
+
+/// Device functions promoted to Vulkan 1.1.
+///
+/// The core entry points are used if the device supports Vulkan 1.1, otherwise the entry points of the `KHR`
+/// extensions are used.
+#[derive(Clone)]
+pub struct PromotedDeviceFns {
+    handle: vk::Device,
+    bind_memory2: vk::KhrBindMemory2Fn,
+    get_memory_requirements2: vk::KhrGetMemoryRequirements2Fn,
+    sampler_ycbcr_conversion: vk::KhrSamplerYcbcrConversionFn,
+}
+
+impl PromotedDeviceFns {
+    pub fn new(instance: &ash::Instance, device: &ash::Device, version: u32) -> Self {
+        let handle = device.handle();
+        let load = |name: &CStr| {
+            // The core entry points have the same name without the extension suffix.
+            let name = match name.to_bytes().strip_suffix(b"KHR") {
+                Some(core_name) if version >= vk::API_VERSION_1_1 => {
+                    CString::new(core_name).unwrap()
+                }
+                _ => name.to_owned(),
+            };
+
+            unsafe { mem::transmute(instance.get_device_proc_addr(handle, name.as_ptr())) }
+        };
+
+        Self {
+            handle,
+            bind_memory2: vk::KhrBindMemory2Fn::load(load),
+            get_memory_requirements2: vk::KhrGetMemoryRequirements2Fn::load(load),
+            sampler_ycbcr_conversion: vk::KhrSamplerYcbcrConversionFn::load(load),
+        }
+    }
+
+    /// <https://www.khronos.org/registry/vulkan/specs/1.3-extensions/man/html/vkBindImageMemory2.html>
+    pub unsafe fn bind_image_memory2(
+        &self,
+        bind_infos: &[vk::BindImageMemoryInfo],
+    ) -> VkResult<()> {
+        (self.bind_memory2.bind_image_memory2_khr)(
+            self.handle,
+            bind_infos.len() as u32,
+            bind_infos.as_ptr(),
+        )
+        .result()
+    }
+
+    /// <https://www.khronos.org/registry/vulkan/specs/1.3-extensions/man/html/vkGetImageMemoryRequirements2.html>
+    pub unsafe fn get_image_memory_requirements2(
+        &self,
+        info: &vk::ImageMemoryRequirementsInfo2,
+        requirements: &mut vk::MemoryRequirements2,
+    ) {
+        (self
+            .get_memory_requirements2
+            .get_image_memory_requirements2_khr)(self.handle, info, requirements)
+    }
+
+    /// <https://www.khronos.org/registry/vulkan/specs/1.3-extensions/man/html/vkCreateSamplerYcbcrConversion.html>
+    pub unsafe fn create_sampler_ycbcr_conversion(
+        &self,
+        create_info: &vk::SamplerYcbcrConversionCreateInfo,
+    ) -> VkResult<vk::SamplerYcbcrConversion> {
+        let mut conversion = vk::SamplerYcbcrConversion::null();
+        (self
+            .sampler_ycbcr_conversion
+            .create_sampler_ycbcr_conversion_khr)(
+            self.handle,
+            create_info,
+            ptr::null(),
+            &mut conversion,
+        )
+        .result_with_success(conversion)
+    }
+
+    /// <https://www.khronos.org/registry/vulkan/specs/1.3-extensions/man/html/vkDestroySamplerYcbcrConversion.html>
+    pub unsafe fn destroy_sampler_ycbcr_conversion(&self, conversion: vk::SamplerYcbcrConversion) {
+        (self
+            .sampler_ycbcr_conversion
+            .destroy_sampler_ycbcr_conversion_khr)(self.handle, conversion, ptr::null())
+    }
+}
@@ -1,41 +1,95 @@
-use wgpu::Instance;
-use wgpu_hal::{InstanceDescriptor, InstanceFlags};
+use std::ffi::CStr;
+
+use wgpu::{Backends, Instance};
+use wgpu_hal::InstanceFlags;
 
 use crate::imp::{egl, vulkan};
 
+/// Describes an [`Instance`] that supports adapters that support DRM extensions.
+#[derive(Debug, Clone)]
+pub struct DrmInstanceDescriptor<'a> {
+    /// Which backends the instance may create adapters for.
+    ///
+    /// Only [`Backends::VULKAN`] and [`Backends::GL`] are supported, any other backends are ignored.
+    pub backends: Backends,
+
+    /// Flags to enable validation and debugging features of the backends.
+    pub flags: InstanceFlags,
+
+    /// The name of the application.
+    ///
+    /// - Vulkan: Equivalent to `pApplicationName` in `VkApplicationInfo`. No Vulkan instance is created if the
+    ///   name contains a nul byte.
+    pub application_name: &'a str,
+
+    /// The version of the application.
+    ///
+    /// - Vulkan: Equivalent to `applicationVersion` in `VkApplicationInfo`
+    pub application_version: u32,
+
+    /// Additional Vulkan instance extensions to enable.
+    ///
+    /// Extensions which are not available are ignored.
+    pub instance_extensions: &'a [&'static CStr],
+}
+
+impl Default for DrmInstanceDescriptor<'_> {
+    /// Enables the Vulkan and GL backends, and validation in debug builds.
+    fn default() -> Self {
+        let flags = if cfg!(debug_assertions) {
+            InstanceFlags::VALIDATION | InstanceFlags::DEBUG
+        } else {
+            InstanceFlags::empty()
+        };
+
+        Self {
+            backends: Backends::VULKAN | Backends::GL,
+            flags,
+            application_name: "wgpu-drm",
+            application_version: 1,
+            instance_extensions: &[],
+        }
+    }
+}
+
 /// Extension trait for creating an [`Instance`] that supports adapters that support DRM extensions.
 pub trait InstanceExt: Sized {
     /// Create an new instance of wgpu capable of creating adapters that support DRM extensions.
+    ///
+    /// This is equivalent to calling [`InstanceExt::with_drm_descriptor`] with the default descriptor.
     fn with_drm() -> Instance;
+
+    /// Create an new instance of wgpu capable of creating adapters that support DRM extensions using the
+    /// specified descriptor.
+    fn with_drm_descriptor(desc: &DrmInstanceDescriptor) -> Instance;
 }
 
 impl InstanceExt for Instance {
     fn with_drm() -> Instance {
-        // Create the wgpu_core::Instance with Vulkan and EGL
+        Self::with_drm_descriptor(&DrmInstanceDescriptor::default())
+    }
+
+    fn with_drm_descriptor(desc: &DrmInstanceDescriptor) -> Instance {
+        let vulkan = if desc.backends.contains(Backends::VULKAN) {
+            vulkan::try_create_instance(desc)
+        } else {
+            None
+        };
+
+        let gl = if desc.backends.contains(Backends::GL) {
+            egl::try_create_instance(desc)
+        } else {
+            None
+        };
+
+        // Create the wgpu_core::Instance with the requested backends
         let instance = wgpu_core::instance::Instance {
-            name: "wgpu-drm".into(),
-            vulkan: vulkan::try_create_instance(INSTANCE_DESC),
-            gl: egl::try_create_instance(INSTANCE_DESC),
+            name: desc.application_name.into(),
+            vulkan,
+            gl,
         };
 
         // SAFETY: We initialized the instances ourselves and any hal backend safety requirements have been satisfied.
         unsafe { Instance::from_core(instance) }
     }
 }
-
-const INSTANCE_DESC: InstanceDescriptor = {
-    let mut flags = InstanceFlags::empty();
-
-    if cfg!(debug_assertions) {
-        // impl const for traits is not stable. To make this const, convert the constants to bits and the
-        // flags from bits.
-        flags = InstanceFlags::from_bits_truncate(
-            InstanceFlags::VALIDATION.bits() | InstanceFlags::DEBUG.bits(),
-        );
-    }
-
-    InstanceDescriptor {
-        name: "wgpu-drm",
-        flags,
-    }
-};
@@ -0,0 +1,189 @@
+//! Helpers for presenting exported textures using KMS.
+
+use std::{
+    io,
+    os::unix::io::{AsFd, AsRawFd, OwnedFd},
+};
+
+use drm_fourcc::DrmModifier;
+
+use crate::dmabuf::{Dmabuf, ExportedTexture};
+
+/// Error returned when creating a [`Framebuffer`].
+#[derive(Debug, thiserror::Error)]
+pub enum FramebufferError {
+    /// KMS framebuffers may have at most 4 planes.
+    #[error("the dmabuf has {0} planes, at most 4 planes are supported")]
+    TooManyPlanes(usize),
+
+    /// A plane of the dmabuf could not be converted to a GEM handle.
+    #[error("failed to import dmabuf plane {plane}: {source}")]
+    PrimeImport { plane: usize, source: nix::Error },
+
+    /// The framebuffer could not be created.
+    #[error("failed to create framebuffer: {0}")]
+    AddFramebuffer(nix::Error),
+
+    /// The DRM file descriptor could not be duplicated.
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// A KMS framebuffer created from a dmabuf.
+///
+/// The framebuffer and the GEM handles of the dmabuf planes are destroyed when this is dropped.
+#[derive(Debug)]
+pub struct Framebuffer {
+    drm: OwnedFd,
+    fb_id: u32,
+    handles: Vec<u32>,
+}
+
+impl Framebuffer {
+    /// Creates a framebuffer from the dmabuf of an exported texture.
+    ///
+    /// The DRM file descriptor must be for a primary node of a device which is capable of importing the
+    /// dmabuf.
+    pub fn new(drm: &impl AsFd, texture: &ExportedTexture) -> Result<Self, FramebufferError> {
+        Self::from_dmabuf(drm, texture.dmabuf())
+    }
+
+    /// Creates a framebuffer from a dmabuf.
+    ///
+    /// The DRM file descriptor must be for a primary node of a device which is capable of importing the
+    /// dmabuf.
+    pub fn from_dmabuf(drm: &impl AsFd, dmabuf: &Dmabuf) -> Result<Self, FramebufferError> {
+        if dmabuf.planes.len() > 4 {
+            return Err(FramebufferError::TooManyPlanes(dmabuf.planes.len()));
+        }
+
+        // GEM handles belong to the open file description, so a duplicate of the file descriptor may be used
+        // to release the handles later.
+        let drm = drm.as_fd().try_clone_to_owned()?;
+
+        let mut framebuffer = Self {
+            drm,
+            fb_id: 0,
+            handles: Vec::with_capacity(dmabuf.planes.len()),
+        };
+
+        let mut cmd = ioctl::drm_mode_fb_cmd2 {
+            width: dmabuf.width,
+            height: dmabuf.height,
+            pixel_format: dmabuf.format.code as u32,
+            ..Default::default()
+        };
+
+        // DRM_FORMAT_MOD_INVALID means the modifier is implicit.
+        if dmabuf.format.modifier != DrmModifier::Invalid {
+            cmd.flags |= ioctl::DRM_MODE_FB_MODIFIERS;
+        }
+
+        for (index, plane) in dmabuf.planes.iter().enumerate() {
+            let mut prime = ioctl::drm_prime_handle {
+                fd: plane.fd.as_raw_fd(),
+                ..Default::default()
+            };
+
+            // If any step fails, dropping the framebuffer will close the handles created so far.
+            unsafe { ioctl::prime_fd_to_handle(framebuffer.drm.as_raw_fd(), &mut prime) }.map_err(
+                |source| FramebufferError::PrimeImport {
+                    plane: index,
+                    source,
+                },
+            )?;
+
+            // Importing planes stored in the same dmabuf return the same handle, which must only be closed once.
+            if !framebuffer.handles.contains(&prime.handle) {
+                framebuffer.handles.push(prime.handle);
+            }
+
+            cmd.handles[index] = prime.handle;
+            cmd.pitches[index] = plane.stride;
+            cmd.offsets[index] = plane.offset;
+
+            if cmd.flags & ioctl::DRM_MODE_FB_MODIFIERS != 0 {
+                cmd.modifier[index] = u64::from(dmabuf.format.modifier);
+            }
+        }
+
+        unsafe { ioctl::mode_addfb2(framebuffer.drm.as_raw_fd(), &mut cmd) }
+            .map_err(FramebufferError::AddFramebuffer)?;
+        framebuffer.fb_id = cmd.fb_id;
+
+        Ok(framebuffer)
+    }
+
+    /// The id of the framebuffer.
+    ///
+    /// This may be passed to KMS when setting the `FB_ID` property of a plane.
+    pub fn id(&self) -> u32 {
+        self.fb_id
+    }
+}
+
+impl Drop for Framebuffer {
+    fn drop(&mut self) {
+        let drm = self.drm.as_raw_fd();
+
+        if self.fb_id != 0 {
+            let mut fb_id = self.fb_id;
+
+            if let Err(err) = unsafe { ioctl::mode_rmfb(drm, &mut fb_id) } {
+                log::warn!("Failed to remove framebuffer {}: {}", self.fb_id, err);
+            }
+        }
+
+        for &handle in &self.handles {
+            let close = ioctl::drm_gem_close { handle, pad: 0 };
+
+            if let Err(err) = unsafe { ioctl::gem_close(drm, &close) } {
+                log::warn!("Failed to close GEM handle {}: {}", handle, err);
+            }
+        }
+    }
+}
+
+/// DRM ioctls from `drm.h` and `drm_mode.h`.
+#[allow(non_camel_case_types)]
+mod ioctl {
+    use nix::{ioctl_readwrite, ioctl_write_ptr};
+
+    const DRM_IOCTL_BASE: u8 = b'd';
+
+    pub const DRM_MODE_FB_MODIFIERS: u32 = 1 << 1;
+
+    #[repr(C)]
+    #[derive(Debug, Default)]
+    pub struct drm_gem_close {
+        pub handle: u32,
+        pub pad: u32,
+    }
+
+    #[repr(C)]
+    #[derive(Debug, Default)]
+    pub struct drm_prime_handle {
+        pub handle: u32,
+        pub flags: u32,
+        pub fd: i32,
+    }
+
+    #[repr(C)]
+    #[derive(Debug, Default)]
+    pub struct drm_mode_fb_cmd2 {
+        pub fb_id: u32,
+        pub width: u32,
+        pub height: u32,
+        pub pixel_format: u32,
+        pub flags: u32,
+        pub handles: [u32; 4],
+        pub pitches: [u32; 4],
+        pub offsets: [u32; 4],
+        pub modifier: [u64; 4],
+    }
+
+    ioctl_write_ptr!(gem_close, DRM_IOCTL_BASE, 0x09, drm_gem_close);
+    ioctl_readwrite!(prime_fd_to_handle, DRM_IOCTL_BASE, 0x2e, drm_prime_handle);
+    ioctl_readwrite!(mode_rmfb, DRM_IOCTL_BASE, 0xaf, u32);
+    ioctl_readwrite!(mode_addfb2, DRM_IOCTL_BASE, 0xb8, drm_mode_fb_cmd2);
+}
@@ -0,0 +1,90 @@
+//! Layouts of textures shared with other processes and graphics APIs.
+//!
+//! wgpu only tracks the layout of a texture while wgpu uses it. When a dmabuf texture is handed to or received
+//! from the other side, the texture must be transitioned between the layout wgpu expects and the layout the
+//! other side expects, and ownership of the texture must be transferred to or from the other side.
+//!
+//! The device tracks the layouts the other side uses for every dmabuf texture it imports or exports. Call
+//! [`ExternalMemoryDevice::acquire_texture`](crate::ExternalMemoryDevice::acquire_texture) before submitting
+//! work which uses a texture received from the other side, and
+//! [`ExternalMemoryDevice::release_texture`](crate::ExternalMemoryDevice::release_texture) after submitting
+//! work before handing the texture to the other side.
+//!
+//! wgpu does not expose the layout it leaves a texture in. Before the texture is released, the device copies a
+//! texel from the texture or begins a render pass using it, which moves the texture to a layout the device
+//! knows. The texture must therefore be created with
+//! [`TextureUsages::COPY_SRC`](wgpu::TextureUsages::COPY_SRC) or
+//! [`TextureUsages::RENDER_ATTACHMENT`](wgpu::TextureUsages::RENDER_ATTACHMENT). The texture is acquired in
+//! this layout the next time.
+//!
+//! Until wgpu first uses a texture, wgpu considers it uninitialized and transitions it from
+//! `VK_IMAGE_LAYOUT_UNDEFINED` on its first use. A texture which has never been released is therefore acquired
+//! in the general layout, which is valid for that transition, and the device does not use the texture before it
+//! is acquired. Drivers may discard the contents of an image in a transition from the undefined layout, so the
+//! contents of an imported texture may be lost in its first use in wgpu.
+
+use wgpu_hal::DeviceError;
+
+use crate::semaphore::TextureLayout;
+
+/// The layouts the other side uses for a texture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ExternalLayouts {
+    /// The layout the other side leaves the texture in when handing it over.
+    ///
+    /// Use [`TextureLayout::Undefined`] if the contents of the texture are not needed.
+    pub acquire: TextureLayout,
+
+    /// The layout the other side expects the texture to be in when it is handed over.
+    ///
+    /// This may not be [`TextureLayout::Undefined`].
+    pub release: TextureLayout,
+}
+
+impl Default for ExternalLayouts {
+    /// Dmabufs are conventionally shared between processes in the general layout.
+    fn default() -> Self {
+        Self {
+            acquire: TextureLayout::General,
+            release: TextureLayout::General,
+        }
+    }
+}
+
+/// Error returned when tracking the layout of a texture.
+#[derive(Debug, thiserror::Error)]
+pub enum LayoutError {
+    /// The device does not track the layouts of external textures.
+    #[error("the device does not track the layouts of external textures")]
+    Unsupported,
+
+    /// The texture was not imported or exported as a dmabuf by the device.
+    #[error("the texture was not imported or exported as a dmabuf by the device")]
+    UnknownTexture,
+
+    /// The texture was acquired without being released.
+    #[error("the texture is already owned by the device")]
+    AlreadyAcquired,
+
+    /// The texture was released without being acquired.
+    #[error("the texture is not owned by the device")]
+    NotAcquired,
+
+    /// The layout wgpu leaves the texture in is only known for textures created with
+    /// [`TextureUsages::COPY_SRC`](wgpu::TextureUsages::COPY_SRC) or
+    /// [`TextureUsages::RENDER_ATTACHMENT`](wgpu::TextureUsages::RENDER_ATTACHMENT).
+    #[error("the texture must be created with the COPY_SRC or RENDER_ATTACHMENT usage")]
+    UnsupportedUsage,
+
+    /// The other side may not expect a texture in [`TextureLayout::Undefined`].
+    #[error("textures cannot be released in the undefined layout")]
+    UndefinedRelease,
+
+    /// A texture may not be acquired into [`TextureLayout::Undefined`].
+    #[error("textures cannot be acquired in the undefined layout")]
+    UndefinedAcquire,
+
+    /// An error occurred in the device.
+    #[error(transparent)]
+    Device(#[from] DeviceError),
+}
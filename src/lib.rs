@@ -13,22 +13,47 @@
 // Goals:
 // - Allow sharing wgpu textures with other processes and graphics APIs.
 // - Provide enough API to use wgpu in contexts where KMS is needed.
-//
-// Q?:
-// - Creating textures with specific drm formats?
 
 mod imp;
 
 pub mod reexports {
+    pub use ash;
     pub use wgpu;
     pub use wgpu_hal;
 }
 
 pub mod adapter;
+pub mod cache;
+pub mod copy;
+pub mod dmabuf;
+pub mod feedback;
 pub mod instance;
+pub mod kms;
+pub mod layout;
+pub mod negotiation;
+pub mod opaque_fd;
+pub mod semaphore;
+pub mod shared;
+pub mod swapchain;
+pub mod transport;
+pub mod udmabuf;
+pub mod validation;
+pub mod ycbcr;
 
+use adapter::DeviceUuids;
 use bitflags::bitflags;
+use cache::DmabufImportCache;
+use dmabuf::{Dmabuf, DmabufImportDescriptor, ExportError, ExportedTexture, ImportError};
+use drm_fourcc::{DrmFormat, DrmFourcc, DrmModifier};
 use imp::DeviceInner;
+use layout::{ExternalLayouts, LayoutError};
+use opaque_fd::{OpaqueFd, OpaqueFdExportError, OpaqueFdImportError};
+use semaphore::{
+    ExternalSemaphore, SemaphoreError, SemaphoreFd, SemaphoreOperation, TextureLayout,
+};
+use swapchain::{DmabufSwapchain, DmabufSwapchainDescriptor};
+use validation::DmabufConstraints;
+use ycbcr::{YcbcrImportDescriptor, YcbcrTexture};
 
 bitflags! {
     /// Describes what operations may be performed on external memory.
@@ -44,17 +69,60 @@ bitflags! {
     }
 }
 
+/// The type of handle used to share external memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ExternalMemoryType {
+    /// A Linux dmabuf file descriptor.
+    Dmabuf,
+
+    /// An opaque file descriptor.
+    ///
+    /// Opaque file descriptors may only be shared with a device using the same driver and device uuids.
+    OpaqueFd,
+
+    /// A pointer to host memory, such as memory returned by `mmap`.
+    HostAllocation,
+}
+
+/// Describes what operations are supported on external memory of a specific handle type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExternalMemoryProperties {
+    /// Whether memory of the handle type may be imported, exported or both.
+    pub capabilities: ExternalMemoryCapabilities,
+
+    /// Whether imported or exported memory must be bound to a dedicated allocation.
+    pub dedicated_only: bool,
+
+    /// Whether imported or exported memory should be bound to a dedicated allocation, for example because the
+    /// driver disables compression otherwise.
+    ///
+    /// Drivers only report this for a specific image, so the capabilities of an adapter set this if
+    /// `dedicated_only` is set. Use [`ExternalMemoryDevice::external_memory_capabilities`] to query whether the
+    /// driver prefers a dedicated allocation for a texture.
+    pub prefers_dedicated: bool,
+}
+
 /// A device capable of importing and exporting external memory objects.
 #[derive(Debug)]
 pub struct ExternalMemoryDevice {
-    device: wgpu::Device,
+    // The inner state must be dropped before the device.
     inner: DeviceInner,
+    device: wgpu::Device,
+    uuids: Option<DeviceUuids>,
 }
 
 impl ExternalMemoryDevice {
     pub fn device(&self) -> &wgpu::Device {
         &self.device
     }
+
+    /// The uuids of the adapter the device was requested from.
+    ///
+    /// See [`AdapterExt::uuids`](adapter::AdapterExt::uuids).
+    pub fn uuids(&self) -> Option<DeviceUuids> {
+        self.uuids
+    }
 }
 
 impl AsRef<wgpu::Device> for ExternalMemoryDevice {
@@ -63,4 +131,321 @@ impl AsRef<wgpu::Device> for ExternalMemoryDevice {
     }
 }
 
-impl ExternalMemoryDevice {}
+impl ExternalMemoryDevice {
+    /// Returns the fourcc codes and modifiers of the dmabufs the device can import and export.
+    ///
+    /// This is empty if the device does not support dmabufs.
+    pub fn dmabuf_formats(&self) -> Vec<DrmFormat> {
+        match &self.inner {
+            DeviceInner::Vulkan(inner) => inner.dmabuf_formats(),
+            DeviceInner::Egl(_) => Vec::new(),
+        }
+    }
+
+    /// Returns the fourcc codes and modifiers of the dmabufs the device can import and export as textures
+    /// with the specified usages.
+    pub fn dmabuf_formats_with_usage(&self, usage: wgpu::TextureUsages) -> Vec<DrmFormat> {
+        match &self.inner {
+            DeviceInner::Vulkan(inner) => inner.dmabuf_formats_with_usage(usage),
+            DeviceInner::Egl(_) => Vec::new(),
+        }
+    }
+
+    /// Returns the constraints the device places on the layout of dmabufs with the fourcc code and modifier.
+    ///
+    /// This is [`None`] if the device does not support the format.
+    pub fn dmabuf_constraints(&self, format: DrmFormat) -> Option<DmabufConstraints> {
+        match &self.inner {
+            DeviceInner::Vulkan(inner) => inner.dmabuf_constraints(format),
+            DeviceInner::Egl(_) => None,
+        }
+    }
+
+    /// Returns what operations are supported on external memory of the specified handle type for a texture
+    /// with the descriptor.
+    ///
+    /// Unlike the capabilities of the adapter, this also reports whether the driver prefers the memory of the
+    /// texture to be bound to a dedicated allocation.
+    ///
+    /// This will return [`None`] if the handle type may not be imported or exported for the texture.
+    pub fn external_memory_capabilities(
+        &self,
+        handle_type: ExternalMemoryType,
+        desc: &wgpu::TextureDescriptor,
+    ) -> Option<ExternalMemoryProperties> {
+        match &self.inner {
+            DeviceInner::Vulkan(inner) => {
+                inner.external_memory_capabilities(&self.device, handle_type, desc)
+            }
+            DeviceInner::Egl(inner) => {
+                inner.external_memory_capabilities(&self.device, handle_type, desc)
+            }
+        }
+    }
+
+    /// Creates a texture whose memory is exported as a dmabuf.
+    ///
+    /// The memory layout of the image is described by the fourcc code, the format of the texture must have
+    /// the same memory layout. The modifier of the dmabuf is chosen by the device from the modifiers which
+    /// support the usages of the texture.
+    pub fn create_exportable_texture(
+        &self,
+        desc: &wgpu::TextureDescriptor,
+        fourcc: DrmFourcc,
+    ) -> Result<ExportedTexture, ExportError> {
+        match &self.inner {
+            DeviceInner::Vulkan(inner) => {
+                inner.create_exportable_texture(&self.device, desc, fourcc)
+            }
+            DeviceInner::Egl(_) => Err(ExportError::Unsupported),
+        }
+    }
+
+    /// Creates a texture whose memory is exported as a dmabuf using one of the specified modifiers.
+    ///
+    /// The device chooses the modifier from the modifiers which support the usages of the texture, so the
+    /// modifiers should be the modifiers accepted by the consumer of the dmabuf, such as a KMS plane or a
+    /// video encoder. The chosen modifier is the modifier of the format of the exported dmabuf.
+    pub fn create_texture_with_modifiers(
+        &self,
+        desc: &wgpu::TextureDescriptor,
+        fourcc: DrmFourcc,
+        modifiers: &[DrmModifier],
+    ) -> Result<ExportedTexture, ExportError> {
+        match &self.inner {
+            DeviceInner::Vulkan(inner) => {
+                inner.create_texture_with_modifiers(&self.device, desc, fourcc, modifiers)
+            }
+            DeviceInner::Egl(_) => Err(ExportError::Unsupported),
+        }
+    }
+
+    /// Imports a dmabuf as a texture.
+    ///
+    /// The memory layout of the texture format must be the same as the fourcc code of the dmabuf. YCbCr
+    /// dmabufs must be imported using [`ExternalMemoryDevice::import_ycbcr_dmabuf`].
+    pub fn import_dmabuf(
+        &self,
+        dmabuf: &Dmabuf,
+        desc: &DmabufImportDescriptor,
+    ) -> Result<wgpu::Texture, ImportError> {
+        match &self.inner {
+            DeviceInner::Vulkan(inner) => inner.import_dmabuf(&self.device, dmabuf, desc),
+            DeviceInner::Egl(_) => Err(ImportError::Unsupported),
+        }
+    }
+
+    /// Imports a multi-planar or subsampled YCbCr dmabuf, such as NV12, P010, YUV420 or YUYV.
+    ///
+    /// The image is sampled using a sampler YCbCr conversion configured by the descriptor.
+    pub fn import_ycbcr_dmabuf(
+        &self,
+        dmabuf: &Dmabuf,
+        desc: &YcbcrImportDescriptor,
+    ) -> Result<YcbcrTexture<'_>, ImportError> {
+        match &self.inner {
+            DeviceInner::Vulkan(inner) => inner.import_ycbcr_dmabuf(&self.device, dmabuf, desc),
+            DeviceInner::Egl(_) => Err(ImportError::Unsupported),
+        }
+    }
+
+    /// Sets the layouts the other side uses for an imported or exported dmabuf texture.
+    ///
+    /// The layouts default to [`TextureLayout::General`].
+    pub fn set_external_layouts(
+        &self,
+        texture: &wgpu::Texture,
+        layouts: ExternalLayouts,
+    ) -> Result<(), LayoutError> {
+        match &self.inner {
+            DeviceInner::Vulkan(inner) => inner.set_external_layouts(texture, layouts),
+            DeviceInner::Egl(_) => Err(LayoutError::Unsupported),
+        }
+    }
+
+    /// Acquires a dmabuf texture from the other side before any work submitted to the queue afterwards.
+    ///
+    /// The texture is transitioned from the acquire layout of the other side to the layout wgpu expects. If the
+    /// other side did not modify the texture since it was released, set `unmodified` to skip the transition from
+    /// the acquire layout and let the driver keep metadata such as compression state, using
+    /// `VK_EXT_external_memory_acquire_unmodified` if it is available.
+    ///
+    /// Imported textures must be acquired before they are used. The other side must have finished using the
+    /// texture, this does not wait for it.
+    ///
+    /// # Safety
+    ///
+    /// The ownership transfer is submitted to the queue of this device directly, so no other thread may use the
+    /// queue until this returns.
+    pub unsafe fn acquire_texture(
+        &self,
+        texture: &wgpu::Texture,
+        unmodified: bool,
+    ) -> Result<(), LayoutError> {
+        match &self.inner {
+            DeviceInner::Vulkan(inner) => inner.acquire_texture(&self.device, texture, unmodified),
+            DeviceInner::Egl(_) => Err(LayoutError::Unsupported),
+        }
+    }
+
+    /// Releases a dmabuf texture to the other side after all work submitted to the queue.
+    ///
+    /// The texture is transitioned from the layout of its last use in wgpu to the release layout of the other
+    /// side.
+    ///
+    /// # Safety
+    ///
+    /// The queue must be the queue of this device. The ownership transfer is submitted to the Vulkan queue
+    /// directly, so no other thread may use the queue until this returns.
+    pub unsafe fn release_texture(
+        &self,
+        queue: &wgpu::Queue,
+        texture: &wgpu::Texture,
+    ) -> Result<(), LayoutError> {
+        match &self.inner {
+            DeviceInner::Vulkan(inner) => inner.release_texture(&self.device, queue, texture),
+            DeviceInner::Egl(_) => Err(LayoutError::Unsupported),
+        }
+    }
+
+    /// Sets the layouts the other side uses for an imported YCbCr dmabuf.
+    ///
+    /// The layouts default to [`TextureLayout::General`].
+    pub fn set_ycbcr_external_layouts(
+        &self,
+        texture: &YcbcrTexture,
+        layouts: ExternalLayouts,
+    ) -> Result<(), LayoutError> {
+        match &self.inner {
+            DeviceInner::Vulkan(inner) => inner.set_ycbcr_external_layouts(texture, layouts),
+            DeviceInner::Egl(_) => Err(LayoutError::Unsupported),
+        }
+    }
+
+    /// Acquires an imported YCbCr dmabuf from the other side before any work submitted to the queue afterwards.
+    ///
+    /// wgpu does not use the image, so it is transitioned from the acquire layout of the other side to `layout`,
+    /// which is the layout the caller samples the image in, usually [`TextureLayout::ShaderReadOnly`]. See
+    /// [`ExternalMemoryDevice::acquire_texture`] for `unmodified`.
+    ///
+    /// # Safety
+    ///
+    /// The ownership transfer is submitted to the queue of this device directly, so no other thread may use the
+    /// queue until this returns.
+    pub unsafe fn acquire_ycbcr_texture(
+        &self,
+        texture: &YcbcrTexture,
+        layout: TextureLayout,
+        unmodified: bool,
+    ) -> Result<(), LayoutError> {
+        match &self.inner {
+            DeviceInner::Vulkan(inner) => {
+                inner.acquire_ycbcr_texture(&self.device, texture, layout, unmodified)
+            }
+            DeviceInner::Egl(_) => Err(LayoutError::Unsupported),
+        }
+    }
+
+    /// Releases an imported YCbCr dmabuf to the other side after all work submitted to the queue.
+    ///
+    /// The image is transitioned from `layout`, which must be the layout the caller left the image in, to the
+    /// release layout of the other side.
+    ///
+    /// # Safety
+    ///
+    /// The ownership transfer is submitted to the queue of this device directly, so no other thread may use the
+    /// queue until this returns.
+    pub unsafe fn release_ycbcr_texture(
+        &self,
+        texture: &YcbcrTexture,
+        layout: TextureLayout,
+    ) -> Result<(), LayoutError> {
+        match &self.inner {
+            DeviceInner::Vulkan(inner) => {
+                inner.release_ycbcr_texture(&self.device, texture, layout)
+            }
+            DeviceInner::Egl(_) => Err(LayoutError::Unsupported),
+        }
+    }
+
+    /// Creates a texture whose memory is exported as an opaque file descriptor.
+    ///
+    /// Only the Vulkan backend supports exporting opaque file descriptors.
+    pub fn create_opaque_fd_texture(
+        &self,
+        desc: &wgpu::TextureDescriptor,
+    ) -> Result<(wgpu::Texture, OpaqueFd), OpaqueFdExportError> {
+        match &self.inner {
+            DeviceInner::Vulkan(inner) => inner.create_opaque_fd_texture(&self.device, desc),
+            DeviceInner::Egl(_) => Err(OpaqueFdExportError::Unsupported),
+        }
+    }
+
+    /// Imports memory exported as an opaque file descriptor as a texture.
+    ///
+    /// The texture must be imported with the same descriptor it was created with on the exporting device.
+    /// Only the GLES backend supports importing opaque file descriptors, using `GL_EXT_memory_object_fd`.
+    pub fn import_opaque_fd(
+        &self,
+        memory: OpaqueFd,
+        desc: &wgpu::TextureDescriptor,
+    ) -> Result<wgpu::Texture, OpaqueFdImportError> {
+        match &self.inner {
+            // TODO: VK_KHR_external_memory_fd
+            DeviceInner::Vulkan(_) => Err(OpaqueFdImportError::Unsupported),
+            DeviceInner::Egl(inner) => inner.import_opaque_fd(&self.device, memory, desc),
+        }
+    }
+
+    /// Imports a semaphore exported by another device.
+    ///
+    /// Only the GLES backend supports importing semaphores. Opaque file descriptors are imported using
+    /// `GL_EXT_semaphore_fd`, and sync files are imported using `EGL_ANDROID_native_fence_sync`.
+    pub fn import_semaphore(
+        &self,
+        semaphore: SemaphoreFd,
+    ) -> Result<ExternalSemaphore<'_>, SemaphoreError> {
+        match &self.inner {
+            // TODO: VK_KHR_external_semaphore_fd
+            DeviceInner::Vulkan(_) => Err(SemaphoreError::Unsupported(semaphore.handle_type)),
+            DeviceInner::Egl(inner) => Ok(ExternalSemaphore {
+                device: self,
+                inner: inner.import_semaphore(&self.device, semaphore)?,
+            }),
+        }
+    }
+
+    /// Submits command buffers to the queue after waiting on semaphores and signals semaphores once the command
+    /// buffers complete.
+    ///
+    /// The textures of each operation are transitioned from or to the layouts the other device uses.
+    pub fn submit_with_semaphores<I: IntoIterator<Item = wgpu::CommandBuffer>>(
+        &self,
+        queue: &wgpu::Queue,
+        command_buffers: I,
+        waits: &[SemaphoreOperation],
+        signals: &[SemaphoreOperation],
+    ) -> Result<wgpu::SubmissionIndex, SemaphoreError> {
+        match &self.inner {
+            DeviceInner::Vulkan(_) => match waits.iter().chain(signals).next() {
+                Some(op) => Err(SemaphoreError::Unsupported(op.semaphore.handle_type())),
+                None => Ok(queue.submit(command_buffers)),
+            },
+            DeviceInner::Egl(inner) => {
+                inner.submit_with_semaphores(&self.device, queue, command_buffers, waits, signals)
+            }
+        }
+    }
+
+    /// Creates a cache of imported dmabufs which holds at most `capacity` dmabufs.
+    pub fn create_dmabuf_import_cache(&self, capacity: usize) -> DmabufImportCache<'_> {
+        DmabufImportCache::new(self, capacity)
+    }
+
+    /// Creates a swapchain of exportable textures.
+    ///
+    /// No textures are allocated until the first buffer is acquired.
+    pub fn create_dmabuf_swapchain(&self, desc: &DmabufSwapchainDescriptor) -> DmabufSwapchain<'_> {
+        DmabufSwapchain::new(self, desc)
+    }
+}
@@ -0,0 +1,158 @@
+//! Negotiation of dmabuf formats shared between two devices.
+//!
+//! In PRIME setups the textures are rendered on one device and exported as dmabufs, which are then imported
+//! on another device, such as the device driving the display. The format and modifier of the dmabufs must be
+//! supported by both devices for the usages of the textures on each device.
+
+use drm_fourcc::{DrmFormat, DrmFourcc, DrmModifier};
+use wgpu::TextureUsages;
+
+use crate::ExternalMemoryDevice;
+
+/// Describes how the textures are used on each device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NegotiationDescriptor {
+    /// Usages of the textures on the device exporting the dmabufs.
+    pub export_usage: TextureUsages,
+
+    /// Usages of the textures on the device importing the dmabufs.
+    pub import_usage: TextureUsages,
+}
+
+/// Error returned when no modifier of a fourcc code is usable on both devices.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum NegotiationError {
+    /// The exporting device does not support the fourcc code.
+    #[error("the exporting device does not support {0}")]
+    ExportUnsupported(DrmFourcc),
+
+    /// The importing device does not support the fourcc code.
+    #[error("the importing device does not support {0}")]
+    ImportUnsupported(DrmFourcc),
+
+    /// The exporting device supports the fourcc code, but no modifier supports the export usages.
+    #[error("no modifier of {fourcc} supports {usage:?} on the exporting device")]
+    UnsupportedExportUsage {
+        fourcc: DrmFourcc,
+        usage: TextureUsages,
+    },
+
+    /// The importing device supports the fourcc code, but no modifier supports the import usages.
+    #[error("no modifier of {fourcc} supports {usage:?} on the importing device")]
+    UnsupportedImportUsage {
+        fourcc: DrmFourcc,
+        usage: TextureUsages,
+    },
+
+    /// Both devices support the fourcc code, but they do not share a modifier.
+    #[error("the devices do not share a modifier of {0}")]
+    NoCommonModifier(DrmFourcc),
+}
+
+/// Returns the modifiers of a fourcc code which may be exported by one device and imported by the other.
+///
+/// The modifiers are ranked by preference. Modifiers other than `DRM_FORMAT_MOD_LINEAR` are preferred since
+/// they are usually faster to render to and sample from, followed by `DRM_FORMAT_MOD_LINEAR`.
+///
+/// The result may be passed to [`ExternalMemoryDevice::create_texture_with_modifiers`] on the exporting
+/// device.
+pub fn negotiate_modifiers(
+    exporter: &ExternalMemoryDevice,
+    importer: &ExternalMemoryDevice,
+    fourcc: DrmFourcc,
+    desc: &NegotiationDescriptor,
+) -> Result<Vec<DrmModifier>, NegotiationError> {
+    let export_modifiers =
+        modifiers_with_usage(exporter, fourcc, desc.export_usage).map_err(|err| match err {
+            UsageError::Unsupported => NegotiationError::ExportUnsupported(fourcc),
+            UsageError::UnsupportedUsage => NegotiationError::UnsupportedExportUsage {
+                fourcc,
+                usage: desc.export_usage,
+            },
+        })?;
+    let import_modifiers =
+        modifiers_with_usage(importer, fourcc, desc.import_usage).map_err(|err| match err {
+            UsageError::Unsupported => NegotiationError::ImportUnsupported(fourcc),
+            UsageError::UnsupportedUsage => NegotiationError::UnsupportedImportUsage {
+                fourcc,
+                usage: desc.import_usage,
+            },
+        })?;
+
+    let mut modifiers = export_modifiers
+        .into_iter()
+        .filter(|modifier| *modifier != DrmModifier::Invalid && import_modifiers.contains(modifier))
+        .collect::<Vec<_>>();
+
+    if modifiers.is_empty() {
+        return Err(NegotiationError::NoCommonModifier(fourcc));
+    }
+
+    // The sort is stable, so the order of the other modifiers is kept.
+    modifiers.sort_by_key(|modifier| *modifier == DrmModifier::Linear);
+
+    Ok(modifiers)
+}
+
+/// Returns every format which may be exported by one device and imported by the other.
+///
+/// The formats are grouped by fourcc code, and the modifiers of each fourcc code are ranked as described by
+/// [`negotiate_modifiers`]. Fourcc codes with no usable modifier are skipped, use [`negotiate_modifiers`] to
+/// find out why a fourcc code is unusable.
+pub fn negotiate_formats(
+    exporter: &ExternalMemoryDevice,
+    importer: &ExternalMemoryDevice,
+    desc: &NegotiationDescriptor,
+) -> Vec<DrmFormat> {
+    let mut fourccs = exporter
+        .dmabuf_formats()
+        .into_iter()
+        .map(|format| format.code)
+        .collect::<Vec<_>>();
+    fourccs.dedup();
+
+    fourccs
+        .into_iter()
+        .flat_map(|fourcc| {
+            negotiate_modifiers(exporter, importer, fourcc, desc)
+                .unwrap_or_default()
+                .into_iter()
+                .map(move |modifier| DrmFormat {
+                    code: fourcc,
+                    modifier,
+                })
+        })
+        .collect()
+}
+
+enum UsageError {
+    Unsupported,
+    UnsupportedUsage,
+}
+
+fn modifiers_with_usage(
+    device: &ExternalMemoryDevice,
+    fourcc: DrmFourcc,
+    usage: TextureUsages,
+) -> Result<Vec<DrmModifier>, UsageError> {
+    if !device
+        .dmabuf_formats()
+        .iter()
+        .any(|format| format.code == fourcc)
+    {
+        return Err(UsageError::Unsupported);
+    }
+
+    let modifiers = device
+        .dmabuf_formats_with_usage(usage)
+        .into_iter()
+        .filter(|format| format.code == fourcc)
+        .map(|format| format.modifier)
+        .collect::<Vec<_>>();
+
+    if modifiers.is_empty() {
+        return Err(UsageError::UnsupportedUsage);
+    }
+
+    Ok(modifiers)
+}
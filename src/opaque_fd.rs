@@ -0,0 +1,69 @@
+//! Opaque file descriptor external memory.
+//!
+//! Opaque file descriptors may only be shared between devices using the same driver, and both devices must
+//! report the same [`DeviceUuids`](crate::adapter::DeviceUuids). The memory layout of the image is not
+//! described by the file descriptor, so the texture must be imported with the same parameters it was created
+//! with.
+
+use std::os::unix::io::OwnedFd;
+
+use wgpu::TextureFormat;
+use wgpu_hal::DeviceError;
+
+/// Memory exported as an opaque file descriptor.
+#[derive(Debug)]
+pub struct OpaqueFd {
+    /// The file descriptor of the memory.
+    pub fd: OwnedFd,
+
+    /// Size of the memory in bytes.
+    pub size: u64,
+}
+
+/// Error returned when importing an opaque file descriptor.
+#[derive(Debug, thiserror::Error)]
+pub enum OpaqueFdImportError {
+    /// The device does not support importing opaque file descriptors.
+    #[error("the device does not support importing opaque file descriptors")]
+    Unsupported,
+
+    /// The texture format cannot be imported.
+    #[error("the texture format {0:?} is not supported")]
+    UnsupportedFormat(TextureFormat),
+
+    /// The texture descriptor describes a texture which cannot be imported.
+    ///
+    /// Only 2D textures with a single mip level, array layer and sample may be imported.
+    #[error("only 2D textures with a single mip level, array layer and sample may be imported")]
+    InvalidDescriptor,
+
+    /// The driver rejected the file descriptor or the texture parameters.
+    #[error("the driver failed to import the memory")]
+    ImportFailed,
+
+    /// An error occurred in the device.
+    #[error(transparent)]
+    Device(#[from] DeviceError),
+}
+
+/// Error returned when creating a texture exportable as an opaque file descriptor.
+#[derive(Debug, thiserror::Error)]
+pub enum OpaqueFdExportError {
+    /// The device does not support exporting opaque file descriptors.
+    #[error("the device does not support exporting opaque file descriptors")]
+    Unsupported,
+
+    /// The texture format cannot be exported.
+    #[error("the texture format {0:?} is not supported")]
+    UnsupportedFormat(TextureFormat),
+
+    /// The texture descriptor describes a texture which cannot be exported.
+    ///
+    /// Only 2D textures with a single mip level, array layer and sample may be exported.
+    #[error("only 2D textures with a single mip level, array layer and sample may be exported")]
+    InvalidDescriptor,
+
+    /// An error occurred in the device.
+    #[error(transparent)]
+    Device(#[from] DeviceError),
+}
@@ -0,0 +1,119 @@
+//! Semaphores shared with other graphics APIs.
+//!
+//! Semaphores order accesses to shared textures between devices. A semaphore is waited on before a submission
+//! reading a texture written by the other device, and signalled after a submission writing a texture the other
+//! device reads. Both sides must agree on the layout of each texture when the semaphore is signalled.
+
+use std::os::unix::io::OwnedFd;
+
+use crate::{
+    imp::{self, DeviceInner},
+    ExternalMemoryDevice,
+};
+
+/// The type of a semaphore file descriptor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SemaphoreHandleType {
+    /// An opaque file descriptor, which may only be shared with a device using the same driver.
+    ///
+    /// The semaphore may be waited on and signalled any number of times.
+    OpaqueFd,
+
+    /// A sync file, which is signalled once.
+    ///
+    /// The semaphore may only be waited on.
+    SyncFd,
+}
+
+/// The layout of a texture when a semaphore is waited on or signalled.
+///
+/// These correspond to the Vulkan image layouts of the same name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TextureLayout {
+    /// The contents of the texture are not preserved.
+    ///
+    /// This is only valid when the other device hands the texture over.
+    Undefined,
+    General,
+    ColorAttachment,
+    DepthStencilAttachment,
+    DepthStencilReadOnly,
+    ShaderReadOnly,
+    TransferSrc,
+    TransferDst,
+}
+
+/// An imported semaphore.
+///
+/// The semaphore is destroyed when dropped, so it borrows the device it was imported on.
+#[derive(Debug)]
+pub struct ExternalSemaphore<'a> {
+    pub(crate) device: &'a ExternalMemoryDevice,
+    pub(crate) inner: imp::egl::Semaphore,
+}
+
+impl ExternalSemaphore<'_> {
+    /// The type of the file descriptor the semaphore was imported from.
+    pub fn handle_type(&self) -> SemaphoreHandleType {
+        self.inner.handle_type()
+    }
+}
+
+impl Drop for ExternalSemaphore<'_> {
+    fn drop(&mut self) {
+        // Semaphores are only imported on the GLES backend.
+        if let DeviceInner::Egl(inner) = &self.device.inner {
+            inner.destroy_semaphore(self.device.device(), &self.inner);
+        }
+    }
+}
+
+/// A texture accessed by both devices and its layout.
+#[derive(Debug, Clone, Copy)]
+pub struct SemaphoreTexture<'a> {
+    pub texture: &'a wgpu::Texture,
+    pub layout: TextureLayout,
+}
+
+/// A semaphore to wait on or signal, and the textures it guards.
+#[derive(Debug, Clone, Copy)]
+pub struct SemaphoreOperation<'a> {
+    pub semaphore: &'a ExternalSemaphore<'a>,
+
+    /// The textures shared with the other device and their layouts.
+    ///
+    /// When waiting, the layout is the layout the other device left the texture in. When signalling, the
+    /// layout is the layout the other device expects the texture to be in.
+    pub textures: &'a [SemaphoreTexture<'a>],
+}
+
+/// The file descriptor of a semaphore to import.
+#[derive(Debug)]
+pub struct SemaphoreFd {
+    pub fd: OwnedFd,
+    pub handle_type: SemaphoreHandleType,
+}
+
+/// Error returned when importing or using a semaphore.
+#[derive(Debug, thiserror::Error)]
+pub enum SemaphoreError {
+    /// The device does not support importing semaphores of the handle type.
+    #[error("the device does not support importing {0:?} semaphores")]
+    Unsupported(SemaphoreHandleType),
+
+    /// The driver rejected the file descriptor.
+    #[error("the driver failed to import the semaphore")]
+    ImportFailed,
+
+    /// The driver failed to wait on the semaphore.
+    #[error("the driver failed to wait on the semaphore")]
+    WaitFailed,
+
+    /// Sync file semaphores may only be waited on.
+    #[error("sync file semaphores cannot be signalled")]
+    SignalSyncFd,
+
+    /// A texture is not backed by a texture object of the device.
+    #[error("the texture cannot be used with a semaphore")]
+    InvalidTexture,
+}
@@ -0,0 +1,318 @@
+//! Sharing textures between a Vulkan device and a GLES device in the same process.
+//!
+//! A [`SharedTexture`] is allocated on the Vulkan device and imported on the GLES device. The memory of the
+//! texture and the semaphores ordering the accesses of the devices are shared as opaque file descriptors, so
+//! both devices must use the same driver and report the same [`DeviceUuids`](crate::adapter::DeviceUuids).
+//!
+//! The GLES backend cannot import dmabufs, so textures are never shared as dmabufs.
+//!
+//! Only one device may access the texture at a time. The Vulkan device releases the texture to the GLES
+//! device, which submits its work and hands the texture back to the Vulkan device:
+//!
+//! 1. Submit work using the texture on the Vulkan queue.
+//! 2. [`SharedTexture::release_to_gl`] with the Vulkan queue.
+//! 3. [`SharedTexture::submit_gl`] with the command buffers using the texture on the GLES device.
+//! 4. [`SharedTexture::acquire_from_gl`] before submitting more work using the texture on the Vulkan queue.
+
+use std::sync::Mutex;
+
+use wgpu::{Extent3d, TextureDimension, TextureFormat, TextureUsages};
+use wgpu_hal::DeviceError;
+
+use crate::{
+    imp::{self, DeviceInner},
+    layout::LayoutError,
+    opaque_fd::{OpaqueFdExportError, OpaqueFdImportError},
+    semaphore::{
+        ExternalSemaphore, SemaphoreError, SemaphoreFd, SemaphoreHandleType, SemaphoreOperation,
+        SemaphoreTexture, TextureLayout,
+    },
+    ExternalMemoryDevice,
+};
+
+/// Describes a [`SharedTexture`].
+#[derive(Debug, Clone)]
+pub struct SharedTextureDescriptor<'a> {
+    /// Debug label of the textures.
+    pub label: wgpu::Label<'a>,
+
+    /// Width of the texture in pixels.
+    pub width: u32,
+
+    /// Height of the texture in pixels.
+    pub height: u32,
+
+    /// Format of the texture.
+    pub format: TextureFormat,
+
+    /// Allowed usages of the texture on the Vulkan device.
+    ///
+    /// This must include [`TextureUsages::COPY_SRC`] or [`TextureUsages::RENDER_ATTACHMENT`], which are used to
+    /// move the texture to a known layout before it is released to the GLES device.
+    pub vulkan_usage: TextureUsages,
+
+    /// Allowed usages of the texture on the GLES device.
+    pub gl_usage: TextureUsages,
+}
+
+/// Error returned when creating or handing off a [`SharedTexture`].
+#[derive(Debug, thiserror::Error)]
+pub enum SharedTextureError {
+    /// The first device must use the Vulkan backend and the second device must use the GLES backend.
+    #[error("a texture may only be shared from a Vulkan device to a GLES device")]
+    InvalidBackends,
+
+    /// The devices do not use the same driver or cannot share opaque memory or semaphores for the texture.
+    #[error("the devices cannot share memory")]
+    Unsupported,
+
+    /// The texture could not be exported as an opaque file descriptor.
+    #[error(transparent)]
+    OpaqueFdExport(#[from] OpaqueFdExportError),
+
+    /// The opaque file descriptor could not be imported.
+    #[error(transparent)]
+    OpaqueFdImport(#[from] OpaqueFdImportError),
+
+    /// The semaphores could not be shared or used.
+    #[error(transparent)]
+    Semaphore(#[from] SemaphoreError),
+
+    /// The layout of the texture on the Vulkan device cannot be tracked.
+    #[error(transparent)]
+    Layout(#[from] LayoutError),
+
+    /// The handoff was called out of order.
+    #[error("the texture is not owned by the device handing it off")]
+    NotOwned,
+
+    /// An error occurred in the device.
+    #[error(transparent)]
+    Device(#[from] DeviceError),
+}
+
+/// The device which may currently access the texture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Owner {
+    Vulkan,
+    /// Released by the Vulkan device in the layout, but not yet used on the GLES device.
+    ReleasedToGl(TextureLayout),
+    /// Used on the GLES device and returned in the layout, but not yet acquired by the Vulkan device.
+    ReleasedToVulkan(TextureLayout),
+}
+
+/// A texture shared between a Vulkan device and a GLES device.
+///
+/// See the [module level documentation](self) for how to hand the texture off between the devices.
+pub struct SharedTexture<'a> {
+    /// The handoff uses the raw Vulkan device, which must outlive it.
+    vulkan: &'a ExternalMemoryDevice,
+    gl: &'a ExternalMemoryDevice,
+    vulkan_texture: wgpu::Texture,
+    vulkan_usage: TextureUsages,
+    gl_texture: wgpu::Texture,
+    handoff: imp::vulkan::Handoff,
+    /// The semaphores signalled by the Vulkan device and by the GLES device.
+    ///
+    /// Declared after the handoff, which waits for the last handoff to complete when dropped.
+    semaphores: (ExternalSemaphore<'a>, ExternalSemaphore<'a>),
+    owner: Mutex<Owner>,
+}
+
+impl<'a> SharedTexture<'a> {
+    /// Allocates a texture on the Vulkan device and imports it on the GLES device.
+    ///
+    /// The Vulkan device initially owns the texture.
+    pub fn new(
+        vulkan: &'a ExternalMemoryDevice,
+        gl: &'a ExternalMemoryDevice,
+        desc: &SharedTextureDescriptor,
+    ) -> Result<Self, SharedTextureError> {
+        let vulkan_inner = match (&vulkan.inner, &gl.inner) {
+            (DeviceInner::Vulkan(vulkan_inner), DeviceInner::Egl(_)) => vulkan_inner,
+            _ => return Err(SharedTextureError::InvalidBackends),
+        };
+
+        // Opaque memory and semaphores need VK_KHR_external_memory_fd, VK_KHR_external_semaphore_fd and the
+        // same driver on both devices.
+        if !vulkan_inner.supports_opaque_fd {
+            return Err(SharedTextureError::Unsupported);
+        }
+
+        match (vulkan.uuids(), gl.uuids()) {
+            (Some(vulkan_uuids), Some(gl_uuids)) if vulkan_uuids == gl_uuids => (),
+            _ => return Err(SharedTextureError::Unsupported),
+        }
+
+        if !desc
+            .vulkan_usage
+            .intersects(TextureUsages::COPY_SRC | TextureUsages::RENDER_ATTACHMENT)
+        {
+            return Err(LayoutError::UnsupportedUsage.into());
+        }
+
+        let mut texture_desc = wgpu::TextureDescriptor {
+            label: desc.label,
+            size: Extent3d {
+                width: desc.width,
+                height: desc.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: desc.format,
+            usage: desc.vulkan_usage,
+        };
+
+        let (vulkan_texture, memory) = match vulkan.create_opaque_fd_texture(&texture_desc) {
+            Ok(shared) => shared,
+            Err(OpaqueFdExportError::Unsupported) => return Err(SharedTextureError::Unsupported),
+            Err(err) => return Err(err.into()),
+        };
+
+        texture_desc.usage = desc.gl_usage;
+
+        let gl_texture = match gl.import_opaque_fd(memory, &texture_desc) {
+            Ok(gl_texture) => gl_texture,
+            Err(OpaqueFdImportError::Unsupported) => return Err(SharedTextureError::Unsupported),
+            Err(err) => return Err(err.into()),
+        };
+
+        let (handoff, release_fd, acquire_fd) =
+            vulkan_inner.create_handoff(vulkan.device(), &vulkan_texture)?;
+
+        // Opaque semaphores are shared the same way as opaque memory, which requires the same driver.
+        let to_gl = gl.import_semaphore(SemaphoreFd {
+            fd: release_fd,
+            handle_type: SemaphoreHandleType::OpaqueFd,
+        })?;
+        let to_vulkan = gl.import_semaphore(SemaphoreFd {
+            fd: acquire_fd,
+            handle_type: SemaphoreHandleType::OpaqueFd,
+        })?;
+
+        Ok(Self {
+            vulkan,
+            gl,
+            vulkan_texture,
+            vulkan_usage: desc.vulkan_usage,
+            gl_texture,
+            handoff,
+            semaphores: (to_gl, to_vulkan),
+            owner: Mutex::new(Owner::Vulkan),
+        })
+    }
+
+    /// The texture on the Vulkan device.
+    pub fn vulkan_texture(&self) -> &wgpu::Texture {
+        &self.vulkan_texture
+    }
+
+    /// The texture on the GLES device.
+    pub fn gl_texture(&self) -> &wgpu::Texture {
+        &self.gl_texture
+    }
+
+    /// Releases the texture to the GLES device after all work submitted on the Vulkan queue.
+    ///
+    /// The texture is first used once more on the Vulkan queue to move it to a layout which is known, see the
+    /// [`layout`](crate::layout) module. The texture stays in this layout while the GLES device uses it.
+    ///
+    /// # Safety
+    ///
+    /// The queue must be the queue of the Vulkan device. The handoff is submitted to the Vulkan queue directly,
+    /// so no other thread may use the queue until this returns.
+    pub unsafe fn release_to_gl(&self, queue: &wgpu::Queue) -> Result<(), SharedTextureError> {
+        let mut owner = self.owner.lock().unwrap();
+
+        if *owner != Owner::Vulkan {
+            return Err(SharedTextureError::NotOwned);
+        }
+
+        let vulkan_inner = match &self.vulkan.inner {
+            DeviceInner::Vulkan(inner) => inner,
+            _ => unreachable!("the backend is checked when the texture is created"),
+        };
+        let layout = vulkan_inner.pin_wgpu_layout(
+            self.vulkan.device(),
+            queue,
+            &self.vulkan_texture,
+            self.vulkan_usage,
+        )?;
+        self.handoff
+            .release(imp::vulkan::conv::map_texture_layout(layout))?;
+        *owner = Owner::ReleasedToGl(layout);
+
+        Ok(())
+    }
+
+    /// Submits command buffers using the texture to the GLES queue and hands the texture back to the Vulkan
+    /// device once they complete.
+    ///
+    /// The command buffers wait for the Vulkan device to release the texture. The texture is returned in the
+    /// layout it was released in.
+    pub fn submit_gl<I: IntoIterator<Item = wgpu::CommandBuffer>>(
+        &self,
+        queue: &wgpu::Queue,
+        command_buffers: I,
+    ) -> Result<wgpu::SubmissionIndex, SharedTextureError> {
+        let mut owner = self.owner.lock().unwrap();
+
+        let layout = match *owner {
+            Owner::ReleasedToGl(layout) => layout,
+            _ => return Err(SharedTextureError::NotOwned),
+        };
+
+        let (to_gl, to_vulkan) = &self.semaphores;
+        let textures = [SemaphoreTexture {
+            texture: &self.gl_texture,
+            layout,
+        }];
+
+        let index = self.gl.submit_with_semaphores(
+            queue,
+            command_buffers,
+            &[SemaphoreOperation {
+                semaphore: to_gl,
+                textures: &textures,
+            }],
+            &[SemaphoreOperation {
+                semaphore: to_vulkan,
+                textures: &textures,
+            }],
+        )?;
+        *owner = Owner::ReleasedToVulkan(layout);
+
+        Ok(index)
+    }
+
+    /// Acquires the texture from the GLES device before any work submitted to the Vulkan queue afterwards.
+    ///
+    /// # Safety
+    ///
+    /// The handoff is submitted to the queue of the Vulkan device directly, so no other thread may use the
+    /// queue until this returns.
+    pub unsafe fn acquire_from_gl(&self) -> Result<(), SharedTextureError> {
+        let mut owner = self.owner.lock().unwrap();
+
+        let layout = match *owner {
+            Owner::ReleasedToVulkan(layout) => layout,
+            _ => return Err(SharedTextureError::NotOwned),
+        };
+
+        self.handoff
+            .acquire(imp::vulkan::conv::map_texture_layout(layout))?;
+        *owner = Owner::Vulkan;
+
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for SharedTexture<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SharedTexture")
+            .field("owner", &*self.owner.lock().unwrap())
+            .finish()
+    }
+}
@@ -0,0 +1,302 @@
+//! A swapchain of exportable textures.
+
+use std::os::unix::io::OwnedFd;
+
+use drm_fourcc::{DrmFourcc, DrmModifier};
+use wgpu::{Extent3d, TextureDimension, TextureFormat, TextureUsages};
+
+use crate::{
+    dmabuf::{Dmabuf, ExportError, ExportedTexture},
+    ExternalMemoryDevice,
+};
+
+/// Describes a [`DmabufSwapchain`].
+#[derive(Debug, Clone)]
+pub struct DmabufSwapchainDescriptor<'a> {
+    /// Debug label of the textures of the swapchain.
+    pub label: wgpu::Label<'a>,
+
+    /// Width of the textures in pixels.
+    pub width: u32,
+
+    /// Height of the textures in pixels.
+    pub height: u32,
+
+    /// Format of the textures.
+    pub format: TextureFormat,
+
+    /// The fourcc code of the dmabufs, which must have the same memory layout as the texture format.
+    pub fourcc: DrmFourcc,
+
+    /// The modifiers accepted by the consumer of the dmabufs.
+    ///
+    /// If this is empty, any modifier supported by the device may be used.
+    pub modifiers: &'a [DrmModifier],
+
+    /// Allowed usages of the textures.
+    pub usage: TextureUsages,
+
+    /// The maximum number of textures the swapchain will allocate.
+    pub buffer_count: usize,
+}
+
+/// Identifies a buffer of a [`DmabufSwapchain`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BufferId(u64);
+
+/// A buffer acquired from a [`DmabufSwapchain`].
+#[derive(Debug)]
+pub struct SwapchainBuffer<'s> {
+    /// Identifies the buffer when presenting or releasing it.
+    pub id: BufferId,
+
+    /// The texture to render to.
+    pub texture: &'s wgpu::Texture,
+
+    /// The dmabuf backing the texture.
+    pub dmabuf: &'s Dmabuf,
+
+    /// A sync file which is signalled once the consumer is no longer accessing the buffer.
+    ///
+    /// The buffer must not be written to until this fence has signalled.
+    pub release_fence: Option<OwnedFd>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BufferState {
+    /// The buffer may be acquired.
+    Free,
+
+    /// The buffer has been acquired by the producer.
+    Acquired,
+
+    /// The buffer has been presented and is held by the consumer.
+    Held,
+}
+
+#[derive(Debug)]
+struct Buffer {
+    id: BufferId,
+    texture: ExportedTexture,
+    state: BufferState,
+    release_fence: Option<OwnedFd>,
+    /// The configuration the buffer was allocated with.
+    generation: u64,
+}
+
+/// A ring of exportable textures.
+///
+/// Textures are allocated as they are needed, up to the buffer count of the swapchain. Every texture uses the
+/// same modifier, which is chosen by the device from the modifiers accepted by the consumer when the first
+/// texture is allocated.
+///
+/// Changing the size or the accepted modifiers of the swapchain will recreate the textures. Textures held by
+/// the consumer are destroyed once they are released.
+#[derive(Debug)]
+pub struct DmabufSwapchain<'a> {
+    device: &'a ExternalMemoryDevice,
+    label: Option<String>,
+    width: u32,
+    height: u32,
+    format: TextureFormat,
+    fourcc: DrmFourcc,
+    modifiers: Vec<DrmModifier>,
+    usage: TextureUsages,
+    buffer_count: usize,
+    /// The modifier chosen for the current configuration.
+    modifier: Option<DrmModifier>,
+    buffers: Vec<Buffer>,
+    generation: u64,
+    next_id: u64,
+}
+
+impl<'a> DmabufSwapchain<'a> {
+    pub(crate) fn new(device: &'a ExternalMemoryDevice, desc: &DmabufSwapchainDescriptor) -> Self {
+        Self {
+            device,
+            label: desc.label.map(ToOwned::to_owned),
+            width: desc.width,
+            height: desc.height,
+            format: desc.format,
+            fourcc: desc.fourcc,
+            modifiers: desc.modifiers.to_vec(),
+            usage: desc.usage,
+            buffer_count: desc.buffer_count,
+            modifier: None,
+            buffers: Vec::with_capacity(desc.buffer_count),
+            generation: 0,
+            next_id: 0,
+        }
+    }
+
+    /// Acquires the next free buffer.
+    ///
+    /// Returns [`None`] if every buffer has been acquired or is held by the consumer.
+    pub fn acquire(&mut self) -> Result<Option<SwapchainBuffer<'_>>, ExportError> {
+        let generation = self.generation;
+        // Buffers of a previous configuration which are still held by the consumer do not count towards the
+        // buffer count.
+        let allocated = self
+            .buffers
+            .iter()
+            .filter(|buffer| buffer.generation == generation)
+            .count();
+        let free = self.buffers.iter().position(|buffer| {
+            buffer.generation == generation && buffer.state == BufferState::Free
+        });
+
+        let index = match free {
+            Some(index) => index,
+
+            None if allocated < self.buffer_count => {
+                let texture = self.allocate()?;
+                let id = BufferId(self.next_id);
+                self.next_id += 1;
+
+                self.buffers.push(Buffer {
+                    id,
+                    texture,
+                    state: BufferState::Free,
+                    release_fence: None,
+                    generation,
+                });
+
+                self.buffers.len() - 1
+            }
+
+            None => return Ok(None),
+        };
+
+        let buffer = &mut self.buffers[index];
+        buffer.state = BufferState::Acquired;
+
+        Ok(Some(SwapchainBuffer {
+            id: buffer.id,
+            texture: buffer.texture.texture(),
+            dmabuf: buffer.texture.dmabuf(),
+            release_fence: buffer.release_fence.take(),
+        }))
+    }
+
+    /// Marks an acquired buffer as presented, meaning the buffer is now held by the consumer.
+    ///
+    /// Presenting an unknown buffer does nothing, since the buffer may have been destroyed after the swapchain
+    /// was reconfigured.
+    ///
+    /// # Panics
+    /// If the buffer was not acquired.
+    pub fn present(&mut self, id: BufferId) {
+        if let Some(buffer) = self.buffers.iter_mut().find(|buffer| buffer.id == id) {
+            assert_eq!(
+                buffer.state,
+                BufferState::Acquired,
+                "Only acquired buffers may be presented"
+            );
+            buffer.state = BufferState::Held;
+        }
+    }
+
+    /// Releases a buffer, allowing it to be acquired again.
+    ///
+    /// The release fence is returned with the buffer when it is next acquired. Releasing an acquired buffer
+    /// which has not been presented cancels the acquisition.
+    ///
+    /// Releasing an unknown buffer does nothing, since the buffer may have been destroyed after the swapchain
+    /// was reconfigured.
+    pub fn release(&mut self, id: BufferId, release_fence: Option<OwnedFd>) {
+        let generation = self.generation;
+
+        if let Some(index) = self.buffers.iter().position(|buffer| buffer.id == id) {
+            if self.buffers[index].generation != generation {
+                // The buffer belongs to a previous configuration.
+                self.buffers.remove(index);
+                return;
+            }
+
+            let buffer = &mut self.buffers[index];
+            buffer.state = BufferState::Free;
+            buffer.release_fence = release_fence;
+        }
+    }
+
+    /// Changes the size of the textures.
+    ///
+    /// The textures will be recreated if the size is different.
+    pub fn resize(&mut self, width: u32, height: u32) {
+        if self.width != width || self.height != height {
+            self.width = width;
+            self.height = height;
+            self.invalidate();
+        }
+    }
+
+    /// Changes the modifiers accepted by the consumer.
+    ///
+    /// The textures will be recreated if the modifier of the current textures is no longer accepted.
+    pub fn set_modifiers(&mut self, modifiers: &[DrmModifier]) {
+        let still_accepted = match self.modifier {
+            Some(modifier) => modifiers.is_empty() || modifiers.contains(&modifier),
+            None => true,
+        };
+
+        self.modifiers = modifiers.to_vec();
+
+        if !still_accepted {
+            self.invalidate();
+        }
+    }
+
+    /// The modifier of the current textures.
+    ///
+    /// Returns [`None`] if no textures have been allocated since the swapchain was created or reconfigured.
+    pub fn modifier(&self) -> Option<DrmModifier> {
+        self.modifier
+    }
+
+    /// The size of the textures.
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn invalidate(&mut self) {
+        self.generation += 1;
+        self.modifier = None;
+
+        // Free buffers can be destroyed immediately.
+        let generation = self.generation;
+        self.buffers
+            .retain(|buffer| buffer.generation == generation || buffer.state != BufferState::Free);
+    }
+
+    fn allocate(&mut self) -> Result<ExportedTexture, ExportError> {
+        let desc = wgpu::TextureDescriptor {
+            label: self.label.as_deref(),
+            size: Extent3d {
+                width: self.width,
+                height: self.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: self.format,
+            usage: self.usage,
+        };
+
+        // Every buffer uses the modifier chosen for the first buffer.
+        let modifiers = match self.modifier {
+            Some(modifier) => vec![modifier],
+            None => self.modifiers.clone(),
+        };
+
+        let texture = if modifiers.is_empty() {
+            self.device.create_exportable_texture(&desc, self.fourcc)?
+        } else {
+            self.device
+                .create_texture_with_modifiers(&desc, self.fourcc, &modifiers)?
+        };
+
+        self.modifier = Some(texture.modifier());
+        Ok(texture)
+    }
+}
@@ -0,0 +1,645 @@
+//! Sending dmabufs between processes over Unix sockets.
+//!
+//! A dmabuf is sent as a fixed size [`DmabufDescriptor`] followed by the file descriptors of the planes, and
+//! optionally a sync file, which are passed using `SCM_RIGHTS`. The descriptor may also contain the device
+//! UUID of the device which produced the dmabuf, which the receiver can compare against its own
+//! [`DeviceUuids`](crate::adapter::DeviceUuids).
+//!
+//! The sender keeps ownership of its file descriptors, the receiver receives duplicates.
+
+use std::{
+    io::{self, IoSlice, IoSliceMut},
+    os::unix::{
+        io::{AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
+        net::UnixStream,
+    },
+};
+
+use drm_fourcc::{DrmFormat, DrmFourcc, DrmModifier};
+use nix::{
+    cmsg_space,
+    errno::Errno,
+    sys::socket::{recvmsg, sendmsg, ControlMessage, ControlMessageOwned, MsgFlags, UnixAddr},
+};
+
+use crate::{
+    adapter::UUID_LEN,
+    dmabuf::{Dmabuf, DmabufPlane},
+    validation::{PlaneLayout, MAX_PLANES},
+};
+
+/// Identifies a serialised [`DmabufDescriptor`].
+const MAGIC: [u8; 4] = *b"WDMB";
+
+/// The version of the serialisation.
+const VERSION: u32 = 1;
+
+/// The descriptor is followed by a sync file.
+const FLAG_SYNC_FILE: u32 = 1 << 0;
+
+/// The descriptor contains the device UUID of the producer.
+const FLAG_DEVICE_UUID: u32 = 1 << 1;
+
+const HEADER_LEN: usize = 4 // magic
+    + 4 // version
+    + 4 // fourcc
+    + 8 // modifier
+    + 4 // width
+    + 4 // height
+    + 4 // plane count
+    + 4 // flags
+    + UUID_LEN;
+
+/// The size of a serialised [`DmabufDescriptor`] in bytes.
+pub const DESCRIPTOR_LEN: usize = HEADER_LEN + MAX_PLANES * 8;
+
+/// Error returned when sending or receiving a dmabuf.
+#[derive(Debug, thiserror::Error)]
+pub enum TransportError {
+    /// The dmabuf has more planes than may be sent.
+    #[error("the dmabuf has {0} planes, at most {MAX_PLANES} planes are supported")]
+    TooManyPlanes(usize),
+
+    /// The received data is not a serialised dmabuf descriptor.
+    #[error("the message is not a dmabuf descriptor")]
+    InvalidMagic,
+
+    /// The descriptor was serialised by an incompatible version.
+    #[error("unsupported descriptor version {0}")]
+    UnsupportedVersion(u32),
+
+    /// The fourcc code of the descriptor is not known.
+    #[error("unknown fourcc code {0:#x}")]
+    UnknownFourcc(u32),
+
+    /// The descriptor is invalid.
+    #[error("the descriptor is malformed")]
+    Malformed,
+
+    /// Fewer bytes than a descriptor were received.
+    #[error("received {0} bytes, but a descriptor is {DESCRIPTOR_LEN} bytes")]
+    Truncated(usize),
+
+    /// The number of received file descriptors does not match the descriptor.
+    #[error("expected {expected} file descriptors, but received {actual}")]
+    FdCountMismatch { expected: usize, actual: usize },
+
+    /// The peer closed the connection.
+    #[error("the connection was closed")]
+    Closed,
+
+    /// An error occurred while sending or receiving.
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// Describes a dmabuf without its file descriptors.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DmabufDescriptor {
+    /// The fourcc code and modifier of the image.
+    pub format: DrmFormat,
+
+    /// Width of the image in pixels.
+    pub width: u32,
+
+    /// Height of the image in pixels.
+    pub height: u32,
+
+    /// The layout of each plane.
+    pub planes: Vec<PlaneLayout>,
+
+    /// Whether the file descriptors are followed by a sync file.
+    pub sync_file: bool,
+
+    /// The device UUID of the device which produced the dmabuf.
+    pub device_uuid: Option<[u8; UUID_LEN]>,
+}
+
+impl DmabufDescriptor {
+    /// The number of file descriptors sent with the descriptor.
+    pub fn fd_count(&self) -> usize {
+        self.planes.len() + usize::from(self.sync_file)
+    }
+
+    /// Serialises the descriptor.
+    pub fn to_bytes(&self) -> Result<[u8; DESCRIPTOR_LEN], TransportError> {
+        if self.planes.len() > MAX_PLANES {
+            return Err(TransportError::TooManyPlanes(self.planes.len()));
+        }
+
+        let mut flags = 0;
+
+        if self.sync_file {
+            flags |= FLAG_SYNC_FILE;
+        }
+
+        if self.device_uuid.is_some() {
+            flags |= FLAG_DEVICE_UUID;
+        }
+
+        let mut bytes = [0; DESCRIPTOR_LEN];
+        let mut writer = Writer(&mut bytes[..]);
+        writer.write(&MAGIC);
+        writer.write(&VERSION.to_le_bytes());
+        writer.write(&(self.format.code as u32).to_le_bytes());
+        writer.write(&u64::from(self.format.modifier).to_le_bytes());
+        writer.write(&self.width.to_le_bytes());
+        writer.write(&self.height.to_le_bytes());
+        writer.write(&(self.planes.len() as u32).to_le_bytes());
+        writer.write(&flags.to_le_bytes());
+        writer.write(&self.device_uuid.unwrap_or_default());
+
+        for plane in &self.planes {
+            writer.write(&plane.offset.to_le_bytes());
+            writer.write(&plane.stride.to_le_bytes());
+        }
+
+        Ok(bytes)
+    }
+
+    /// Deserialises a descriptor.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, TransportError> {
+        if bytes.len() < DESCRIPTOR_LEN {
+            return Err(TransportError::Truncated(bytes.len()));
+        }
+
+        let mut reader = Reader(bytes);
+
+        if reader.read::<4>() != MAGIC {
+            return Err(TransportError::InvalidMagic);
+        }
+
+        let version = u32::from_le_bytes(reader.read());
+
+        if version != VERSION {
+            return Err(TransportError::UnsupportedVersion(version));
+        }
+
+        let code = u32::from_le_bytes(reader.read());
+        let code = DrmFourcc::try_from(code).map_err(|_| TransportError::UnknownFourcc(code))?;
+        let modifier = DrmModifier::from(u64::from_le_bytes(reader.read()));
+        let width = u32::from_le_bytes(reader.read());
+        let height = u32::from_le_bytes(reader.read());
+        let plane_count = u32::from_le_bytes(reader.read()) as usize;
+        let flags = u32::from_le_bytes(reader.read());
+        let device_uuid = reader.read::<UUID_LEN>();
+
+        if plane_count == 0
+            || plane_count > MAX_PLANES
+            || flags & !(FLAG_SYNC_FILE | FLAG_DEVICE_UUID) != 0
+        {
+            return Err(TransportError::Malformed);
+        }
+
+        let planes = (0..plane_count)
+            .map(|_| PlaneLayout {
+                offset: u32::from_le_bytes(reader.read()),
+                stride: u32::from_le_bytes(reader.read()),
+            })
+            .collect();
+
+        Ok(Self {
+            format: DrmFormat { code, modifier },
+            width,
+            height,
+            planes,
+            sync_file: flags & FLAG_SYNC_FILE != 0,
+            device_uuid: Some(device_uuid).filter(|_| flags & FLAG_DEVICE_UUID != 0),
+        })
+    }
+}
+
+/// A dmabuf received from another process.
+#[derive(Debug)]
+pub struct ReceivedDmabuf {
+    /// The received dmabuf.
+    pub dmabuf: Dmabuf,
+
+    /// A sync file which is signalled once the producer has finished writing to the dmabuf.
+    pub sync_file: Option<OwnedFd>,
+
+    /// The device UUID of the device which produced the dmabuf.
+    pub device_uuid: Option<[u8; UUID_LEN]>,
+}
+
+/// Sends a dmabuf, an optional sync file and the device UUID of the producer over a Unix socket.
+pub fn send_dmabuf(
+    stream: &UnixStream,
+    dmabuf: &Dmabuf,
+    sync_file: Option<BorrowedFd<'_>>,
+    device_uuid: Option<[u8; UUID_LEN]>,
+) -> Result<(), TransportError> {
+    let descriptor = DmabufDescriptor {
+        format: dmabuf.format,
+        width: dmabuf.width,
+        height: dmabuf.height,
+        planes: dmabuf
+            .planes
+            .iter()
+            .map(|plane| PlaneLayout {
+                offset: plane.offset,
+                stride: plane.stride,
+            })
+            .collect(),
+        sync_file: sync_file.is_some(),
+        device_uuid,
+    };
+    let bytes = descriptor.to_bytes()?;
+
+    let fds = dmabuf
+        .planes
+        .iter()
+        .map(|plane| plane.fd.as_raw_fd())
+        .chain(sync_file.map(|fd| fd.as_raw_fd()))
+        .collect::<Vec<_>>();
+
+    let iov = [IoSlice::new(&bytes)];
+    let cmsgs = [ControlMessage::ScmRights(&fds)];
+    let sent = loop {
+        match sendmsg::<UnixAddr>(stream.as_raw_fd(), &iov, &cmsgs, MsgFlags::empty(), None) {
+            Ok(sent) => break sent,
+            // Nothing was sent if the call was interrupted, so the file descriptors are sent again.
+            Err(Errno::EINTR) => (),
+            Err(err) => return Err(io::Error::from(err).into()),
+        }
+    };
+
+    // The file descriptors are sent with the first byte, so the rest of a partially sent descriptor is
+    // sent without them.
+    let mut rest = &bytes[sent..];
+
+    while !rest.is_empty() {
+        match nix::unistd::write(stream.as_raw_fd(), rest) {
+            Ok(sent) => rest = &rest[sent..],
+            Err(Errno::EINTR) => (),
+            Err(err) => return Err(io::Error::from(err).into()),
+        }
+    }
+
+    Ok(())
+}
+
+/// Receives a dmabuf sent using [`send_dmabuf`].
+///
+/// The peer is not trusted, so the dmabuf should be checked using
+/// [`validate_dmabuf`](crate::validation::validate_dmabuf) before it is imported.
+pub fn recv_dmabuf(stream: &UnixStream) -> Result<ReceivedDmabuf, TransportError> {
+    let mut bytes = [0; DESCRIPTOR_LEN];
+    let mut cmsg_buffer = cmsg_space!([RawFd; MAX_PLANES + 1]);
+
+    let (received, fds, truncated) = {
+        let mut iov = [IoSliceMut::new(&mut bytes)];
+        let msg = loop {
+            match recvmsg::<UnixAddr>(
+                stream.as_raw_fd(),
+                &mut iov,
+                Some(&mut cmsg_buffer),
+                MsgFlags::MSG_CMSG_CLOEXEC,
+            ) {
+                Ok(msg) => break msg,
+                Err(Errno::EINTR) => (),
+                Err(err) => return Err(io::Error::from(err).into()),
+            }
+        };
+
+        // Take ownership of the file descriptors first, so they are closed if the message is invalid.
+        let fds = msg
+            .cmsgs()
+            .filter_map(|cmsg| match cmsg {
+                ControlMessageOwned::ScmRights(fds) => Some(fds),
+                _ => None,
+            })
+            .flatten()
+            // SAFETY: The received file descriptors are new file descriptors owned by this process.
+            .map(|fd| unsafe { OwnedFd::from_raw_fd(fd) })
+            .collect::<Vec<_>>();
+
+        (msg.bytes, fds, msg.flags.contains(MsgFlags::MSG_CTRUNC))
+    };
+
+    if received == 0 {
+        return Err(TransportError::Closed);
+    }
+
+    // The rest of the descriptor may arrive separately from the file descriptors.
+    let mut filled = received;
+
+    while filled < DESCRIPTOR_LEN {
+        match nix::unistd::read(stream.as_raw_fd(), &mut bytes[filled..]) {
+            Ok(0) => return Err(TransportError::Truncated(filled)),
+            Ok(read) => filled += read,
+            Err(Errno::EINTR) => (),
+            Err(err) => return Err(io::Error::from(err).into()),
+        }
+    }
+
+    let descriptor = DmabufDescriptor::from_bytes(&bytes)?;
+    let expected = descriptor.fd_count();
+
+    // More file descriptors were sent than fit in the buffer.
+    if truncated || fds.len() != expected {
+        return Err(TransportError::FdCountMismatch {
+            expected,
+            actual: fds.len(),
+        });
+    }
+
+    let mut fds = fds.into_iter();
+    let planes = descriptor
+        .planes
+        .iter()
+        .zip(&mut fds)
+        .map(|(layout, fd)| DmabufPlane {
+            fd,
+            offset: layout.offset,
+            stride: layout.stride,
+        })
+        .collect();
+
+    Ok(ReceivedDmabuf {
+        dmabuf: Dmabuf {
+            format: descriptor.format,
+            width: descriptor.width,
+            height: descriptor.height,
+            planes,
+        },
+        sync_file: fds.next(),
+        device_uuid: descriptor.device_uuid,
+    })
+}
+
+struct Writer<'a>(&'a mut [u8]);
+
+impl Writer<'_> {
+    fn write(&mut self, bytes: &[u8]) {
+        let (head, tail) = std::mem::take(&mut self.0).split_at_mut(bytes.len());
+        head.copy_from_slice(bytes);
+        self.0 = tail;
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn read<const N: usize>(&mut self) -> [u8; N] {
+        let (head, tail) = self.0.split_at(N);
+        self.0 = tail;
+        head.try_into().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        ffi::CStr,
+        fs::File,
+        os::unix::{fs::MetadataExt, io::AsFd},
+    };
+
+    use nix::sys::memfd::{memfd_create, MemFdCreateFlag};
+
+    use super::*;
+
+    const NV12_LINEAR: DrmFormat = DrmFormat {
+        code: DrmFourcc::Nv12,
+        modifier: DrmModifier::Linear,
+    };
+
+    fn descriptor() -> DmabufDescriptor {
+        DmabufDescriptor {
+            format: NV12_LINEAR,
+            width: 64,
+            height: 32,
+            planes: vec![
+                PlaneLayout {
+                    offset: 0,
+                    stride: 64,
+                },
+                PlaneLayout {
+                    offset: 2048,
+                    stride: 64,
+                },
+            ],
+            sync_file: true,
+            device_uuid: Some([7; UUID_LEN]),
+        }
+    }
+
+    fn memfd() -> File {
+        let name = CStr::from_bytes_with_nul(b"wgpu-dmabuf-test\0").unwrap();
+        let fd = memfd_create(name, MemFdCreateFlag::MFD_CLOEXEC).unwrap();
+        // SAFETY: memfd_create returns a new file descriptor which we now own.
+        unsafe { File::from_raw_fd(fd) }
+    }
+
+    fn dmabuf() -> Dmabuf {
+        let descriptor = descriptor();
+
+        Dmabuf {
+            format: descriptor.format,
+            width: descriptor.width,
+            height: descriptor.height,
+            planes: descriptor
+                .planes
+                .iter()
+                .map(|layout| DmabufPlane {
+                    fd: memfd().into(),
+                    offset: layout.offset,
+                    stride: layout.stride,
+                })
+                .collect(),
+        }
+    }
+
+    fn inode(fd: impl AsFd) -> u64 {
+        let file = File::from(fd.as_fd().try_clone_to_owned().unwrap());
+        file.metadata().unwrap().ino()
+    }
+
+    #[test]
+    fn descriptor_round_trip() {
+        let descriptor = descriptor();
+        let bytes = descriptor.to_bytes().unwrap();
+        assert_eq!(DmabufDescriptor::from_bytes(&bytes).unwrap(), descriptor);
+
+        let descriptor = DmabufDescriptor {
+            sync_file: false,
+            device_uuid: None,
+            ..descriptor
+        };
+        let bytes = descriptor.to_bytes().unwrap();
+        assert_eq!(DmabufDescriptor::from_bytes(&bytes).unwrap(), descriptor);
+    }
+
+    #[test]
+    fn descriptor_too_many_planes() {
+        let descriptor = DmabufDescriptor {
+            planes: vec![
+                PlaneLayout {
+                    offset: 0,
+                    stride: 64,
+                };
+                MAX_PLANES + 1
+            ],
+            ..descriptor()
+        };
+
+        assert!(matches!(
+            descriptor.to_bytes(),
+            Err(TransportError::TooManyPlanes(5))
+        ));
+    }
+
+    #[test]
+    fn descriptor_malformed() {
+        let bytes = descriptor().to_bytes().unwrap();
+        let modified = |offset: usize, value: &[u8]| {
+            let mut bytes = bytes;
+            bytes[offset..offset + value.len()].copy_from_slice(value);
+            DmabufDescriptor::from_bytes(&bytes)
+        };
+
+        assert!(matches!(
+            DmabufDescriptor::from_bytes(&bytes[..DESCRIPTOR_LEN - 1]),
+            Err(TransportError::Truncated(len)) if len == DESCRIPTOR_LEN - 1
+        ));
+        assert!(matches!(
+            modified(0, b"XXXX"),
+            Err(TransportError::InvalidMagic)
+        ));
+        assert!(matches!(
+            modified(4, &2u32.to_le_bytes()),
+            Err(TransportError::UnsupportedVersion(2))
+        ));
+        assert!(matches!(
+            modified(8, &0u32.to_le_bytes()),
+            Err(TransportError::UnknownFourcc(0))
+        ));
+        // Plane counts of zero and more than the maximum, and unknown flags.
+        assert!(matches!(
+            modified(28, &0u32.to_le_bytes()),
+            Err(TransportError::Malformed)
+        ));
+        assert!(matches!(
+            modified(28, &5u32.to_le_bytes()),
+            Err(TransportError::Malformed)
+        ));
+        assert!(matches!(
+            modified(32, &(1u32 << 2).to_le_bytes()),
+            Err(TransportError::Malformed)
+        ));
+    }
+
+    #[test]
+    fn send_recv() {
+        let (sender, receiver) = UnixStream::pair().unwrap();
+        let dmabuf = dmabuf();
+        let sync_file = memfd();
+
+        send_dmabuf(
+            &sender,
+            &dmabuf,
+            Some(sync_file.as_fd()),
+            Some([7; UUID_LEN]),
+        )
+        .unwrap();
+        let received = recv_dmabuf(&receiver).unwrap();
+
+        assert_eq!(received.dmabuf.format, dmabuf.format);
+        assert_eq!(received.dmabuf.width, dmabuf.width);
+        assert_eq!(received.dmabuf.height, dmabuf.height);
+        assert_eq!(received.dmabuf.planes.len(), dmabuf.planes.len());
+        assert_eq!(received.device_uuid, Some([7; UUID_LEN]));
+
+        // The receiver gets duplicates of the file descriptors of the sender.
+        for (received, sent) in received.dmabuf.planes.iter().zip(&dmabuf.planes) {
+            assert_eq!(received.offset, sent.offset);
+            assert_eq!(received.stride, sent.stride);
+            assert_ne!(received.fd.as_raw_fd(), sent.fd.as_raw_fd());
+            assert_eq!(inode(&received.fd), inode(&sent.fd));
+        }
+
+        assert_eq!(inode(received.sync_file.unwrap()), inode(&sync_file));
+    }
+
+    #[test]
+    fn send_recv_without_sync_file() {
+        let (sender, receiver) = UnixStream::pair().unwrap();
+
+        send_dmabuf(&sender, &dmabuf(), None, None).unwrap();
+        let received = recv_dmabuf(&receiver).unwrap();
+
+        assert_eq!(received.dmabuf.planes.len(), 2);
+        assert!(received.sync_file.is_none());
+        assert!(received.device_uuid.is_none());
+    }
+
+    #[test]
+    fn recv_split_descriptor() {
+        let (sender, receiver) = UnixStream::pair().unwrap();
+        let plane = memfd();
+        let bytes = DmabufDescriptor {
+            planes: vec![PlaneLayout {
+                offset: 0,
+                stride: 64,
+            }],
+            sync_file: false,
+            ..descriptor()
+        }
+        .to_bytes()
+        .unwrap();
+
+        // Only part of the descriptor is sent with the file descriptors.
+        let fds = [plane.as_raw_fd()];
+        sendmsg::<UnixAddr>(
+            sender.as_raw_fd(),
+            &[IoSlice::new(&bytes[..8])],
+            &[ControlMessage::ScmRights(&fds)],
+            MsgFlags::empty(),
+            None,
+        )
+        .unwrap();
+        nix::unistd::write(sender.as_raw_fd(), &bytes[8..]).unwrap();
+
+        let received = recv_dmabuf(&receiver).unwrap();
+        assert_eq!(received.dmabuf.planes.len(), 1);
+        assert_eq!(inode(&received.dmabuf.planes[0].fd), inode(&plane));
+    }
+
+    #[test]
+    fn recv_fd_count_mismatch() {
+        let (sender, receiver) = UnixStream::pair().unwrap();
+        let plane = memfd();
+
+        // The descriptor announces two planes and a sync file, but only one file descriptor is sent.
+        let bytes = descriptor().to_bytes().unwrap();
+        let fds = [plane.as_raw_fd()];
+        sendmsg::<UnixAddr>(
+            sender.as_raw_fd(),
+            &[IoSlice::new(&bytes)],
+            &[ControlMessage::ScmRights(&fds)],
+            MsgFlags::empty(),
+            None,
+        )
+        .unwrap();
+
+        assert!(matches!(
+            recv_dmabuf(&receiver),
+            Err(TransportError::FdCountMismatch {
+                expected: 3,
+                actual: 1
+            })
+        ));
+    }
+
+    #[test]
+    fn recv_closed() {
+        let (sender, receiver) = UnixStream::pair().unwrap();
+        drop(sender);
+
+        assert!(matches!(
+            recv_dmabuf(&receiver),
+            Err(TransportError::Closed)
+        ));
+    }
+}
@@ -0,0 +1,494 @@
+//! Allocating dmabufs from system memory using `/dev/udmabuf`.
+//!
+//! udmabuf creates a dmabuf from the pages of a memfd. This allows producing real dmabufs without a GPU, for
+//! example to test importing, validating and sending dmabufs, or to share images rendered on the CPU.
+//!
+//! Images are stored with the linear modifier. The memfd is returned with the dmabuf, so the image may be
+//! written by mapping the memfd.
+
+use std::{
+    ffi::CStr,
+    fs::{File, OpenOptions},
+    io,
+    os::unix::{
+        fs::OpenOptionsExt,
+        io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd},
+    },
+    path::Path,
+    ptr,
+};
+
+use drm_fourcc::{DrmFormat, DrmFourcc, DrmModifier};
+use nix::{
+    fcntl::{fcntl, FcntlArg, SealFlag},
+    libc,
+    sys::memfd::{memfd_create, MemFdCreateFlag},
+    unistd::ftruncate,
+};
+
+use crate::{
+    dmabuf::{Dmabuf, DmabufPlane},
+    validation::{self, PlaneLayout},
+};
+
+/// The path of the udmabuf device.
+pub const UDMABUF_PATH: &str = "/dev/udmabuf";
+
+/// The alignment of the stride of each plane in bytes.
+///
+/// This satisfies the stride alignment most GPUs require to import linear images.
+pub const STRIDE_ALIGNMENT: u32 = 256;
+
+/// Describes a dmabuf allocated by an [`UdmabufAllocator`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UdmabufDescriptor {
+    /// The fourcc code of the image.
+    pub fourcc: DrmFourcc,
+
+    /// Width of the image in pixels.
+    pub width: u32,
+
+    /// Height of the image in pixels.
+    pub height: u32,
+
+    /// Whether each plane is stored in a separate dmabuf.
+    pub disjoint: bool,
+}
+
+/// A range of a memfd which is part of a udmabuf.
+#[derive(Debug, Clone, Copy)]
+pub struct UdmabufRange<'a> {
+    /// The memfd, which must be sealed against shrinking and must not be sealed against writing.
+    pub memfd: BorrowedFd<'a>,
+
+    /// Offset of the range in bytes, which must be a multiple of the page size.
+    pub offset: u64,
+
+    /// Size of the range in bytes, which must be a multiple of the page size.
+    pub size: u64,
+}
+
+/// Error returned when allocating a udmabuf.
+#[derive(Debug, thiserror::Error)]
+pub enum UdmabufError {
+    /// The memory layout of the fourcc code is not known.
+    #[error("the format {0} is not supported")]
+    UnsupportedFormat(DrmFourcc),
+
+    /// The size of the image is zero or too large.
+    #[error("invalid image size")]
+    InvalidSize,
+
+    /// The memfd or udmabuf could not be created.
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// A dmabuf allocated by an [`UdmabufAllocator`].
+#[derive(Debug)]
+pub struct UdmabufBuffer {
+    /// The dmabuf, which uses the linear modifier.
+    pub dmabuf: Dmabuf,
+
+    /// The memfd storing the planes of the dmabuf, which may be mapped to write the image.
+    ///
+    /// The planes are stored at the offsets returned by [`linear_layout`], including if the dmabuf is disjoint.
+    pub memfd: OwnedFd,
+
+    /// Size of the memfd in bytes.
+    pub size: u64,
+}
+
+/// Allocates dmabufs from system memory.
+#[derive(Debug)]
+pub struct UdmabufAllocator {
+    device: File,
+}
+
+impl UdmabufAllocator {
+    /// Opens `/dev/udmabuf`.
+    pub fn new() -> io::Result<Self> {
+        Self::open(UDMABUF_PATH)
+    }
+
+    /// Opens a udmabuf device at the path.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let device = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_CLOEXEC)
+            .open(path)?;
+
+        Ok(Self { device })
+    }
+
+    /// Allocates a zeroed dmabuf with the linear modifier.
+    pub fn allocate(&self, desc: &UdmabufDescriptor) -> Result<UdmabufBuffer, UdmabufError> {
+        let page_size = page_size();
+        let (planes, size) = linear_layout(desc.fourcc, desc.width, desc.height, page_size)?;
+        let memfd = create_memfd(size)?;
+
+        let planes = if desc.disjoint {
+            let ends = planes
+                .iter()
+                .skip(1)
+                .map(|plane| u64::from(plane.offset))
+                .chain([size]);
+
+            // Every plane starts on a page, so each plane may be turned into a separate dmabuf.
+            planes
+                .iter()
+                .zip(ends)
+                .map(|(layout, end)| {
+                    let range = UdmabufRange {
+                        memfd: memfd.as_fd(),
+                        offset: u64::from(layout.offset),
+                        size: end - u64::from(layout.offset),
+                    };
+
+                    Ok(DmabufPlane {
+                        fd: self.create(&range)?,
+                        offset: 0,
+                        stride: layout.stride,
+                    })
+                })
+                .collect::<io::Result<Vec<_>>>()?
+        } else {
+            let fd = self.create(&UdmabufRange {
+                memfd: memfd.as_fd(),
+                offset: 0,
+                size,
+            })?;
+
+            // Each plane owns a file descriptor of the same dmabuf.
+            planes
+                .iter()
+                .map(|layout| {
+                    Ok(DmabufPlane {
+                        fd: fd.try_clone()?,
+                        offset: layout.offset,
+                        stride: layout.stride,
+                    })
+                })
+                .collect::<io::Result<Vec<_>>>()?
+        };
+
+        Ok(UdmabufBuffer {
+            dmabuf: Dmabuf {
+                format: DrmFormat {
+                    code: desc.fourcc,
+                    modifier: DrmModifier::Linear,
+                },
+                width: desc.width,
+                height: desc.height,
+                planes,
+            },
+            memfd,
+            size,
+        })
+    }
+
+    /// Creates a dmabuf from a range of a memfd using `UDMABUF_CREATE`.
+    pub fn create(&self, range: &UdmabufRange) -> io::Result<OwnedFd> {
+        let create = ioctl::udmabuf_create {
+            memfd: range.memfd.as_raw_fd() as u32,
+            flags: ioctl::UDMABUF_FLAGS_CLOEXEC,
+            offset: range.offset,
+            size: range.size,
+        };
+
+        let fd = unsafe { ioctl::create(self.device.as_raw_fd(), &create) }?;
+
+        // SAFETY: UDMABUF_CREATE returns a new file descriptor which we now own.
+        Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+    }
+
+    /// Creates a dmabuf from ranges of one or more memfds using `UDMABUF_CREATE_LIST`.
+    ///
+    /// The ranges are concatenated in order.
+    pub fn create_list(&self, ranges: &[UdmabufRange]) -> io::Result<OwnedFd> {
+        // The list is a header followed by the items, which are both aligned to 8 bytes.
+        let header_len = std::mem::size_of::<ioctl::udmabuf_create_list>() / 8;
+        let item_len = std::mem::size_of::<ioctl::udmabuf_create_item>() / 8;
+        let mut list = vec![0u64; header_len + ranges.len() * item_len];
+
+        let fd = unsafe {
+            let header = list.as_mut_ptr() as *mut ioctl::udmabuf_create_list;
+            ptr::write(
+                header,
+                ioctl::udmabuf_create_list {
+                    flags: ioctl::UDMABUF_FLAGS_CLOEXEC,
+                    count: ranges.len() as u32,
+                },
+            );
+
+            let items = list.as_mut_ptr().add(header_len) as *mut ioctl::udmabuf_create_item;
+
+            for (index, range) in ranges.iter().enumerate() {
+                ptr::write(
+                    items.add(index),
+                    ioctl::udmabuf_create_item {
+                        memfd: range.memfd.as_raw_fd() as u32,
+                        pad: 0,
+                        offset: range.offset,
+                        size: range.size,
+                    },
+                );
+            }
+
+            ioctl::create_list(self.device.as_raw_fd(), header)
+        }?;
+
+        // SAFETY: UDMABUF_CREATE_LIST returns a new file descriptor which we now own.
+        Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+    }
+}
+
+/// Computes the layout of a linear image with every plane stored in a single buffer.
+///
+/// Strides are aligned to [`STRIDE_ALIGNMENT`] and each plane starts at a multiple of `plane_alignment`.
+/// Returns the layout of each plane and the size of the buffer, which is also a multiple of
+/// `plane_alignment`.
+pub fn linear_layout(
+    fourcc: DrmFourcc,
+    width: u32,
+    height: u32,
+    plane_alignment: u64,
+) -> Result<(Vec<PlaneLayout>, u64), UdmabufError> {
+    let plane_formats =
+        validation::plane_formats(fourcc).ok_or(UdmabufError::UnsupportedFormat(fourcc))?;
+
+    if width == 0 || height == 0 {
+        return Err(UdmabufError::InvalidSize);
+    }
+
+    let mut size = 0u64;
+
+    let planes = plane_formats
+        .iter()
+        .map(|format| {
+            let row = validation::div_ceil(width, format.hsub) * format.cpp;
+            let stride = align(row, u64::from(STRIDE_ALIGNMENT))
+                .and_then(|stride| u32::try_from(stride).ok())
+                .ok_or(UdmabufError::InvalidSize)?;
+            let offset = u32::try_from(size).map_err(|_| UdmabufError::InvalidSize)?;
+
+            let plane_size = u64::from(stride) * validation::div_ceil(height, format.vsub);
+            size = size
+                .checked_add(plane_size)
+                .and_then(|end| align(end, plane_alignment))
+                .ok_or(UdmabufError::InvalidSize)?;
+
+            Ok(PlaneLayout { offset, stride })
+        })
+        .collect::<Result<Vec<_>, UdmabufError>>()?;
+
+    Ok((planes, size))
+}
+
+/// Creates a memfd of the size which is sealed against resizing, as udmabuf requires.
+fn create_memfd(size: u64) -> io::Result<OwnedFd> {
+    let name = unsafe { CStr::from_bytes_with_nul_unchecked(b"wgpu-drm-udmabuf\0") };
+    let fd = memfd_create(
+        name,
+        MemFdCreateFlag::MFD_CLOEXEC | MemFdCreateFlag::MFD_ALLOW_SEALING,
+    )?;
+    // SAFETY: memfd_create returns a new file descriptor which we now own.
+    let memfd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    let size =
+        libc::off_t::try_from(size).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+    ftruncate(memfd.as_raw_fd(), size)?;
+    fcntl(
+        memfd.as_raw_fd(),
+        FcntlArg::F_ADD_SEALS(
+            SealFlag::F_SEAL_SHRINK | SealFlag::F_SEAL_GROW | SealFlag::F_SEAL_SEAL,
+        ),
+    )?;
+
+    Ok(memfd)
+}
+
+fn page_size() -> u64 {
+    // SAFETY: sysconf has no safety requirements.
+    match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+        size if size > 0 => size as u64,
+        _ => 4096,
+    }
+}
+
+fn align(value: u64, alignment: u64) -> Option<u64> {
+    let alignment = alignment.max(1);
+
+    value
+        .checked_add(alignment - 1)
+        .map(|value| value / alignment * alignment)
+}
+
+/// udmabuf ioctls from `linux/udmabuf.h`.
+#[allow(non_camel_case_types)]
+mod ioctl {
+    use nix::ioctl_write_ptr;
+
+    const UDMABUF_IOCTL_BASE: u8 = b'u';
+
+    pub const UDMABUF_FLAGS_CLOEXEC: u32 = 0x01;
+
+    #[repr(C)]
+    #[derive(Debug, Default)]
+    pub struct udmabuf_create {
+        pub memfd: u32,
+        pub flags: u32,
+        pub offset: u64,
+        pub size: u64,
+    }
+
+    #[repr(C)]
+    #[derive(Debug, Default)]
+    pub struct udmabuf_create_item {
+        pub memfd: u32,
+        pub pad: u32,
+        pub offset: u64,
+        pub size: u64,
+    }
+
+    /// Followed by `count` items.
+    #[repr(C)]
+    #[derive(Debug, Default)]
+    pub struct udmabuf_create_list {
+        pub flags: u32,
+        pub count: u32,
+    }
+
+    ioctl_write_ptr!(create, UDMABUF_IOCTL_BASE, 0x42, udmabuf_create);
+    ioctl_write_ptr!(create_list, UDMABUF_IOCTL_BASE, 0x43, udmabuf_create_list);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::validation::{validate_dmabuf, DmabufConstraints};
+
+    const PAGE_SIZE: u64 = 4096;
+
+    fn layout(offset: u32, stride: u32) -> PlaneLayout {
+        PlaneLayout { offset, stride }
+    }
+
+    #[test]
+    fn layout_single_plane() {
+        let (planes, size) = linear_layout(DrmFourcc::Xrgb8888, 100, 10, PAGE_SIZE).unwrap();
+
+        // A row of 400 bytes is aligned to 512 bytes, and the buffer to a page.
+        assert_eq!(planes, [layout(0, 512)]);
+        assert_eq!(size, 8192);
+    }
+
+    #[test]
+    fn layout_nv12() {
+        let (planes, size) = linear_layout(DrmFourcc::Nv12, 100, 51, PAGE_SIZE).unwrap();
+
+        // The luma plane is 256 * 51 bytes, so the chroma plane starts on the following page. The chroma plane
+        // has 50 samples of two bytes per row and 26 rows.
+        assert_eq!(planes, [layout(0, 256), layout(16384, 256)]);
+        assert_eq!(size, 16384 + 8192);
+    }
+
+    #[test]
+    fn layout_yuv420() {
+        let (planes, size) = linear_layout(DrmFourcc::Yuv420, 640, 480, PAGE_SIZE).unwrap();
+
+        // The luma plane is 768 * 480 bytes and each chroma plane is 512 * 240 bytes, rounded up to a page.
+        assert_eq!(
+            planes,
+            [layout(0, 768), layout(368640, 512), layout(491520, 512)]
+        );
+        assert_eq!(size, 614400);
+    }
+
+    #[test]
+    fn layout_alignment() {
+        for fourcc in [
+            DrmFourcc::Rgb565,
+            DrmFourcc::Rgb888,
+            DrmFourcc::Argb8888,
+            DrmFourcc::Abgr16161616f,
+            DrmFourcc::Yuyv,
+            DrmFourcc::Nv12,
+            DrmFourcc::P010,
+            DrmFourcc::Yuv420,
+            DrmFourcc::Yuv444,
+        ] {
+            let (planes, size) = linear_layout(fourcc, 333, 77, PAGE_SIZE).unwrap();
+
+            assert_eq!(size % PAGE_SIZE, 0, "{}", fourcc);
+
+            for plane in &planes {
+                assert_eq!(plane.stride % STRIDE_ALIGNMENT, 0, "{}", fourcc);
+                assert_eq!(u64::from(plane.offset) % PAGE_SIZE, 0, "{}", fourcc);
+            }
+
+            // The layout must pass validation.
+            let ends = validation::validate_layout(
+                DrmFormat {
+                    code: fourcc,
+                    modifier: DrmModifier::Linear,
+                },
+                333,
+                77,
+                &planes,
+                &DmabufConstraints::default(),
+            )
+            .unwrap();
+            assert!(ends.iter().all(|&end| end <= size), "{}", fourcc);
+        }
+    }
+
+    #[test]
+    fn layout_invalid() {
+        assert!(matches!(
+            linear_layout(DrmFourcc::Q410, 16, 16, PAGE_SIZE),
+            Err(UdmabufError::UnsupportedFormat(DrmFourcc::Q410))
+        ));
+        assert!(matches!(
+            linear_layout(DrmFourcc::Xrgb8888, 0, 16, PAGE_SIZE),
+            Err(UdmabufError::InvalidSize)
+        ));
+        // The stride does not fit in 32 bits.
+        assert!(matches!(
+            linear_layout(DrmFourcc::Xrgb8888, u32::MAX, 1, PAGE_SIZE),
+            Err(UdmabufError::InvalidSize)
+        ));
+    }
+
+    #[test]
+    fn allocate() {
+        let allocator = match UdmabufAllocator::new() {
+            Ok(allocator) => allocator,
+            // udmabuf is not available or accessible everywhere tests are run.
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::NotFound | io::ErrorKind::PermissionDenied
+                ) =>
+            {
+                return
+            }
+            Err(err) => panic!("failed to open {}: {}", UDMABUF_PATH, err),
+        };
+
+        for disjoint in [false, true] {
+            let desc = UdmabufDescriptor {
+                fourcc: DrmFourcc::Nv12,
+                width: 100,
+                height: 51,
+                disjoint,
+            };
+            let buffer = allocator.allocate(&desc).unwrap();
+
+            assert_eq!(buffer.dmabuf.planes.len(), 2);
+            assert_eq!(buffer.size % page_size(), 0);
+            validate_dmabuf(&buffer.dmabuf, &DmabufConstraints::default()).unwrap();
+        }
+    }
+}
@@ -0,0 +1,751 @@
+//! Validation of dmabufs received from untrusted sources.
+//!
+//! Dmabufs received from clients may describe planes which do not fit in the dmabuf, use file descriptors
+//! which are closed, have more planes than the modifier uses, or have strides and offsets which split pixels.
+//! Importing such a dmabuf may fail deep inside the driver or, worse, succeed and read out of bounds.
+//!
+//! The strides and offsets of linear planes must be aligned to the pixels of the plane. Neither Vulkan nor EGL
+//! report any further alignment a driver requires of imported planes, so that is left for the driver to
+//! check.
+//!
+//! The checks here do not use a device, so a dmabuf may be validated before a device is chosen. The
+//! constraints of a device for a format may be queried using [`ExternalMemoryDevice::dmabuf_constraints`].
+//!
+//! [`ExternalMemoryDevice::dmabuf_constraints`]: crate::ExternalMemoryDevice::dmabuf_constraints
+
+use std::{
+    io,
+    os::unix::io::{AsRawFd, RawFd},
+};
+
+use drm_fourcc::{DrmFormat, DrmFourcc, DrmModifier};
+use nix::{
+    errno::Errno,
+    fcntl::{fcntl, FcntlArg},
+    unistd::{lseek, Whence},
+};
+
+use crate::dmabuf::Dmabuf;
+
+/// The maximum number of planes of a dmabuf.
+pub const MAX_PLANES: usize = 4;
+
+/// Constraints on the layout of a dmabuf.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DmabufConstraints {
+    /// The number of memory planes of the modifier.
+    ///
+    /// If [`None`], the number of planes is only checked against the fourcc code.
+    pub plane_count: Option<usize>,
+}
+
+/// The offset and stride of a single plane of a dmabuf.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlaneLayout {
+    /// Offset of the plane from the start of the dmabuf in bytes.
+    pub offset: u32,
+
+    /// Number of bytes between the start of consecutive rows of the plane.
+    pub stride: u32,
+}
+
+/// Error returned when a dmabuf is invalid.
+#[derive(Debug, thiserror::Error)]
+pub enum ValidationError {
+    /// The width or height of the image is zero.
+    #[error("the image has no pixels")]
+    ZeroSize,
+
+    /// The number of planes does not match the number of planes required by the format.
+    #[error("expected {expected} planes, but the dmabuf has {actual} planes")]
+    InvalidPlaneCount { expected: usize, actual: usize },
+
+    /// The stride of a linear plane is not aligned to the pixels of the plane.
+    #[error("the stride {stride} of plane {plane} is not aligned to {alignment} bytes")]
+    MisalignedStride {
+        plane: usize,
+        stride: u32,
+        alignment: u32,
+    },
+
+    /// The offset of a linear plane is not aligned to the pixels of the plane.
+    #[error("the offset {offset} of plane {plane} is not aligned to {alignment} bytes")]
+    MisalignedOffset {
+        plane: usize,
+        offset: u32,
+        alignment: u32,
+    },
+
+    /// The stride of a linear plane is smaller than a row of the plane.
+    #[error("the stride {stride} of plane {plane} is smaller than a row of {min} bytes")]
+    StrideTooSmall { plane: usize, stride: u32, min: u64 },
+
+    /// The size of a plane overflows.
+    #[error("the size of plane {0} overflows")]
+    Overflow(usize),
+
+    /// A plane extends past the end of its dmabuf.
+    #[error("plane {plane} ends at {end} bytes, but the dmabuf is only {size} bytes")]
+    OutOfBounds { plane: usize, end: u64, size: u64 },
+
+    /// The file descriptor of a plane is closed.
+    #[error("the file descriptor of plane {0} is closed")]
+    ClosedFd(usize),
+
+    /// Two planes use the same file descriptor.
+    ///
+    /// Each plane owns its file descriptor, so the file descriptor would be closed twice. Planes stored in
+    /// the same dmabuf must use duplicated file descriptors.
+    #[error("planes {0} and {1} use the same file descriptor")]
+    DuplicateFd(usize, usize),
+
+    /// The size of a dmabuf could not be queried.
+    #[error("failed to query the size of the dmabuf: {0}")]
+    Io(#[from] io::Error),
+}
+
+/// Checks the planes of a dmabuf fit in the dmabufs they are stored in and satisfy the constraints.
+pub fn validate_dmabuf(
+    dmabuf: &Dmabuf,
+    constraints: &DmabufConstraints,
+) -> Result<(), ValidationError> {
+    let layouts = dmabuf
+        .planes
+        .iter()
+        .map(|plane| PlaneLayout {
+            offset: plane.offset,
+            stride: plane.stride,
+        })
+        .collect::<Vec<_>>();
+
+    let extents = validate_layout(
+        dmabuf.format,
+        dmabuf.width,
+        dmabuf.height,
+        &layouts,
+        constraints,
+    )?;
+
+    let fds = dmabuf
+        .planes
+        .iter()
+        .map(|plane| plane.fd.as_raw_fd())
+        .collect::<Vec<_>>();
+
+    check_fds(&fds)?;
+
+    for (plane, (&fd, end)) in fds.iter().zip(extents).enumerate() {
+        let size = dmabuf_size(fd)?;
+
+        if end > size {
+            return Err(ValidationError::OutOfBounds { plane, end, size });
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks the layout of the planes of a dmabuf without accessing the file descriptors.
+///
+/// Returns the offset of the end of each plane, which must not exceed the size of the dmabuf the plane is
+/// stored in.
+pub fn validate_layout(
+    format: DrmFormat,
+    width: u32,
+    height: u32,
+    planes: &[PlaneLayout],
+    constraints: &DmabufConstraints,
+) -> Result<Vec<u64>, ValidationError> {
+    if width == 0 || height == 0 {
+        return Err(ValidationError::ZeroSize);
+    }
+
+    let plane_formats = plane_formats(format.code);
+    check_plane_count(format, plane_formats, planes.len(), constraints)?;
+
+    let linear = format.modifier == DrmModifier::Linear;
+
+    planes
+        .iter()
+        .enumerate()
+        .map(|(plane, layout)| {
+            // Auxiliary planes of a modifier, such as compression metadata, have a layout specific to the
+            // modifier, so only the offset is known to be inside the dmabuf.
+            let plane_format = match plane_formats.and_then(|formats| formats.get(plane)) {
+                Some(plane_format) => plane_format,
+                None => return Ok(u64::from(layout.offset)),
+            };
+
+            let plane_width = div_ceil(width, plane_format.hsub);
+            let plane_height = div_ceil(height, plane_format.vsub);
+
+            // The stride of tiled modifiers may be measured in tiles rather than rows, so only linear strides
+            // are checked against the width.
+            if linear {
+                check_alignment(plane, layout, plane_format)?;
+
+                let min = plane_width
+                    .checked_mul(plane_format.cpp)
+                    .ok_or(ValidationError::Overflow(plane))?;
+
+                if u64::from(layout.stride) < min {
+                    return Err(ValidationError::StrideTooSmall {
+                        plane,
+                        stride: layout.stride,
+                        min,
+                    });
+                }
+            }
+
+            u64::from(layout.stride)
+                .checked_mul(plane_height)
+                .and_then(|size| size.checked_add(u64::from(layout.offset)))
+                // The size must also be representable as an offset in the dmabuf.
+                .filter(|&end| end <= i64::MAX as u64)
+                .ok_or(ValidationError::Overflow(plane))
+        })
+        .collect()
+}
+
+/// Checks the stride and offset of a linear plane do not split a pixel.
+///
+/// Pixels are aligned to the largest power of two dividing their size, so the planes of formats with 3 byte
+/// pixels only need to be aligned to a byte.
+fn check_alignment(
+    plane: usize,
+    layout: &PlaneLayout,
+    plane_format: &PlaneFormat,
+) -> Result<(), ValidationError> {
+    let alignment = 1u32 << plane_format.cpp.trailing_zeros();
+
+    if layout.stride % alignment != 0 {
+        return Err(ValidationError::MisalignedStride {
+            plane,
+            stride: layout.stride,
+            alignment,
+        });
+    }
+
+    if layout.offset % alignment != 0 {
+        return Err(ValidationError::MisalignedOffset {
+            plane,
+            offset: layout.offset,
+            alignment,
+        });
+    }
+
+    Ok(())
+}
+
+fn check_plane_count(
+    format: DrmFormat,
+    plane_formats: Option<&[PlaneFormat]>,
+    actual: usize,
+    constraints: &DmabufConstraints,
+) -> Result<(), ValidationError> {
+    let fourcc_planes = plane_formats.map(<[PlaneFormat]>::len);
+    let invalid = |expected| Err(ValidationError::InvalidPlaneCount { expected, actual });
+
+    // Linear images have no auxiliary planes.
+    let expected = constraints
+        .plane_count
+        .or_else(|| fourcc_planes.filter(|_| format.modifier == DrmModifier::Linear));
+
+    if let Some(expected) = expected {
+        if actual != expected {
+            return invalid(expected);
+        }
+    }
+
+    // Modifiers may add auxiliary planes, but never remove planes of the fourcc code.
+    let min = fourcc_planes.unwrap_or(1);
+
+    if actual < min {
+        return invalid(min);
+    }
+
+    if actual > MAX_PLANES {
+        return invalid(MAX_PLANES);
+    }
+
+    Ok(())
+}
+
+pub(crate) fn div_ceil(value: u32, divisor: u32) -> u64 {
+    (u64::from(value) + u64::from(divisor) - 1) / u64::from(divisor)
+}
+
+/// Checks the file descriptors of the planes are open and not shared between planes.
+fn check_fds(fds: &[RawFd]) -> Result<(), ValidationError> {
+    for (plane, &fd) in fds.iter().enumerate() {
+        match fcntl(fd, FcntlArg::F_GETFD) {
+            Ok(_) => (),
+            Err(Errno::EBADF) => return Err(ValidationError::ClosedFd(plane)),
+            Err(err) => return Err(io::Error::from(err).into()),
+        }
+
+        if let Some(other) = fds[..plane].iter().position(|&other| other == fd) {
+            return Err(ValidationError::DuplicateFd(other, plane));
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the size of a dmabuf in bytes.
+///
+/// The file offset is shared with every duplicate of the file descriptor, so it is restored afterwards.
+fn dmabuf_size(fd: RawFd) -> Result<u64, ValidationError> {
+    let offset = lseek(fd, 0, Whence::SeekCur).map_err(io::Error::from)?;
+    // Dmabufs report their size when seeking to the end.
+    let size = lseek(fd, 0, Whence::SeekEnd).map_err(io::Error::from)?;
+    lseek(fd, offset, Whence::SeekSet).map_err(io::Error::from)?;
+
+    Ok(size as u64)
+}
+
+/// The memory layout of a single plane of a fourcc code.
+#[derive(Debug)]
+pub(crate) struct PlaneFormat {
+    /// Bytes per pixel, or per pair of horizontally adjacent pixels for packed 4:2:2 formats.
+    pub cpp: u64,
+    /// Horizontal subsampling.
+    pub hsub: u32,
+    /// Vertical subsampling.
+    pub vsub: u32,
+}
+
+const fn plane(cpp: u64, hsub: u32, vsub: u32) -> PlaneFormat {
+    PlaneFormat { cpp, hsub, vsub }
+}
+
+/// Returns the layout of the planes of a fourcc code, or [`None`] if the layout of the fourcc code is not
+/// known.
+pub(crate) fn plane_formats(fourcc: DrmFourcc) -> Option<&'static [PlaneFormat]> {
+    const R8: &[PlaneFormat] = &[plane(1, 1, 1)];
+    const R16: &[PlaneFormat] = &[plane(2, 1, 1)];
+    const RGB: &[PlaneFormat] = &[plane(3, 1, 1)];
+    const RGBA: &[PlaneFormat] = &[plane(4, 1, 1)];
+    const RGBA16: &[PlaneFormat] = &[plane(8, 1, 1)];
+    const PACKED_422: &[PlaneFormat] = &[plane(4, 2, 1)];
+    const NV12: &[PlaneFormat] = &[plane(1, 1, 1), plane(2, 2, 2)];
+    const NV16: &[PlaneFormat] = &[plane(1, 1, 1), plane(2, 2, 1)];
+    const NV24: &[PlaneFormat] = &[plane(1, 1, 1), plane(2, 1, 1)];
+    const P010: &[PlaneFormat] = &[plane(2, 1, 1), plane(4, 2, 2)];
+    const YUV420: &[PlaneFormat] = &[plane(1, 1, 1), plane(1, 2, 2), plane(1, 2, 2)];
+    const YUV422: &[PlaneFormat] = &[plane(1, 1, 1), plane(1, 2, 1), plane(1, 2, 1)];
+    const YUV444: &[PlaneFormat] = &[plane(1, 1, 1), plane(1, 1, 1), plane(1, 1, 1)];
+
+    let formats = match fourcc {
+        DrmFourcc::R8 | DrmFourcc::C8 => R8,
+
+        DrmFourcc::R16
+        | DrmFourcc::Gr88
+        | DrmFourcc::Rg88
+        | DrmFourcc::Rgb565
+        | DrmFourcc::Bgr565
+        | DrmFourcc::Argb4444
+        | DrmFourcc::Xrgb4444
+        | DrmFourcc::Abgr4444
+        | DrmFourcc::Xbgr4444
+        | DrmFourcc::Argb1555
+        | DrmFourcc::Xrgb1555 => R16,
+
+        DrmFourcc::Rgb888 | DrmFourcc::Bgr888 => RGB,
+
+        DrmFourcc::Argb8888
+        | DrmFourcc::Xrgb8888
+        | DrmFourcc::Abgr8888
+        | DrmFourcc::Xbgr8888
+        | DrmFourcc::Rgba8888
+        | DrmFourcc::Rgbx8888
+        | DrmFourcc::Bgra8888
+        | DrmFourcc::Bgrx8888
+        | DrmFourcc::Argb2101010
+        | DrmFourcc::Xrgb2101010
+        | DrmFourcc::Abgr2101010
+        | DrmFourcc::Xbgr2101010
+        | DrmFourcc::Gr1616
+        | DrmFourcc::Rg1616 => RGBA,
+
+        DrmFourcc::Abgr16161616f
+        | DrmFourcc::Xbgr16161616f
+        | DrmFourcc::Argb16161616f
+        | DrmFourcc::Xrgb16161616f => RGBA16,
+
+        DrmFourcc::Yuyv | DrmFourcc::Yvyu | DrmFourcc::Uyvy | DrmFourcc::Vyuy => PACKED_422,
+
+        DrmFourcc::Nv12 | DrmFourcc::Nv21 => NV12,
+        DrmFourcc::Nv16 | DrmFourcc::Nv61 => NV16,
+        DrmFourcc::Nv24 | DrmFourcc::Nv42 => NV24,
+        DrmFourcc::P010 | DrmFourcc::P012 | DrmFourcc::P016 => P010,
+        DrmFourcc::Yuv420 | DrmFourcc::Yvu420 => YUV420,
+        DrmFourcc::Yuv422 | DrmFourcc::Yvu422 => YUV422,
+        DrmFourcc::Yuv444 | DrmFourcc::Yvu444 => YUV444,
+
+        _ => return None,
+    };
+
+    Some(formats)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        ffi::CStr,
+        fs::File,
+        io::{Seek, SeekFrom},
+        os::unix::io::{FromRawFd, OwnedFd},
+    };
+
+    use nix::sys::memfd::{memfd_create, MemFdCreateFlag};
+
+    use super::*;
+    use crate::dmabuf::DmabufPlane;
+
+    const XRGB_LINEAR: DrmFormat = DrmFormat {
+        code: DrmFourcc::Xrgb8888,
+        modifier: DrmModifier::Linear,
+    };
+    const XRGB_TILED: DrmFormat = DrmFormat {
+        code: DrmFourcc::Xrgb8888,
+        modifier: DrmModifier::I915_y_tiled_ccs,
+    };
+    const NV12_LINEAR: DrmFormat = DrmFormat {
+        code: DrmFourcc::Nv12,
+        modifier: DrmModifier::Linear,
+    };
+
+    fn layout(offset: u32, stride: u32) -> PlaneLayout {
+        PlaneLayout { offset, stride }
+    }
+
+    fn memfd(size: u64) -> File {
+        let name = CStr::from_bytes_with_nul(b"wgpu-dmabuf-test\0").unwrap();
+        let fd = memfd_create(name, MemFdCreateFlag::MFD_CLOEXEC).unwrap();
+        // SAFETY: memfd_create returns a new file descriptor which we now own.
+        let file = unsafe { File::from_raw_fd(fd) };
+        file.set_len(size).unwrap();
+        file
+    }
+
+    #[test]
+    fn layout_extents() {
+        let extents = validate_layout(
+            NV12_LINEAR,
+            64,
+            31,
+            &[layout(0, 64), layout(4096, 64)],
+            &DmabufConstraints::default(),
+        )
+        .unwrap();
+
+        // The chroma plane has half as many rows, rounded up.
+        assert_eq!(extents, [64 * 31, 4096 + 64 * 16]);
+    }
+
+    #[test]
+    fn layout_zero_size() {
+        let result = validate_layout(
+            XRGB_LINEAR,
+            0,
+            16,
+            &[layout(0, 64)],
+            &DmabufConstraints::default(),
+        );
+
+        assert!(matches!(result, Err(ValidationError::ZeroSize)));
+    }
+
+    #[test]
+    fn layout_plane_count() {
+        let constraints = DmabufConstraints::default();
+
+        // Linear images must have exactly the planes of the fourcc code.
+        let result = validate_layout(NV12_LINEAR, 16, 16, &[layout(0, 16)], &constraints);
+        assert!(matches!(
+            result,
+            Err(ValidationError::InvalidPlaneCount {
+                expected: 2,
+                actual: 1
+            })
+        ));
+
+        let result = validate_layout(
+            XRGB_LINEAR,
+            16,
+            16,
+            &[layout(0, 64), layout(1024, 64)],
+            &constraints,
+        );
+        assert!(matches!(
+            result,
+            Err(ValidationError::InvalidPlaneCount {
+                expected: 1,
+                actual: 2
+            })
+        ));
+
+        // Modifiers may add auxiliary planes, whose extent is only known to start at the offset.
+        let extents = validate_layout(
+            XRGB_TILED,
+            16,
+            16,
+            &[layout(0, 64), layout(4096, 128)],
+            &constraints,
+        )
+        .unwrap();
+        assert_eq!(extents, [1024, 4096]);
+
+        let result = validate_layout(XRGB_TILED, 16, 16, &[layout(0, 64); 5], &constraints);
+        assert!(matches!(
+            result,
+            Err(ValidationError::InvalidPlaneCount {
+                expected: MAX_PLANES,
+                actual: 5
+            })
+        ));
+
+        // The plane count of the modifier takes precedence over the fourcc code.
+        let constraints = DmabufConstraints {
+            plane_count: Some(2),
+        };
+        let result = validate_layout(XRGB_TILED, 16, 16, &[layout(0, 64)], &constraints);
+        assert!(matches!(
+            result,
+            Err(ValidationError::InvalidPlaneCount {
+                expected: 2,
+                actual: 1
+            })
+        ));
+    }
+
+    #[test]
+    fn layout_stride_too_small() {
+        let result = validate_layout(
+            XRGB_LINEAR,
+            100,
+            16,
+            &[layout(0, 396)],
+            &DmabufConstraints::default(),
+        );
+        assert!(matches!(
+            result,
+            Err(ValidationError::StrideTooSmall {
+                plane: 0,
+                stride: 396,
+                min: 400
+            })
+        ));
+
+        // The chroma plane of NV12 has two bytes per sample at half the width, rounded up.
+        let result = validate_layout(
+            NV12_LINEAR,
+            33,
+            16,
+            &[layout(0, 33), layout(1024, 32)],
+            &DmabufConstraints::default(),
+        );
+        assert!(matches!(
+            result,
+            Err(ValidationError::StrideTooSmall {
+                plane: 1,
+                stride: 32,
+                min: 34
+            })
+        ));
+
+        // Tiled strides are not measured in pixels.
+        validate_layout(
+            XRGB_TILED,
+            100,
+            16,
+            &[layout(0, 128)],
+            &DmabufConstraints::default(),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn layout_overflow() {
+        let result = validate_layout(
+            XRGB_LINEAR,
+            16,
+            u32::MAX,
+            &[layout(u32::MAX - 3, u32::MAX - 3)],
+            &DmabufConstraints::default(),
+        );
+
+        assert!(matches!(result, Err(ValidationError::Overflow(0))));
+    }
+
+    #[test]
+    fn fds_closed() {
+        let file = memfd(0);
+        let result = check_fds(&[file.as_raw_fd(), -1]);
+
+        assert!(matches!(result, Err(ValidationError::ClosedFd(1))));
+    }
+
+    #[test]
+    fn fds_duplicate() {
+        let a = memfd(0);
+        let b = memfd(0);
+        let result = check_fds(&[a.as_raw_fd(), b.as_raw_fd(), a.as_raw_fd()]);
+
+        assert!(matches!(result, Err(ValidationError::DuplicateFd(0, 2))));
+
+        // Duplicated file descriptors of the same dmabuf are allowed.
+        let dup = a.try_clone().unwrap();
+        check_fds(&[a.as_raw_fd(), dup.as_raw_fd()]).unwrap();
+    }
+
+    #[test]
+    fn size_restores_offset() {
+        let mut file = memfd(8192);
+        file.seek(SeekFrom::Start(100)).unwrap();
+
+        assert_eq!(dmabuf_size(file.as_raw_fd()).unwrap(), 8192);
+        assert_eq!(file.stream_position().unwrap(), 100);
+    }
+
+    #[test]
+    fn layout_misaligned() {
+        // NV12 chroma samples are two bytes.
+        let result = validate_layout(
+            NV12_LINEAR,
+            64,
+            32,
+            &[layout(0, 65), layout(2080, 65)],
+            &DmabufConstraints::default(),
+        );
+        assert!(matches!(
+            result,
+            Err(ValidationError::MisalignedStride {
+                plane: 1,
+                stride: 65,
+                alignment: 2
+            })
+        ));
+
+        let result = validate_layout(
+            XRGB_LINEAR,
+            100,
+            100,
+            &[layout(2, 400)],
+            &DmabufConstraints::default(),
+        );
+        assert!(matches!(
+            result,
+            Err(ValidationError::MisalignedOffset {
+                plane: 0,
+                offset: 2,
+                alignment: 4
+            })
+        ));
+
+        // Tiled planes are not checked.
+        validate_layout(
+            XRGB_TILED,
+            16,
+            16,
+            &[layout(2, 66)],
+            &DmabufConstraints::default(),
+        )
+        .unwrap();
+
+        // 3 byte pixels only need to be aligned to a byte.
+        let rgb = DrmFormat {
+            code: DrmFourcc::Rgb888,
+            modifier: DrmModifier::Linear,
+        };
+        validate_layout(rgb, 16, 16, &[layout(1, 49)], &DmabufConstraints::default()).unwrap();
+    }
+
+    #[test]
+    fn dmabuf_misaligned() {
+        let dmabuf = |offsets: [u32; 2], strides: [u32; 2]| Dmabuf {
+            format: NV12_LINEAR,
+            width: 64,
+            height: 32,
+            planes: offsets
+                .iter()
+                .zip(strides)
+                .map(|(&offset, stride)| DmabufPlane {
+                    fd: OwnedFd::from(memfd(8192)),
+                    offset,
+                    stride,
+                })
+                .collect(),
+        };
+
+        validate_dmabuf(&dmabuf([0, 4096], [64, 64]), &DmabufConstraints::default()).unwrap();
+
+        let result = validate_dmabuf(&dmabuf([0, 4096], [64, 65]), &DmabufConstraints::default());
+        assert!(matches!(
+            result,
+            Err(ValidationError::MisalignedStride {
+                plane: 1,
+                stride: 65,
+                alignment: 2
+            })
+        ));
+
+        let result = validate_dmabuf(&dmabuf([0, 4097], [64, 64]), &DmabufConstraints::default());
+        assert!(matches!(
+            result,
+            Err(ValidationError::MisalignedOffset {
+                plane: 1,
+                offset: 4097,
+                alignment: 2
+            })
+        ));
+
+        // XRGB pixels are four bytes.
+        let xrgb = Dmabuf {
+            format: XRGB_LINEAR,
+            width: 16,
+            height: 16,
+            planes: vec![DmabufPlane {
+                fd: OwnedFd::from(memfd(4096)),
+                offset: 0,
+                stride: 66,
+            }],
+        };
+        let result = validate_dmabuf(&xrgb, &DmabufConstraints::default());
+        assert!(matches!(
+            result,
+            Err(ValidationError::MisalignedStride {
+                plane: 0,
+                stride: 66,
+                alignment: 4
+            })
+        ));
+    }
+
+    #[test]
+    fn dmabuf_out_of_bounds() {
+        let dmabuf = |height| Dmabuf {
+            format: XRGB_LINEAR,
+            width: 16,
+            height,
+            planes: vec![DmabufPlane {
+                fd: OwnedFd::from(memfd(1024)),
+                offset: 0,
+                stride: 64,
+            }],
+        };
+
+        validate_dmabuf(&dmabuf(16), &DmabufConstraints::default()).unwrap();
+
+        let result = validate_dmabuf(&dmabuf(17), &DmabufConstraints::default());
+        assert!(matches!(
+            result,
+            Err(ValidationError::OutOfBounds {
+                plane: 0,
+                end: 1088,
+                size: 1024
+            })
+        ));
+    }
+}
@@ -0,0 +1,209 @@
+//! Importing multi-planar and subsampled YCbCr dmabufs.
+//!
+//! Dmabufs produced by video decoders and cameras usually store images in a YCbCr format such as NV12. These
+//! images are sampled as a single texture using a sampler YCbCr conversion, which converts the image to RGB
+//! when sampled.
+//!
+//! wgpu has no texture formats for multi-planar images, so a [`YcbcrTexture`] exposes the raw Vulkan objects to
+//! sample the image with. Only the Vulkan backend supports YCbCr conversion.
+//!
+//! The texture can not be used from wgpu: the image must be sampled through an immutable sampler of the
+//! descriptor set layout, but wgpu bind group layouts have no immutable samplers and neither wgpu nor
+//! wgpu-hal can wrap a raw descriptor set layout or descriptor set. The texture must be sampled using raw
+//! Vulkan, for example by recording commands with the device returned by [`wgpu::Device::as_hal`].
+//!
+//! Like other imported dmabufs, the image is owned by the other side until it is acquired using
+//! [`ExternalMemoryDevice::acquire_ycbcr_texture`](crate::ExternalMemoryDevice::acquire_ycbcr_texture), and
+//! must be released using
+//! [`ExternalMemoryDevice::release_ycbcr_texture`](crate::ExternalMemoryDevice::release_ycbcr_texture) before
+//! the other side uses it again.
+
+use ash::vk;
+use drm_fourcc::DrmFormat;
+use wgpu::{FilterMode, ShaderStages};
+use wgpu_hal::DeviceError;
+
+use crate::imp::vulkan::{conv, Inner, PromotedDeviceFns};
+
+/// The color model used to convert YCbCr values to RGB.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum YcbcrModel {
+    /// The values are already RGB and are passed through unmodified.
+    RgbIdentity,
+
+    /// The values are range expanded but not converted.
+    YcbcrIdentity,
+
+    /// The color model defined in ITU-R BT.601.
+    Bt601,
+
+    /// The color model defined in ITU-R BT.709.
+    Bt709,
+
+    /// The color model defined in ITU-R BT.2020.
+    Bt2020,
+}
+
+/// The range of encoded YCbCr values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum YcbcrRange {
+    /// The full range of values is used.
+    Full,
+
+    /// Headroom and footroom are reserved, for example luma values are in the range 16 to 235 for 8-bit
+    /// images.
+    Narrow,
+}
+
+/// The location of downsampled chroma samples relative to the luma samples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChromaLocation {
+    /// Chroma samples are located at the even luma samples.
+    CositedEven,
+
+    /// Chroma samples are located in the middle of the luma samples.
+    Midpoint,
+}
+
+/// Describes how a YCbCr dmabuf is sampled.
+#[derive(Debug, Clone)]
+pub struct YcbcrImportDescriptor<'a> {
+    /// Debug label of the imported texture.
+    pub label: wgpu::Label<'a>,
+
+    /// The color model of the image.
+    pub model: YcbcrModel,
+
+    /// The range of the encoded values of the image.
+    pub range: YcbcrRange,
+
+    /// The horizontal location of the chroma samples.
+    pub x_chroma_offset: ChromaLocation,
+
+    /// The vertical location of the chroma samples.
+    pub y_chroma_offset: ChromaLocation,
+
+    /// The filter used to reconstruct the chroma samples.
+    pub chroma_filter: FilterMode,
+}
+
+/// An imported YCbCr dmabuf, which is sampled using a sampler YCbCr conversion.
+///
+/// The image is sampled using a combined image sampler, whose sampler must be an immutable sampler of the
+/// descriptor set layout. Use [`YcbcrTexture::create_descriptor_set_layout`] to create such a layout.
+///
+/// The image is owned by the other side until it is acquired, which transitions it to the layout it is
+/// sampled in.
+///
+/// The Vulkan objects are destroyed when the texture is dropped, so the texture may not outlive the device it
+/// was imported with.
+pub struct YcbcrTexture<'a> {
+    pub(crate) device: ash::Device,
+    pub(crate) promoted_fns: PromotedDeviceFns,
+    pub(crate) format: DrmFormat,
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    pub(crate) image: vk::Image,
+    pub(crate) memory: Vec<vk::DeviceMemory>,
+    pub(crate) conversion: vk::SamplerYcbcrConversion,
+    pub(crate) sampler: vk::Sampler,
+    pub(crate) view: vk::ImageView,
+    /// The image stops being tracked by the device when the texture is dropped.
+    pub(crate) inner: &'a Inner,
+}
+
+impl YcbcrTexture<'_> {
+    /// The fourcc code and modifier of the imported dmabuf.
+    pub fn format(&self) -> DrmFormat {
+        self.format
+    }
+
+    /// The width and height of the image.
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// The imported image.
+    pub fn raw_image(&self) -> vk::Image {
+        self.image
+    }
+
+    /// An image view of the whole image which uses the YCbCr conversion.
+    pub fn raw_image_view(&self) -> vk::ImageView {
+        self.view
+    }
+
+    /// A sampler which uses the YCbCr conversion.
+    pub fn raw_sampler(&self) -> vk::Sampler {
+        self.sampler
+    }
+
+    /// The YCbCr conversion used by the image view and sampler.
+    pub fn raw_conversion(&self) -> vk::SamplerYcbcrConversion {
+        self.conversion
+    }
+
+    /// Creates a descriptor set layout with a combined image sampler at the specified binding whose immutable
+    /// sampler is the sampler of this texture.
+    ///
+    /// The caller owns the descriptor set layout and must destroy it using `vkDestroyDescriptorSetLayout`.
+    ///
+    /// # Safety
+    ///
+    /// The descriptor set layout, and any pipeline layout, pipeline or descriptor set created from it, must
+    /// not be used after the texture is dropped, since the texture destroys the immutable sampler. The
+    /// descriptor set layout must be destroyed before the device.
+    pub unsafe fn create_descriptor_set_layout(
+        &self,
+        binding: u32,
+        stages: ShaderStages,
+    ) -> Result<vk::DescriptorSetLayout, DeviceError> {
+        let immutable_samplers = [self.sampler];
+        let bindings = [vk::DescriptorSetLayoutBinding::builder()
+            .binding(binding)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(1)
+            .stage_flags(conv::map_shader_stages(stages))
+            .immutable_samplers(&immutable_samplers)
+            .build()];
+        let create_info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+
+        Ok(self
+            .device
+            .create_descriptor_set_layout(&create_info, None)?)
+    }
+}
+
+impl Drop for YcbcrTexture<'_> {
+    fn drop(&mut self) {
+        // The handle may be reused by the driver once the image is destroyed.
+        self.inner
+            .external_textures
+            .lock()
+            .unwrap()
+            .remove(&self.image);
+
+        unsafe {
+            self.device.destroy_image_view(self.view, None);
+            self.device.destroy_sampler(self.sampler, None);
+            self.promoted_fns
+                .destroy_sampler_ycbcr_conversion(self.conversion);
+            self.device.destroy_image(self.image, None);
+
+            for &memory in &self.memory {
+                self.device.free_memory(memory, None);
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for YcbcrTexture<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("YcbcrTexture")
+            .field("format", &self.format)
+            .field("width", &self.width)
+            .field("height", &self.height)
+            .field("image", &self.image)
+            .finish()
+    }
+}